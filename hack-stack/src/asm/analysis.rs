@@ -0,0 +1,522 @@
+//! Control-flow analysis of a program, for seeing where its code and cycles go. The program is
+//! split into basic blocks, which start at a label or after a jump and end at a jump or before a
+//! label, and the blocks are linked by the jumps between them. Every instruction takes one cycle.
+//!
+//! A jump's target is only known if A was loaded with a label, or an address with a label, earlier
+//! in the same block. Other jumps, like a VM function's return, go to an address computed at run
+//! time, so loops that go through them aren't found.
+//!
+//! The code from a label up to the next label is only entered at the top, so the most cycles it
+//! can take to get from one label to the next is found without going round any loops. The report
+//! lists these alongside each label's size, followed by the blocks and loops:
+//!
+//! ```text
+//! Labels:
+//!   ADDRESS  WORDS  BLOCKS  MAX CYCLES  LABEL
+//!         0      2       1           2  <start>
+//!         2      3       1           3  LOOP
+//!         5      2       1           2  END
+//!
+//! Paths:
+//!   CYCLES  FROM -> TO
+//!        2  <start> -> LOOP
+//!        3  LOOP -> LOOP
+//!        3  LOOP -> END
+//!        2  END -> END
+//! ```
+
+use std::{collections::HashMap, convert::TryFrom, fmt, ops::Range};
+
+use super::ast::{Address, Instruction, Jump};
+
+/// A program's blocks, the sections of code between its labels, and its loops.
+#[derive(Debug)]
+pub struct Analysis<'a> {
+    pub blocks: Vec<Block<'a>>,
+    pub sections: Vec<Section<'a>>,
+    /// The blocks in each loop, in address order.
+    pub loops: Vec<Vec<usize>>,
+}
+
+/// A run of instructions that's only entered at the top and only left at the bottom.
+#[derive(Debug, PartialEq)]
+pub struct Block<'a> {
+    pub start: u16,
+    pub len: u16,
+    /// The labels pointing to the start of the block.
+    pub labels: Vec<&'a str>,
+    /// The blocks control can go to next, as indexes into `Analysis::blocks`.
+    pub successors: Vec<usize>,
+    /// Whether control can also go somewhere unknown, either by a jump whose target isn't known
+    /// or by running off the end of the program.
+    pub unknown_exit: bool,
+}
+
+/// The code from a label up to the next one, or from the start of the program up to the first
+/// label.
+#[derive(Debug, PartialEq)]
+pub struct Section<'a> {
+    /// The labels pointing to the start of the section, which is empty for the code at the start
+    /// of the program if there's no label there.
+    pub labels: Vec<&'a str>,
+    pub start: u16,
+    pub len: u16,
+    /// The section's blocks, as indexes into `Analysis::blocks`.
+    pub blocks: Range<usize>,
+    pub exits: Vec<Exit>,
+}
+
+/// A way out of a section, and the most cycles it takes from the start of the section to leave
+/// that way.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Exit {
+    /// The section control goes to, as an index into `Analysis::sections`, or `None` if it's
+    /// unknown.
+    pub target: Option<usize>,
+    pub cycles: u16,
+}
+
+impl<'a> Section<'a> {
+    /// Returns the most cycles control can spend in the section, each time it's entered.
+    pub fn max_cycles(&self) -> u16 {
+        self.exits.iter().map(|exit| exit.cycles).max().unwrap_or(0)
+    }
+
+    fn name(&self) -> String {
+        match self.labels.first() {
+            Some(label) => (*label).to_owned(),
+            None => "<start>".to_owned(),
+        }
+    }
+}
+
+// How a block ends
+enum End {
+    FallThrough,
+    Jump {
+        target: Option<u16>,
+        conditional: bool,
+    },
+}
+
+/// Analyzes a program, which should have been checked for errors.
+pub fn analyze<'a>(instructions: &'a [Instruction]) -> Analysis<'a> {
+    let mut label_addrs: HashMap<&str, u16> = HashMap::new();
+    let mut addr = 0u16;
+    for inst in instructions {
+        match inst {
+            Instruction::Label(label) => {
+                label_addrs.insert(&label.name, addr);
+            }
+            _ => addr += 1,
+        }
+    }
+
+    // Split the program into blocks, noting how each one ends
+    let mut blocks = vec![];
+    let mut ends = vec![];
+    // The labels before the next block
+    let mut labels = vec![];
+    let mut current: Option<Block> = None;
+    // The address in A, if it was loaded earlier in the block
+    let mut loaded: Option<&Address> = None;
+    let mut addr = 0u16;
+    for inst in instructions {
+        if let Instruction::Label(label) = inst {
+            if let Some(block) = current.take() {
+                blocks.push(block);
+                ends.push(End::FallThrough);
+            }
+            labels.push(&*label.name);
+            loaded = None;
+            continue;
+        }
+        let block = current.get_or_insert_with(|| Block {
+            start: addr,
+            len: 0,
+            labels: labels.split_off(0),
+            successors: vec![],
+            unknown_exit: false,
+        });
+        block.len += 1;
+        addr += 1;
+
+        let c = match inst {
+            Instruction::A(a) => {
+                loaded = Some(&a.addr);
+                continue;
+            }
+            Instruction::C(c) => c,
+            Instruction::Label(_) => unreachable!(),
+        };
+        if let Some(jump) = &c.jump {
+            blocks.push(current.take().unwrap());
+            ends.push(End::Jump {
+                target: loaded.and_then(|addr| resolve(addr, &label_addrs)),
+                conditional: *jump != Jump::JMP,
+            });
+        }
+        if c.dest.as_ref().is_some_and(|dest| dest.a) || c.jump == Some(Jump::JMP) {
+            loaded = None;
+        }
+    }
+    if let Some(block) = current {
+        blocks.push(block);
+        ends.push(End::FallThrough);
+    }
+
+    let block_at = blocks
+        .iter()
+        .enumerate()
+        .map(|(i, block)| (block.start, i))
+        .collect::<HashMap<u16, usize>>();
+    for (i, end) in ends.iter().enumerate() {
+        let (target, falls_through) = match *end {
+            End::FallThrough => (None, true),
+            End::Jump {
+                target,
+                conditional,
+            } => {
+                let target = target.and_then(|addr| block_at.get(&addr).copied());
+                if target.is_none() {
+                    blocks[i].unknown_exit = true;
+                }
+                (target, conditional)
+            }
+        };
+        if let Some(target) = target {
+            blocks[i].successors.push(target);
+        }
+        if falls_through {
+            if i + 1 < blocks.len() {
+                if !blocks[i].successors.contains(&(i + 1)) {
+                    blocks[i].successors.push(i + 1);
+                }
+            } else {
+                blocks[i].unknown_exit = true;
+            }
+        }
+    }
+
+    let sections = sections(&blocks);
+    let loops = strongly_connected(&blocks)
+        .into_iter()
+        .filter(|component| {
+            component.len() > 1 || blocks[component[0]].successors.contains(&component[0])
+        })
+        .collect();
+    Analysis {
+        blocks,
+        sections,
+        loops,
+    }
+}
+
+// Returns the value of an A-instruction if it only depends on labels
+fn resolve(addr: &Address, label_addrs: &HashMap<&str, u16>) -> Option<u16> {
+    match addr {
+        Address::Value(n) => Some(*n),
+        Address::Symbol(s) => label_addrs.get(&**s).copied(),
+        Address::Expression(expr) => {
+            let value = expr
+                .evaluate(&mut |s| {
+                    label_addrs
+                        .get(s)
+                        .map(|&addr| addr as i64)
+                        .ok_or_else(String::new)
+                })
+                .ok()?;
+            u16::try_from(value).ok()
+        }
+    }
+}
+
+fn sections<'a>(blocks: &[Block<'a>]) -> Vec<Section<'a>> {
+    let mut sections: Vec<Section> = vec![];
+    let mut section_of = Vec::with_capacity(blocks.len());
+    for (i, block) in blocks.iter().enumerate() {
+        if i == 0 || !block.labels.is_empty() {
+            sections.push(Section {
+                labels: block.labels.clone(),
+                start: block.start,
+                len: 0,
+                blocks: i..i,
+                exits: vec![],
+            });
+        }
+        let section = sections.last_mut().unwrap();
+        section.len += block.len;
+        section.blocks.end = i + 1;
+        section_of.push(sections.len() - 1);
+    }
+
+    for section in &mut sections {
+        let mut exits: Vec<Exit> = vec![];
+        let mut add_exit = |target, cycles| {
+            match exits.iter_mut().find(|exit| exit.target == target) {
+                Some(exit) => exit.cycles = exit.cycles.max(cycles),
+                None => exits.push(Exit { target, cycles }),
+            };
+        };
+        for i in section.blocks.clone() {
+            let block = &blocks[i];
+            let cycles = block.start + block.len - section.start;
+            for &successor in &block.successors {
+                // Blocks only start in the middle of a section after a conditional jump
+                if successor != i + 1 || !blocks[successor].labels.is_empty() {
+                    add_exit(Some(section_of[successor]), cycles);
+                }
+            }
+            if block.unknown_exit {
+                add_exit(None, cycles);
+            }
+        }
+        section.exits = exits;
+    }
+    sections
+}
+
+// Returns the strongly connected components of the control-flow graph using Tarjan's algorithm,
+// with an explicit stack so long chains of blocks can't overflow the real one
+fn strongly_connected(blocks: &[Block]) -> Vec<Vec<usize>> {
+    const UNVISITED: usize = usize::MAX;
+    let mut index = vec![UNVISITED; blocks.len()];
+    let mut low = vec![0; blocks.len()];
+    let mut on_stack = vec![false; blocks.len()];
+    let mut stack = vec![];
+    let mut next_index = 0;
+    let mut components = vec![];
+
+    for root in 0..blocks.len() {
+        if index[root] != UNVISITED {
+            continue;
+        }
+        // Each block being visited, and the next of its successors to visit
+        let mut work = vec![(root, 0)];
+        while let Some((v, i)) = work.pop() {
+            if i == 0 {
+                index[v] = next_index;
+                low[v] = next_index;
+                next_index += 1;
+                stack.push(v);
+                on_stack[v] = true;
+            }
+            if let Some(&w) = blocks[v].successors.get(i) {
+                work.push((v, i + 1));
+                if index[w] == UNVISITED {
+                    work.push((w, 0));
+                } else if on_stack[w] {
+                    low[v] = low[v].min(index[w]);
+                }
+                continue;
+            }
+
+            if low[v] == index[v] {
+                let mut component = vec![];
+                loop {
+                    let w = stack.pop().unwrap();
+                    on_stack[w] = false;
+                    component.push(w);
+                    if w == v {
+                        break;
+                    }
+                }
+                component.sort_unstable();
+                components.push(component);
+            }
+            if let Some(&(parent, _)) = work.last() {
+                low[parent] = low[parent].min(low[v]);
+            }
+        }
+    }
+    components.sort_unstable_by_key(|component| component[0]);
+    components
+}
+
+impl<'a> Analysis<'a> {
+    // Names a block by its section and its offset into it, e.g. `LOOP+3`
+    fn block_name(&self, i: usize) -> String {
+        let section = &self.sections[self
+            .sections
+            .partition_point(|section| section.blocks.end <= i)];
+        match self.blocks[i].start - section.start {
+            0 => section.name(),
+            offset => format!("{}+{}", section.name(), offset),
+        }
+    }
+}
+
+/// Writes a report with a table of labels and their sizes, the most cycles between each label
+/// and the next, the blocks, and the loops. Names come last, as they can be long.
+impl<'a> fmt::Display for Analysis<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Labels:")?;
+        writeln!(f, "  ADDRESS  WORDS  BLOCKS  MAX CYCLES  LABEL")?;
+        for section in &self.sections {
+            let labels = if section.labels.is_empty() {
+                section.name()
+            } else {
+                section.labels.join(", ")
+            };
+            writeln!(
+                f,
+                "  {:7}  {:5}  {:6}  {:10}  {}",
+                section.start,
+                section.len,
+                section.blocks.len(),
+                section.max_cycles(),
+                labels
+            )?;
+        }
+
+        writeln!(f, "\nPaths:")?;
+        writeln!(f, "  CYCLES  FROM -> TO")?;
+        for section in &self.sections {
+            for exit in &section.exits {
+                let target = match exit.target {
+                    Some(target) => self.sections[target].name(),
+                    None => "?".to_owned(),
+                };
+                writeln!(f, "  {:6}  {} -> {}", exit.cycles, section.name(), target)?;
+            }
+        }
+
+        writeln!(f, "\nBlocks:")?;
+        writeln!(f, "  ADDRESS  WORDS  BLOCK -> SUCCESSORS")?;
+        for (i, block) in self.blocks.iter().enumerate() {
+            let mut successors = block
+                .successors
+                .iter()
+                .map(|&successor| self.block_name(successor))
+                .collect::<Vec<String>>();
+            if block.unknown_exit {
+                successors.push("?".to_owned());
+            }
+            writeln!(
+                f,
+                "  {:7}  {:5}  {} -> {}",
+                block.start,
+                block.len,
+                self.block_name(i),
+                successors.join(", ")
+            )?;
+        }
+
+        writeln!(f, "\nLoops:")?;
+        writeln!(f, "  WORDS  BLOCKS")?;
+        for blocks in &self.loops {
+            let words: usize = blocks.iter().map(|&i| self.blocks[i].len as usize).sum();
+            let names = blocks
+                .iter()
+                .map(|&i| self.block_name(i))
+                .collect::<Vec<String>>();
+            writeln!(f, "  {:5}  {}", words, names.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{Parser, Tokenizer};
+
+    fn parse(src: &str) -> Vec<Instruction<'_>> {
+        Parser::new(Tokenizer::new(src)).parse().unwrap()
+    }
+
+    #[test]
+    fn test_blocks() {
+        let instructions = parse(
+            "@10\nD=A\n(LOOP)\nD=D-1\n@END\nD;JEQ\n@LOOP\n0;JMP\n(END)\n(HALT)\n@R14\nA=M\n0;JMP\n",
+        );
+        let analysis = analyze(&instructions);
+        let starts = analysis
+            .blocks
+            .iter()
+            .map(|block| (block.start, block.len, block.labels.clone()))
+            .collect::<Vec<(u16, u16, Vec<&str>)>>();
+        assert_eq!(
+            starts,
+            vec![
+                (0, 2, vec![]),
+                (2, 3, vec!["LOOP"]),
+                (5, 2, vec![]),
+                (7, 3, vec!["END", "HALT"])
+            ]
+        );
+        let successors = analysis
+            .blocks
+            .iter()
+            .map(|block| (block.successors.clone(), block.unknown_exit))
+            .collect::<Vec<(Vec<usize>, bool)>>();
+        assert_eq!(
+            successors,
+            vec![
+                (vec![1], false),
+                (vec![3, 2], false),
+                (vec![1], false),
+                (vec![], true)
+            ]
+        );
+        assert_eq!(analysis.loops, vec![vec![1, 2]]);
+    }
+
+    #[test]
+    fn test_sections() {
+        let instructions =
+            parse("@10\nD=A\n(LOOP)\nD=D-1\n@END\nD;JEQ\n@LOOP\n0;JMP\n(END)\n@END+0\n0;JMP\n");
+        let analysis = analyze(&instructions);
+        let exits = analysis
+            .sections
+            .iter()
+            .map(|section| (section.len, section.exits.clone()))
+            .collect::<Vec<(u16, Vec<Exit>)>>();
+        let exit = |target, cycles| Exit {
+            target: Some(target),
+            cycles,
+        };
+        assert_eq!(
+            exits,
+            vec![
+                (2, vec![exit(1, 2)]),
+                (5, vec![exit(2, 3), exit(1, 5)]),
+                (2, vec![exit(2, 2)])
+            ]
+        );
+        assert_eq!(analysis.sections[1].max_cycles(), 5);
+        assert_eq!(analysis.loops, vec![vec![1, 2], vec![3]]);
+    }
+
+    #[test]
+    fn test_report() {
+        let instructions = parse("@2\nD=A\n(LOOP)\nD=D-1\n@LOOP\nD;JGT\n(END)\n@END\n0;JMP\n");
+        let expected = [
+            "Labels:",
+            "  ADDRESS  WORDS  BLOCKS  MAX CYCLES  LABEL",
+            "        0      2       1           2  <start>",
+            "        2      3       1           3  LOOP",
+            "        5      2       1           2  END",
+            "",
+            "Paths:",
+            "  CYCLES  FROM -> TO",
+            "       2  <start> -> LOOP",
+            "       3  LOOP -> LOOP",
+            "       3  LOOP -> END",
+            "       2  END -> END",
+            "",
+            "Blocks:",
+            "  ADDRESS  WORDS  BLOCK -> SUCCESSORS",
+            "        0      2  <start> -> LOOP",
+            "        2      3  LOOP -> LOOP, END",
+            "        5      2  END -> END",
+            "",
+            "Loops:",
+            "  WORDS  BLOCKS",
+            "      3  LOOP",
+            "      2  END",
+            "",
+        ];
+        assert_eq!(analyze(&instructions).to_string(), expected.join("\n"));
+    }
+}
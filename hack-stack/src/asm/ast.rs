@@ -1,17 +1,71 @@
-use std::convert::TryFrom;
+use std::{borrow::Cow, convert::TryFrom, fmt};
 
 use crate::common::Span;
 
 #[derive(Debug, PartialEq)]
 pub struct Label<'a> {
-    pub name: &'a str,
+    /// The label's name. Local and anonymous labels are given a unique name by the parser, e.g.
+    /// `.loop` after `(main)` is `main.loop`, and the second `(1)` is `1#2`.
+    pub name: Cow<'a, str>,
     pub span: Span,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Address<'a> {
     Value(u16),
-    Symbol(&'a str),
+    Symbol(Cow<'a, str>),
+    /// A constant expression such as `SCREEN+32*10`, evaluated once every label is known.
+    Expression(Expression<'a>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expression<'a> {
+    Value(u16),
+    Symbol(Cow<'a, str>),
+    Negate(Box<Expression<'a>>),
+    Binary(Box<Expression<'a>>, ExpressionOperator, Box<Expression<'a>>),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ExpressionOperator {
+    Plus,
+    Minus,
+    Times,
+}
+
+impl<'a> Expression<'a> {
+    /// Evaluates the expression, calling `symbol_value` for the value of each symbol in it.
+    pub fn evaluate<'e>(
+        &'e self,
+        symbol_value: &mut impl FnMut(&'e str) -> Result<i64, String>,
+    ) -> Result<i64, String> {
+        let value = match self {
+            Expression::Value(n) => Some(*n as i64),
+            Expression::Symbol(s) => Some(symbol_value(s)?),
+            Expression::Negate(expr) => expr.evaluate(symbol_value)?.checked_neg(),
+            Expression::Binary(lhs, op, rhs) => {
+                let (lhs, rhs) = (lhs.evaluate(symbol_value)?, rhs.evaluate(symbol_value)?);
+                match op {
+                    ExpressionOperator::Plus => lhs.checked_add(rhs),
+                    ExpressionOperator::Minus => lhs.checked_sub(rhs),
+                    ExpressionOperator::Times => lhs.checked_mul(rhs),
+                }
+            }
+        };
+        value.ok_or_else(|| "expression overflowed".to_owned())
+    }
+
+    /// Returns a copy of the expression that doesn't borrow from the source.
+    pub fn into_owned(self) -> Expression<'static> {
+        match self {
+            Expression::Value(n) => Expression::Value(n),
+            Expression::Symbol(s) => Expression::Symbol(Cow::Owned(s.into_owned())),
+            Expression::Negate(expr) => Expression::Negate(Box::new(expr.into_owned())),
+            Expression::Binary(lhs, op, rhs) => {
+                Expression::Binary(Box::new(lhs.into_owned()), op, Box::new(rhs.into_owned()))
+            }
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -32,6 +86,15 @@ impl TryFrom<&str> for Bit {
     }
 }
 
+impl fmt::Display for Bit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Bit::Zero => "0",
+            Bit::One => "1",
+        })
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Register {
     D,
@@ -52,12 +115,31 @@ impl TryFrom<&str> for Register {
     }
 }
 
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Register::D => "D",
+            Register::A => "A",
+            Register::M => "M",
+        })
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Operand {
     Bit(Bit),
     Register(Register),
 }
 
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Bit(bit) => bit.fmt(f),
+            Operand::Register(register) => register.fmt(f),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum UnaryOperator {
     Not,
@@ -85,12 +167,62 @@ pub struct BinaryOperation {
     pub rhs: Operand,
 }
 
+#[derive(Debug, PartialEq)]
+pub enum ShiftDirection {
+    Left,
+    Right,
+}
+
+/// A shift by one bit, e.g. `D<<`. Shifts are only in the extended instruction set.
+#[derive(Debug, PartialEq)]
+pub struct Shift {
+    pub register: Register,
+    pub direction: ShiftDirection,
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Comp {
     Bit(Bit),
     Register(Register),
     UnaryOperation(UnaryOperation),
     BinaryOperation(BinaryOperation),
+    Shift(Shift),
+}
+
+/// Writes the computation as it was written in the source, e.g. `A+D` rather than `D+A`.
+impl fmt::Display for Comp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Comp::Bit(bit) => bit.fmt(f),
+            Comp::Register(register) => register.fmt(f),
+            Comp::UnaryOperation(UnaryOperation { op, operand }) => {
+                let op = match op {
+                    UnaryOperator::Not => "!",
+                    UnaryOperator::Minus => "-",
+                };
+                write!(f, "{}{}", op, operand)
+            }
+            Comp::BinaryOperation(BinaryOperation { op, lhs, rhs }) => {
+                let op = match op {
+                    BinaryOperator::Plus => "+",
+                    BinaryOperator::Minus => "-",
+                    BinaryOperator::And => "&",
+                    BinaryOperator::Or => "|",
+                };
+                write!(f, "{}{}{}", lhs, op, rhs)
+            }
+            Comp::Shift(Shift {
+                register,
+                direction,
+            }) => {
+                let op = match direction {
+                    ShiftDirection::Left => "<<",
+                    ShiftDirection::Right => ">>",
+                };
+                write!(f, "{}{}", register, op)
+            }
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -138,17 +270,21 @@ impl TryFrom<&str> for Dest {
             m: false,
         };
         for c in value.chars() {
-            match c {
-                'A' => dest.a = true,
-                'D' => dest.d = true,
-                'M' => dest.m = true,
+            let register = match c {
+                'A' => &mut dest.a,
+                'D' => &mut dest.d,
+                'M' => &mut dest.m,
                 _ => {
                     return Err(format!(
                         "invalid destination {}, expected a combination of A, D and M",
                         value
                     ))
                 }
+            };
+            if *register {
+                return Err(format!("invalid destination {}, {} is repeated", value, c));
             }
+            *register = true;
         }
         Ok(dest)
     }
@@ -1,53 +1,121 @@
 use std::collections::HashMap;
 
-use super::ast;
-use crate::common::SpanError;
+use super::{ast, object};
+use crate::common::{InstructionSet, Span, SpanError};
+
+/// Symbols that are defined in every program.
+pub const PREDEFINED_SYMBOLS: [(&str, u16); 23] = [
+    ("SP", 0),
+    ("LCL", 1),
+    ("ARG", 2),
+    ("THIS", 3),
+    ("THAT", 4),
+    ("R0", 0),
+    ("R1", 1),
+    ("R2", 2),
+    ("R3", 3),
+    ("R4", 4),
+    ("R5", 5),
+    ("R6", 6),
+    ("R7", 7),
+    ("R8", 8),
+    ("R9", 9),
+    ("R10", 10),
+    ("R11", 11),
+    ("R12", 12),
+    ("R13", 13),
+    ("R14", 14),
+    ("R15", 15),
+    ("SCREEN", 0x4000),
+    ("KBD", 0x6000),
+];
 
 pub struct Codegen<'a> {
     symbol_table: HashMap<&'a str, u16>,
+    labels: Vec<(&'a str, u16)>,
+    variables: Vec<(&'a str, u16)>,
+    /// Variables declared with `.var`, and their address if it was given.
+    declared: Vec<(&'a str, Option<u16>)>,
+    words: Vec<(u16, Span)>,
     next_var_addr: u16,
+    strict: bool,
+    instruction_set: InstructionSet,
 }
 
+/// The number of words in ROM.
+pub const ROM_SIZE: usize = 0x8000;
+
 impl<'a> Codegen<'a> {
     pub fn new() -> Self {
-        let mut symbol_table = HashMap::new();
-        symbol_table.insert("SP", 0);
-        symbol_table.insert("LCL", 1);
-        symbol_table.insert("ARG", 2);
-        symbol_table.insert("THIS", 3);
-        symbol_table.insert("THAT", 4);
-        symbol_table.insert("R0", 0);
-        symbol_table.insert("R1", 1);
-        symbol_table.insert("R2", 2);
-        symbol_table.insert("R3", 3);
-        symbol_table.insert("R4", 4);
-        symbol_table.insert("R5", 5);
-        symbol_table.insert("R6", 6);
-        symbol_table.insert("R7", 7);
-        symbol_table.insert("R8", 8);
-        symbol_table.insert("R9", 9);
-        symbol_table.insert("R10", 10);
-        symbol_table.insert("R11", 11);
-        symbol_table.insert("R12", 12);
-        symbol_table.insert("R13", 13);
-        symbol_table.insert("R14", 14);
-        symbol_table.insert("R15", 15);
-        symbol_table.insert("SCREEN", 0x4000);
-        symbol_table.insert("KBD", 0x6000);
-
+        let symbol_table = PREDEFINED_SYMBOLS.iter().copied().collect();
         Self {
             symbol_table,
+            labels: vec![],
+            variables: vec![],
+            declared: vec![],
+            words: vec![],
             next_var_addr: 0x10,
+            strict: false,
+            instruction_set: InstructionSet::Hack,
         }
     }
 
+    /// In strict mode, a symbol that isn't a label, predefined or declared is an error, rather
+    /// than a variable. VM static variables, like `Main.3`, are implicitly declared, so
+    /// translated programs can be assembled in strict mode.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Sets the instructions that can be used. Shifts are errors unless the instruction set is
+    /// `InstructionSet::Extended`.
+    pub fn set_instruction_set(&mut self, instruction_set: InstructionSet) {
+        self.instruction_set = instruction_set;
+    }
+
+    /// Declares a variable, which is allocated before any undeclared ones. A variable given an
+    /// address is allocated there, and other variables are allocated around it.
+    pub fn declare_variable(&mut self, name: &'a str, address: Option<u16>) {
+        self.declared.push((name, address));
+    }
+
+    /// Returns the labels defined by the last program generated, and their ROM addresses, in the
+    /// order they were defined.
+    pub fn labels(&self) -> &[(&'a str, u16)] {
+        &self.labels
+    }
+
+    /// Returns the variables allocated so far, and their RAM addresses, in the order they were
+    /// allocated.
+    pub fn variables(&self) -> &[(&'a str, u16)] {
+        &self.variables
+    }
+
+    /// Returns each word of the last program generated, and the span of the instruction it was
+    /// generated from. The index of a word is its ROM address.
+    pub fn words(&self) -> &[(u16, Span)] {
+        &self.words
+    }
+
     pub fn generate(&mut self, ast: &'a [ast::Instruction]) -> Result<String, Vec<SpanError>> {
+        self.labels.clear();
+        self.words.clear();
+        self.allocate_declared();
+
+        let mut errors = vec![];
         let mut instructions = vec![];
         for instruction in ast {
-            match &instruction {
+            match instruction {
                 ast::Instruction::Label(label) => {
+                    if self.declared.iter().any(|&(name, _)| name == label.name) {
+                        errors.push(SpanError::new(
+                            format!("label `{}' is also declared as a variable", label.name),
+                            label.span,
+                        ));
+                    }
                     self.symbol_table
-                        .insert(label.name, instructions.len() as u16);
+                        .insert(&label.name, instructions.len() as u16);
+                    self.labels.push((&label.name, instructions.len() as u16));
                 }
                 ast::Instruction::A { .. } | ast::Instruction::C { .. } => {
                     instructions.push(instruction);
@@ -55,12 +123,27 @@ impl<'a> Codegen<'a> {
             };
         }
 
-        let mut errors = vec![];
+        if let Some(first) = instructions.get(ROM_SIZE) {
+            errors.push(SpanError::new(
+                format!(
+                    "the program is {} words, but ROM only holds {}",
+                    instructions.len(),
+                    ROM_SIZE
+                ),
+                first.span(),
+            ));
+        }
         let mut buf = String::with_capacity(instructions.len() * 17);
         for instruction in ast {
             let inst = match &instruction {
                 ast::Instruction::Label(_) => None,
-                ast::Instruction::A(inst) => Some(self.a_instruction(inst)),
+                ast::Instruction::A(inst) => match self.a_instruction(inst) {
+                    Ok(inst) => Some(inst),
+                    Err(err) => {
+                        errors.push(SpanError::new(err, instruction.span()));
+                        None
+                    }
+                },
                 ast::Instruction::C(inst) => match self.c_instruction(inst) {
                     Ok(inst) => Some(inst),
                     Err(err) => {
@@ -72,9 +155,11 @@ impl<'a> Codegen<'a> {
 
             if let Some(inst) = inst {
                 buf.push_str(&format!("{:016b}\n", inst));
+                self.words.push((inst, instruction.span()));
             }
         }
 
+        errors.sort_by_key(|err| err.span.start);
         if errors.is_empty() {
             Ok(buf)
         } else {
@@ -82,24 +167,134 @@ impl<'a> Codegen<'a> {
         }
     }
 
-    fn a_instruction(&mut self, inst: &'a ast::AInstruction) -> u16 {
-        let numeric_address = match inst.addr {
-            ast::Address::Symbol(s) => match self.symbol_table.get(s) {
-                Some(num_addr) => *num_addr,
-                None => {
-                    let addr = self.next_var_addr;
-                    self.next_var_addr += 1;
-                    self.symbol_table.insert(s, addr);
-                    addr
+    /// Generates an object to be linked with others, rather than a whole program. Labels are
+    /// given addresses relative to the start of the object, and A-instructions that refer to
+    /// anything other than a predefined symbol are left for the linker to resolve.
+    pub fn generate_object(
+        &mut self,
+        ast: &'a [ast::Instruction],
+    ) -> Result<object::Object, Vec<SpanError>> {
+        self.labels.clear();
+        self.words.clear();
+
+        let mut errors = vec![];
+        let mut code = vec![];
+        for instruction in ast {
+            let word = match instruction {
+                ast::Instruction::Label(label) => {
+                    if self.declared.iter().any(|&(name, _)| name == label.name) {
+                        errors.push(SpanError::new(
+                            format!("label `{}' is also declared as a variable", label.name),
+                            label.span,
+                        ));
+                    }
+                    self.labels.push((&label.name, code.len() as u16));
+                    continue;
                 }
-            },
-            ast::Address::Value(n) => n,
+                ast::Instruction::A(inst) => object_word(&inst.addr),
+                ast::Instruction::C(inst) => {
+                    self.c_instruction(inst).map(object::Word::Instruction)
+                }
+            };
+            if code.len() == ROM_SIZE {
+                errors.push(SpanError::new(
+                    format!(
+                        "the program is {} words, but ROM only holds {}",
+                        ast.iter()
+                            .filter(|inst| !matches!(inst, ast::Instruction::Label(_)))
+                            .count(),
+                        ROM_SIZE
+                    ),
+                    instruction.span(),
+                ));
+            }
+            match word {
+                Ok(word) => code.push(word),
+                Err(err) => errors.push(SpanError::new(err, instruction.span())),
+            }
+        }
+
+        errors.sort_by_key(|err| err.span.start);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(object::Object {
+            labels: self
+                .labels
+                .iter()
+                .map(|&(name, addr)| (name.to_owned(), addr))
+                .collect(),
+            variables: self
+                .declared
+                .iter()
+                .map(|&(name, address)| (name.to_owned(), address))
+                .collect(),
+            code,
+        })
+    }
+
+    fn a_instruction(&mut self, inst: &'a ast::AInstruction) -> Result<u16, String> {
+        let value = match &inst.addr {
+            ast::Address::Symbol(s) => self.symbol_address(s)? as i64,
+            ast::Address::Value(n) => *n as i64,
+            ast::Address::Expression(expr) => {
+                expr.evaluate(&mut |s| self.symbol_address(s).map(i64::from))?
+            }
         };
-        numeric_address & 0x7FFF
+        a_instruction_value(value)
     }
 
-    fn c_instruction(&mut self, inst: &ast::CInstruction) -> Result<u16, String> {
-        let binary_inst = 0xE000u16;
+    /// Returns the address of a label, variable or predefined symbol, allocating a variable if
+    /// the symbol isn't defined, unless in strict mode.
+    fn symbol_address(&mut self, symbol: &'a str) -> Result<u16, String> {
+        match self.symbol_table.get(symbol) {
+            Some(addr) => Ok(*addr),
+            None if self.strict && !is_vm_static(symbol) => Err(format!(
+                "`{}' isn't defined (declare variables with `.var {}')",
+                symbol, symbol
+            )),
+            None => Ok(self.allocate_variable(symbol)),
+        }
+    }
+
+    // Variables with a fixed address go first, so the others can be allocated around them
+    fn allocate_declared(&mut self) {
+        for i in 0..self.declared.len() {
+            let (name, address) = self.declared[i];
+            if let (Some(address), false) = (address, self.symbol_table.contains_key(name)) {
+                self.symbol_table.insert(name, address);
+                self.variables.push((name, address));
+            }
+        }
+        for i in 0..self.declared.len() {
+            let (name, address) = self.declared[i];
+            if address.is_none() && !self.symbol_table.contains_key(name) {
+                self.allocate_variable(name);
+            }
+        }
+    }
+
+    fn allocate_variable(&mut self, name: &'a str) -> u16 {
+        while self
+            .declared
+            .iter()
+            .any(|&(_, address)| address == Some(self.next_var_addr))
+        {
+            self.next_var_addr += 1;
+        }
+        let addr = self.next_var_addr;
+        self.next_var_addr += 1;
+        self.symbol_table.insert(name, addr);
+        self.variables.push((name, addr));
+        addr
+    }
+
+    /// Encodes a C-instruction, which doesn't depend on any symbols.
+    pub fn c_instruction(&mut self, inst: &ast::CInstruction) -> Result<u16, String> {
+        let binary_inst = match inst.comp {
+            ast::Comp::Shift(_) => 0xA000u16,
+            _ => 0xE000u16,
+        };
 
         let dest_bits = match &inst.dest {
             Some(dest) => {
@@ -129,7 +324,9 @@ impl<'a> Codegen<'a> {
 
     #[allow(clippy::unusual_byte_groupings)]
     fn comp_bits(&self, comp: &ast::Comp) -> Result<u16, String> {
-        use ast::{BinaryOperator::*, Bit::*, Operand::*, Register::*, UnaryOperator};
+        use ast::{
+            BinaryOperator::*, Bit::*, Operand::*, Register::*, ShiftDirection, UnaryOperator,
+        };
 
         let comp_bits = match comp {
             ast::Comp::Bit(Zero) => 0b0_101010,
@@ -145,8 +342,29 @@ impl<'a> Codegen<'a> {
                 (UnaryOperator::Minus, Register(D)) => 0b0_001111,
                 (UnaryOperator::Minus, Register(A)) => 0b0_110011,
                 (UnaryOperator::Minus, Register(M)) => 0b1_110011,
-                _ => return Err(format!("invalid operation {:?} {:?}", op, operand)),
+                _ => return Err(invalid_comp(comp)),
             },
+            ast::Comp::Shift(ast::Shift {
+                register,
+                direction,
+            }) => {
+                if self.instruction_set != InstructionSet::Extended {
+                    return Err(format!(
+                        "`{}' is only in the extended instruction set",
+                        comp
+                    ));
+                }
+                let direction_bit = match direction {
+                    ShiftDirection::Left => 0b0_100000,
+                    ShiftDirection::Right => 0,
+                };
+                let register_bits = match register {
+                    D => 0b0_010000,
+                    A => 0,
+                    M => 0b1_000000,
+                };
+                direction_bit | register_bits
+            }
             ast::Comp::BinaryOperation(ast::BinaryOperation { lhs, op, rhs }) => {
                 match (lhs, op, rhs) {
                     (D, Plus, Bit(One)) => 0b0_011111,
@@ -165,7 +383,7 @@ impl<'a> Codegen<'a> {
                     (D, And, Register(M)) | (M, And, Register(D)) => 0b1_000000,
                     (D, Or, Register(A)) | (A, Or, Register(D)) => 0b0_010101,
                     (D, Or, Register(M)) | (M, Or, Register(D)) => 0b1_010101,
-                    _ => return Err(format!("invalid operation {:?} {:?} {:?}", lhs, op, rhs)),
+                    _ => return Err(invalid_comp(comp)),
                 }
             }
         };
@@ -173,11 +391,64 @@ impl<'a> Codegen<'a> {
     }
 }
 
+/// Returns true if a symbol names a VM static variable, i.e. a file name and a number.
+fn is_vm_static(symbol: &str) -> bool {
+    match symbol.rsplit_once('.') {
+        Some((file, n)) => {
+            !file.is_empty() && !n.is_empty() && n.chars().all(|c| c.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+/// Checks that a value fits in an A-instruction, which only has 15 bits for it.
+pub fn a_instruction_value(value: i64) -> Result<u16, String> {
+    if !(0..0x8000).contains(&value) {
+        return Err(format!(
+            "value {} doesn't fit in an A-instruction, which takes 0-32767",
+            value
+        ));
+    }
+    Ok(value as u16)
+}
+
+// A-instructions that only use predefined symbols can be assembled straight away
+fn object_word(addr: &ast::Address) -> Result<object::Word, String> {
+    let expr = match addr {
+        ast::Address::Value(n) => {
+            return a_instruction_value(*n as i64).map(object::Word::Instruction)
+        }
+        ast::Address::Symbol(s) => ast::Expression::Symbol(s.clone()),
+        ast::Address::Expression(expr) => expr.clone(),
+    };
+    let mut relocatable = false;
+    let value = expr.evaluate(&mut |symbol| match PREDEFINED_SYMBOLS
+        .iter()
+        .find(|&&(name, _)| name == symbol)
+    {
+        Some(&(_, value)) => Ok(value as i64),
+        None => {
+            relocatable = true;
+            Ok(0)
+        }
+    });
+    if relocatable {
+        Ok(object::Word::Reference(expr.into_owned()))
+    } else {
+        a_instruction_value(value?).map(object::Word::Instruction)
+    }
+}
+
+fn invalid_comp(comp: &ast::Comp) -> String {
+    format!("the CPU can't compute `{}'", comp)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::asm::{parser::Parser, tokenizer::Tokenizer};
 
     use super::*;
+    use crate::common::Span;
 
     #[test]
     fn test_instructions() {
@@ -213,6 +484,32 @@ mod tests {
         assert_eq!(out, expected);
     }
 
+    #[test]
+    fn test_local_and_anonymous_labels() {
+        let src = "(a)\n(.loop)\n@.loop\n0;JMP\n(b)\n(.loop)\n(1)\n@1b\n@1f\n(1)\n@.loop";
+        let instructions = Parser::new(Tokenizer::new(src)).parse().unwrap();
+        let mut cg = Codegen::new();
+        cg.generate(&instructions).unwrap();
+        assert_eq!(
+            cg.labels(),
+            &[
+                ("a", 0),
+                ("a.loop", 0),
+                ("b", 2),
+                ("b.loop", 2),
+                ("1#1", 2),
+                ("1#2", 4)
+            ]
+        );
+        assert_eq!(
+            cg.words()
+                .iter()
+                .map(|&(word, _)| word)
+                .collect::<Vec<u16>>(),
+            vec![0, 0b1110101010000111, 2, 4, 2]
+        );
+    }
+
     #[test]
     fn test_variable_addresses() {
         let src = "@foo
@@ -230,4 +527,131 @@ mod tests {
         let out = cg.generate(&parser.parse().unwrap()).unwrap();
         assert_eq!(out, expected);
     }
+
+    #[test]
+    fn test_expressions() {
+        let src = "@SCREEN+32*10
+                        @END-1
+                        @(KBD-SCREEN)*2
+                        (END)";
+        let mut parser = Parser::new(Tokenizer::new(src));
+        let mut cg = Codegen::new();
+        let out = cg.generate(&parser.parse().unwrap()).unwrap();
+        let expected = "0100000101000000\n\
+                             0000000000000010\n\
+                             0100000000000000\n";
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let src = "@-1\n@SCREEN*2";
+        let mut parser = Parser::new(Tokenizer::new(src));
+        let mut cg = Codegen::new();
+        let errs = cg.generate(&parser.parse().unwrap()).unwrap_err();
+        assert_eq!(
+            errs.iter().map(|e| e.msg.as_str()).collect::<Vec<&str>>(),
+            vec![
+                "value -1 doesn't fit in an A-instruction, which takes 0-32767",
+                "value 32768 doesn't fit in an A-instruction, which takes 0-32767"
+            ]
+        );
+        assert_eq!(errs[1].span, Span::new(4, 13));
+    }
+
+    #[test]
+    fn test_invalid_comp() {
+        let src = "D=D+D\nM=-M\nA=A&M\nD=D<<";
+        let mut parser = Parser::new(Tokenizer::new(src));
+        let errs = Codegen::new()
+            .generate(&parser.parse().unwrap())
+            .unwrap_err();
+        assert_eq!(
+            errs.iter().map(|e| e.msg.as_str()).collect::<Vec<&str>>(),
+            vec![
+                "the CPU can't compute `D+D'",
+                "the CPU can't compute `A&M'",
+                "`D<<' is only in the extended instruction set"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strict() {
+        let src = "(LOOP)\n@LOOP\n@R0\n@foo+1\n@bar";
+        let instructions = Parser::new(Tokenizer::new(src)).parse().unwrap();
+        let mut cg = Codegen::new();
+        cg.set_strict(true);
+        let errs = cg.generate(&instructions).unwrap_err();
+        assert_eq!(
+            errs.iter().map(|e| e.msg.as_str()).collect::<Vec<&str>>(),
+            vec![
+                "`foo' isn't defined (declare variables with `.var foo')",
+                "`bar' isn't defined (declare variables with `.var bar')"
+            ]
+        );
+        assert!(cg.variables().is_empty());
+
+        // VM statics are implicitly declared
+        let src = "@Main.0\nM=0\n@Main.12\n@Main.x\n";
+        let instructions = Parser::new(Tokenizer::new(src)).parse().unwrap();
+        let mut cg = Codegen::new();
+        cg.set_strict(true);
+        let errs = cg.generate(&instructions).unwrap_err();
+        assert_eq!(errs.len(), 1);
+        assert_eq!(
+            errs[0].msg,
+            "`Main.x' isn't defined (declare variables with `.var Main.x')"
+        );
+    }
+
+    #[test]
+    fn test_declared_variables() {
+        let src = "@i\n@screen\n@n\n@temp\n(loop)\n@loop";
+        let instructions = Parser::new(Tokenizer::new(src)).parse().unwrap();
+        let mut cg = Codegen::new();
+        cg.declare_variable("n", None);
+        cg.declare_variable("screen", Some(0x4000));
+        cg.declare_variable("i", None);
+        cg.declare_variable("temp", Some(17));
+        cg.set_strict(true);
+        cg.generate(&instructions).unwrap();
+        assert_eq!(
+            cg.variables(),
+            &[("screen", 0x4000), ("temp", 17), ("n", 16), ("i", 18)]
+        );
+
+        // Without strict mode, undeclared variables are allocated after the declared ones
+        let src = "@x\n@i\n";
+        let instructions = Parser::new(Tokenizer::new(src)).parse().unwrap();
+        let mut cg = Codegen::new();
+        cg.declare_variable("i", None);
+        cg.declare_variable("fixed", Some(17));
+        cg.generate(&instructions).unwrap();
+        assert_eq!(cg.variables(), &[("fixed", 17), ("i", 16), ("x", 18)]);
+
+        let src = "(i)\n@i\n";
+        let instructions = Parser::new(Tokenizer::new(src)).parse().unwrap();
+        let mut cg = Codegen::new();
+        cg.declare_variable("i", None);
+        let errs = cg.generate(&instructions).unwrap_err();
+        assert_eq!(errs[0].msg, "label `i' is also declared as a variable");
+    }
+
+    #[test]
+    fn test_rom_overflow() {
+        let src = "D=0\n".repeat(ROM_SIZE) + "(END)\n@5\n0;JMP\n";
+        let instructions = Parser::new(Tokenizer::new(&src)).parse().unwrap();
+        let errs = Codegen::new().generate(&instructions).unwrap_err();
+        assert_eq!(errs.len(), 1);
+        assert_eq!(
+            errs[0].msg,
+            "the program is 32770 words, but ROM only holds 32768"
+        );
+        assert_eq!(errs[0].span.start, 4 * ROM_SIZE + 6);
+
+        let src = "D=0\n".repeat(ROM_SIZE);
+        let instructions = Parser::new(Tokenizer::new(&src)).parse().unwrap();
+        assert!(Codegen::new().generate(&instructions).is_ok());
+    }
 }
@@ -0,0 +1,220 @@
+//! Generates the code for the preprocessor's data directives. ROM can only hold instructions, so
+//! constant data is stored in RAM by code that runs when the program does. Runs of the same value
+//! are stored by a loop when that's shorter than storing each word in turn.
+
+// Loops take a fixed number of instructions, whereas storing a run word by word takes two per word
+const SMALL_LOOP_LEN: usize = 8;
+const LOOP_LEN: usize = 19;
+
+/// Returns instructions that store `values` in RAM from `address` onwards. `address` is a number
+/// or an expression the assembler can evaluate. Loops are labelled `label.1`, `label.2`, etc.
+///
+/// The code overwrites A and D. A run of values that's stored with a loop uses the first word of
+/// the run to count, and stores it last.
+pub fn store(address: &str, values: &[u16], label: &str) -> Vec<String> {
+    let mut gen = StoreGen {
+        address,
+        label,
+        code: vec![],
+        loops: 0,
+        d: None,
+    };
+    let mut offset = 0;
+    while offset < values.len() {
+        let value = values[offset];
+        let len = values[offset..].iter().take_while(|&&v| v == value).count();
+        gen.run(offset, value, len);
+        offset += len;
+    }
+    gen.code
+}
+
+struct StoreGen<'a> {
+    address: &'a str,
+    label: &'a str,
+    code: Vec<String>,
+    loops: usize,
+    // The value in D, if it's known
+    d: Option<u16>,
+}
+
+impl<'a> StoreGen<'a> {
+    fn run(&mut self, offset: usize, value: u16, len: usize) {
+        let at = self.at(offset);
+        match comp(value) {
+            Some(comp) if 2 * len > SMALL_LOOP_LEN => {
+                // Count D down to 0, storing at `at + D`
+                let label = self.next_label();
+                self.emit(&format!("@{}", len - 1));
+                self.emit("D=A");
+                self.emit(&format!("({})", label));
+                self.emit(&format!("@{}", at));
+                self.emit("A=D+A");
+                self.emit(&format!("M={}", comp));
+                self.emit("D=D-1");
+                self.emit(&format!("@{}", label));
+                self.emit("D;JGE");
+                self.d = Some(0xffff);
+            }
+            Some(comp) => {
+                self.emit(&format!("@{}", at));
+                self.emit(&format!("M={}", comp));
+                for _ in 1..len {
+                    self.emit("A=A+1");
+                    self.emit(&format!("M={}", comp));
+                }
+            }
+            None if 2 * len + 2 > LOOP_LEN => {
+                // D is needed for the value, so the address to store at is kept in the first word,
+                // counting down from the end
+                let label = self.next_label();
+                self.emit(&format!("@{}", self.at(offset + len - 1)));
+                self.emit("D=A");
+                self.emit(&format!("@{}", at));
+                self.emit("M=D");
+                // D holds an address here, and the count on the way round the loop
+                self.d = None;
+                self.emit(&format!("({})", label));
+                self.load(value);
+                self.emit(&format!("@{}", at));
+                self.emit("A=M");
+                self.emit("M=D");
+                self.emit(&format!("@{}", at));
+                self.emit("MD=M-1");
+                self.emit(&format!("@{}", at));
+                self.emit("D=D-A");
+                self.emit(&format!("@{}", label));
+                self.emit("D;JGT");
+                self.d = None;
+                self.load(value);
+                self.emit(&format!("@{}", at));
+                self.emit("M=D");
+            }
+            None => {
+                self.load(value);
+                self.emit(&format!("@{}", at));
+                self.emit("M=D");
+                for _ in 1..len {
+                    self.emit("A=A+1");
+                    self.emit("M=D");
+                }
+            }
+        }
+    }
+
+    /// Loads `value` into D, taking advantage of what's already there.
+    fn load(&mut self, value: u16) {
+        if self.d == Some(value) {
+            return;
+        }
+        if let Some(comp) = comp(value) {
+            self.emit(&format!("D={}", comp));
+        } else if self.d == Some(value.wrapping_sub(1)) {
+            self.emit("D=D+1");
+        } else if self.d == Some(value.wrapping_add(1)) {
+            self.emit("D=D-1");
+        } else if value < 0x8000 {
+            self.emit(&format!("@{}", value));
+            self.emit("D=A");
+        } else {
+            self.emit(&format!("@{}", !value));
+            self.emit("D=!A");
+        }
+        self.d = Some(value);
+    }
+
+    fn at(&self, offset: usize) -> String {
+        match self.address.parse::<usize>() {
+            Ok(address) => (address + offset).to_string(),
+            Err(_) if offset == 0 => self.address.to_owned(),
+            Err(_) => format!("{}+{}", self.address, offset),
+        }
+    }
+
+    fn next_label(&mut self) -> String {
+        self.loops += 1;
+        format!("{}.{}", self.label, self.loops)
+    }
+
+    fn emit(&mut self, inst: &str) {
+        self.code.push(inst.to_owned());
+    }
+}
+
+// The computation for values that C-instructions can produce directly
+fn comp(value: u16) -> Option<&'static str> {
+    match value {
+        0 => Some("0"),
+        1 => Some("1"),
+        0xffff => Some("-1"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{Codegen, Parser, Tokenizer};
+    use crate::emulator::Emulator;
+
+    // Runs the code and returns the instruction count and the RAM from 1000 on
+    fn run(address: &str, values: &[u16]) -> (usize, Vec<u16>) {
+        let mut code = store(address, values, "$data");
+        let len = code.iter().filter(|inst| !inst.starts_with('(')).count();
+        code.extend(vec![
+            "(halt)".to_owned(),
+            "@halt".to_owned(),
+            "0;JMP".to_owned(),
+        ]);
+        let src = code.join("\n");
+        let instructions = Parser::new(Tokenizer::new(&src)).parse().unwrap();
+        let mut gen = Codegen::new();
+        let rom = gen
+            .generate(&instructions)
+            .unwrap()
+            .lines()
+            .map(|line| u16::from_str_radix(line, 2).unwrap())
+            .collect::<Vec<u16>>();
+
+        let mut emu = Emulator::new(rom);
+        emu.step_n(100_000).unwrap();
+        (len, emu.memory()[1000..1000 + values.len() + 1].to_vec())
+    }
+
+    #[test]
+    fn test_store() {
+        let values = [7, 8, 7, 0xfff0, 0, 0, 1, 0xffff, 0xffff, 30000];
+        let (len, ram) = run("1000", &values);
+        assert_eq!(ram[..values.len()], values);
+        assert_eq!(ram[values.len()], 0);
+        assert!(len < 4 * values.len());
+    }
+
+    #[test]
+    fn test_loop_after_nearby_value() {
+        // D holds the previous value before the loop, but not inside it
+        let mut values = vec![41];
+        values.extend(vec![42; 20]);
+        values.extend(vec![43; 20]);
+        let (_, ram) = run("1000", &values);
+        assert_eq!(ram[..values.len()], values[..]);
+        assert_eq!(ram[values.len()], 0);
+    }
+
+    #[test]
+    fn test_loops() {
+        let mut values = vec![0; 100];
+        values.extend(vec![0xffff; 6]);
+        values.extend(vec![42; 200]);
+        values.extend(vec![0x8000; 9]);
+        values.push(1);
+
+        let (len, ram) = run("1000", &values);
+        assert_eq!(ram[..values.len()], values[..]);
+        assert_eq!(ram[values.len()], 0);
+        assert_eq!(len, 2 * SMALL_LOOP_LEN + 2 * LOOP_LEN + 2);
+
+        let (_, ram) = run("R0+1000", &values);
+        assert_eq!(ram[..values.len()], values[..]);
+    }
+}
@@ -0,0 +1,281 @@
+//! Checks for mistakes that assemble without complaint but almost certainly don't do what was
+//! intended.
+
+use super::{ast, codegen::PREDEFINED_SYMBOLS};
+use crate::common::{Span, SpanError};
+
+/// Returns errors for labels that are defined twice or shadow a predefined symbol, and warnings
+/// for unused labels, variables that look like misspelled symbols, and jumps whose target is
+/// overwritten by the same instruction. The results are sorted by position.
+pub fn check(instructions: &[ast::Instruction]) -> Vec<SpanError> {
+    check_labels(instructions, true)
+}
+
+/// Like `check`, but for an object rather than a whole program, so labels that are never used
+/// aren't reported, as other objects can use them.
+pub fn check_object(instructions: &[ast::Instruction]) -> Vec<SpanError> {
+    check_labels(instructions, false)
+}
+
+fn check_labels(instructions: &[ast::Instruction], warn_unused: bool) -> Vec<SpanError> {
+    let mut errors = vec![];
+
+    let mut labels: Vec<(&str, Span)> = vec![];
+    for instruction in instructions {
+        if let ast::Instruction::Label(label) = instruction {
+            if is_predefined(&label.name) {
+                errors.push(SpanError::new(
+                    format!("label `{}' shadows the predefined symbol", label.name),
+                    label.span,
+                ));
+            } else if let Some(&(_, first)) = labels.iter().find(|&&(name, _)| name == label.name) {
+                errors.push(
+                    SpanError::new(
+                        format!("label `{}' is already defined", label.name),
+                        label.span,
+                    )
+                    .with_note("first defined here".to_owned(), first),
+                );
+            } else {
+                labels.push((&label.name, label.span));
+            }
+        }
+    }
+
+    // Every symbol referenced by an A-instruction, with the number of times it's used and the
+    // first instruction using it
+    let mut uses: Vec<(&str, usize, Span)> = vec![];
+    for instruction in instructions {
+        let inst = match instruction {
+            ast::Instruction::A(inst) => inst,
+            ast::Instruction::C(inst) => {
+                check_c_instruction(inst, &mut errors);
+                continue;
+            }
+            ast::Instruction::Label(_) => continue,
+        };
+        let mut symbols: Vec<&str> = vec![];
+        match &inst.addr {
+            ast::Address::Symbol(s) => symbols.push(s),
+            ast::Address::Expression(expr) => expression_symbols(expr, &mut symbols),
+            ast::Address::Value(_) => {}
+        }
+        for symbol in symbols {
+            match uses.iter_mut().find(|(name, _, _)| *name == symbol) {
+                Some((_, count, _)) => *count += 1,
+                None => uses.push((symbol, 1, inst.span)),
+            }
+        }
+    }
+
+    // Labels containing `$` are generated by the VM translator, which labels more places than it
+    // jumps to, and anonymous labels (named like `1#2`) are often only there for readability
+    for &(label, span) in labels
+        .iter()
+        .filter(|(name, _)| warn_unused && !name.contains('$') && !name.contains('#'))
+    {
+        if !uses.iter().any(|&(name, _, _)| name == label) {
+            errors.push(SpanError::warning(
+                format!("label `{}' is never used", label),
+                span,
+            ));
+        }
+    }
+
+    // A variable is suspicious if its name is one typo away from a label, a predefined symbol, or
+    // a variable that's used more often
+    let is_label = |symbol: &str| labels.iter().any(|&(name, _)| name == symbol);
+    let variables = uses
+        .iter()
+        .filter(|&&(name, _, _)| !is_label(name) && !is_predefined(name))
+        .collect::<Vec<_>>();
+    for &&(variable, count, span) in &variables {
+        let similar = labels
+            .iter()
+            .map(|&(name, _)| name)
+            .chain(PREDEFINED_SYMBOLS.iter().map(|&(name, _)| name))
+            .chain(
+                variables
+                    .iter()
+                    .filter(|&&&(_, other_count, _)| other_count > count)
+                    .map(|&&(name, _, _)| name),
+            )
+            .find(|name| is_typo(variable, name));
+        if let Some(name) = similar {
+            errors.push(SpanError::warning(
+                format!(
+                    "`{}' isn't defined, so it's a new variable; did you mean `{}'?",
+                    variable, name
+                ),
+                span,
+            ));
+        }
+    }
+
+    errors.sort_by_key(|err| err.span.start);
+    errors
+}
+
+fn check_c_instruction(inst: &ast::CInstruction, errors: &mut Vec<SpanError>) {
+    let assigns_a = inst.dest.as_ref().is_some_and(|dest| dest.a);
+    if assigns_a && inst.jump.is_some() {
+        errors.push(SpanError::warning(
+            "this instruction assigns to A and jumps; the jump goes to A's old value, not the \
+             one assigned"
+                .to_owned(),
+            inst.span,
+        ));
+    }
+}
+
+fn expression_symbols<'a>(expr: &'a ast::Expression, symbols: &mut Vec<&'a str>) {
+    match expr {
+        ast::Expression::Symbol(s) => symbols.push(s),
+        ast::Expression::Value(_) => {}
+        ast::Expression::Negate(expr) => expression_symbols(expr, symbols),
+        ast::Expression::Binary(lhs, _, rhs) => {
+            expression_symbols(lhs, symbols);
+            expression_symbols(rhs, symbols);
+        }
+    }
+}
+
+fn is_predefined(symbol: &str) -> bool {
+    PREDEFINED_SYMBOLS.iter().any(|&(name, _)| name == symbol)
+}
+
+/// Returns true if `a` and `b` differ only in case, or by a single inserted, deleted, replaced
+/// or swapped character. Very short names are too likely to be similar by chance to count, and
+/// names that only differ by digits (e.g. `Main.1` and `Main.2`) are deliberately numbered.
+fn is_typo(a: &str, b: &str) -> bool {
+    if a == b {
+        return false;
+    }
+    if a.eq_ignore_ascii_case(b) {
+        return true;
+    }
+    let (a, b) = (
+        a.chars().collect::<Vec<char>>(),
+        b.chars().collect::<Vec<char>>(),
+    );
+    if a.len().min(b.len()) < 3 {
+        return false;
+    }
+
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a, b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    if a.iter().chain(b).all(char::is_ascii_digit) {
+        return false;
+    }
+    match (a.len(), b.len()) {
+        (0, 1) | (1, 0) | (1, 1) => true,
+        (2, 2) => a[0] == b[1] && a[1] == b[0],
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        asm::{Parser, Tokenizer},
+        common::{Note, Severity},
+    };
+
+    fn check_src(src: &str) -> Vec<(Severity, String)> {
+        let instructions = Parser::new(Tokenizer::new(src)).parse().unwrap();
+        check(&instructions)
+            .into_iter()
+            .map(|err| (err.severity, err.msg))
+            .collect()
+    }
+
+    #[test]
+    fn test_labels() {
+        assert_eq!(
+            check_src("(LOOP)\n@LOOP\n0;JMP\n(LOOP)\n(SCREEN)\n(END)\n(Main.main$ret.1)\n"),
+            vec![
+                (
+                    Severity::Error,
+                    "label `LOOP' is already defined".to_owned()
+                ),
+                (
+                    Severity::Error,
+                    "label `SCREEN' shadows the predefined symbol".to_owned()
+                ),
+                (Severity::Warning, "label `END' is never used".to_owned()),
+            ]
+        );
+
+        let src = "(LOOP)\n@LOOP\n(LOOP)\n";
+        let errors = check(&Parser::new(Tokenizer::new(src)).parse().unwrap());
+        assert_eq!(
+            errors[0].notes,
+            vec![Note {
+                msg: "first defined here".to_owned(),
+                span: Span::new(0, 6)
+            }]
+        );
+    }
+
+    #[test]
+    fn test_object_labels() {
+        let src = "(f)\n(LOOP)\n(LOOP)\n";
+        let instructions = Parser::new(Tokenizer::new(src)).parse().unwrap();
+        let errors = check_object(&instructions);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].msg, "label `LOOP' is already defined");
+    }
+
+    #[test]
+    fn test_variable_typos() {
+        assert_eq!(
+            check_src(
+                "(label)\n@lable\n0;JMP\n@label\n@counter\nM=0\n@counter\n@countr\n@kbd\n@i\n@j\n"
+            ),
+            vec![
+                (
+                    Severity::Warning,
+                    "`lable' isn't defined, so it's a new variable; did you mean `label'?"
+                        .to_owned()
+                ),
+                (
+                    Severity::Warning,
+                    "`countr' isn't defined, so it's a new variable; did you mean `counter'?"
+                        .to_owned()
+                ),
+                (
+                    Severity::Warning,
+                    "`kbd' isn't defined, so it's a new variable; did you mean `KBD'?".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_jump_clobbering_a() {
+        let errors = check_src("@R0\nA=M;JMP\nD=M;JGT\nAM=M-1\n");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, Severity::Warning);
+    }
+
+    #[test]
+    fn test_is_typo() {
+        assert!(is_typo("lable", "label"));
+        assert!(!is_typo("count", "counter"));
+        assert!(is_typo("countr", "counter"));
+        assert!(is_typo("counterr", "counter"));
+        assert!(is_typo("cointer", "counter"));
+        assert!(is_typo("Loop", "LOOP"));
+        assert!(!is_typo("x1", "x2"));
+        assert!(!is_typo("Main.1", "Main.2"));
+        assert!(!is_typo("Main.1", "Main.12"));
+        assert!(!is_typo("loop", "loop"));
+    }
+}
@@ -0,0 +1,227 @@
+use super::hackx::Hackx;
+use crate::common::InstructionSet;
+
+pub fn disassemble(instruction: u16) -> Option<String> {
+    disassemble_for(instruction, InstructionSet::Hack)
+}
+
+/// Disassembles an instruction from `instruction_set`. In the extended instruction set,
+/// C-instructions starting with `101` are shifts.
+pub fn disassemble_for(instruction: u16, instruction_set: InstructionSet) -> Option<String> {
+    if instruction & 0x8000 == 0 {
+        return Some(format!("@{}", instruction));
+    }
+
+    let comp_bits = (instruction >> 6) & 0b1111111;
+    let comp = if instruction_set == InstructionSet::Extended && instruction & 0xE000 == 0xA000 {
+        shift_mnemonic(comp_bits)?
+    } else {
+        comp_mnemonic(comp_bits)?
+    };
+    let dest = dest_mnemonic((instruction >> 3) & 0b111);
+    let jump = jump_mnemonic(instruction & 0b111);
+
+    let mut buf = String::new();
+    if let Some(dest) = dest {
+        buf.push_str(dest);
+        buf.push('=');
+    }
+    buf.push_str(comp);
+    if let Some(jump) = jump {
+        buf.push(';');
+        buf.push_str(jump);
+    }
+    Some(buf)
+}
+
+/// Disassembles a whole program, one instruction per line. If the program has symbols and line
+/// numbers, labels are put back before the instructions they point to, and a comment notes the
+/// source line wherever it doesn't just follow on from the line before. Invalid instructions are
+/// written as comments.
+pub fn disassemble_program(program: &Hackx) -> String {
+    let mut out = String::new();
+    let mut last_line = None;
+    for (addr, &word) in program.rom.iter().enumerate() {
+        let addr = addr as u16;
+        for label in program.labels_at(addr) {
+            out.push_str(&format!("({})\n", label));
+        }
+        let line = program.source_line(addr);
+        if let Some((file, number)) = line {
+            let follows_on = last_line.is_some_and(|(last_file, last_number)| {
+                last_file == file && (last_number == number || last_number + 1 == number)
+            });
+            if !follows_on {
+                out.push_str(&format!("    // {}:{}\n", file, number));
+            }
+        }
+        last_line = line;
+
+        match disassemble_for(word, program.instruction_set) {
+            Some(text) => out.push_str(&format!("    {}\n", text)),
+            None => out.push_str(&format!("    // invalid instruction {:016b}\n", word)),
+        }
+    }
+    // Labels can also point just past the end of the program
+    for label in program.labels_at(program.rom.len() as u16) {
+        out.push_str(&format!("({})\n", label));
+    }
+    out
+}
+
+#[allow(clippy::unusual_byte_groupings)]
+fn comp_mnemonic(comp_bits: u16) -> Option<&'static str> {
+    let mnemonic = match comp_bits {
+        0b0_101010 => "0",
+        0b0_111111 => "1",
+        0b0_111010 => "-1",
+        0b0_001100 => "D",
+        0b0_110000 => "A",
+        0b1_110000 => "M",
+        0b0_001101 => "!D",
+        0b0_110001 => "!A",
+        0b1_110001 => "!M",
+        0b0_001111 => "-D",
+        0b0_110011 => "-A",
+        0b1_110011 => "-M",
+        0b0_011111 => "D+1",
+        0b0_110111 => "A+1",
+        0b1_110111 => "M+1",
+        0b0_001110 => "D-1",
+        0b0_110010 => "A-1",
+        0b1_110010 => "M-1",
+        0b0_000010 => "D+A",
+        0b1_000010 => "D+M",
+        0b0_010011 => "D-A",
+        0b1_010011 => "D-M",
+        0b0_000111 => "A-D",
+        0b1_000111 => "M-D",
+        0b0_000000 => "D&A",
+        0b1_000000 => "D&M",
+        0b0_010101 => "D|A",
+        0b1_010101 => "D|M",
+        _ => return None,
+    };
+    Some(mnemonic)
+}
+
+#[allow(clippy::unusual_byte_groupings)]
+fn shift_mnemonic(comp_bits: u16) -> Option<&'static str> {
+    let mnemonic = match comp_bits {
+        0b0_000000 => "A>>",
+        0b0_010000 => "D>>",
+        0b1_000000 => "M>>",
+        0b0_100000 => "A<<",
+        0b0_110000 => "D<<",
+        0b1_100000 => "M<<",
+        _ => return None,
+    };
+    Some(mnemonic)
+}
+
+fn dest_mnemonic(dest_bits: u16) -> Option<&'static str> {
+    match dest_bits {
+        0b001 => Some("M"),
+        0b010 => Some("D"),
+        0b011 => Some("MD"),
+        0b100 => Some("A"),
+        0b101 => Some("AM"),
+        0b110 => Some("AD"),
+        0b111 => Some("AMD"),
+        _ => None,
+    }
+}
+
+fn jump_mnemonic(jump_bits: u16) -> Option<&'static str> {
+    match jump_bits {
+        0b001 => Some("JGT"),
+        0b010 => Some("JEQ"),
+        0b011 => Some("JGE"),
+        0b100 => Some("JLT"),
+        0b101 => Some("JNE"),
+        0b110 => Some("JLE"),
+        0b111 => Some("JMP"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::{hackx::LineEntry, Codegen, Parser, Tokenizer};
+
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let src = "@0\n@32767\nD=M\nAM=M+1\nD;JGT\nAMD=D|A;JMP\nM=-1\n0;JMP\nD=!M";
+        let mut parser = Parser::new(Tokenizer::new(src));
+        let mut cg = Codegen::new();
+        let out = cg.generate(&parser.parse().unwrap()).unwrap();
+        let disassembled = out
+            .lines()
+            .map(|line| disassemble(u16::from_str_radix(line, 2).unwrap()).unwrap())
+            .collect::<Vec<String>>();
+        assert_eq!(disassembled, src.lines().collect::<Vec<&str>>());
+    }
+
+    #[test]
+    fn test_shifts() {
+        let src = "D=D<<\nM=M>>;JLT\nAD=A<<\nA>>;JMP";
+        let mut parser = Parser::new(Tokenizer::new(src));
+        let mut cg = Codegen::new();
+        cg.set_instruction_set(InstructionSet::Extended);
+        let out = cg.generate(&parser.parse().unwrap()).unwrap();
+        let words = out
+            .lines()
+            .map(|line| u16::from_str_radix(line, 2).unwrap())
+            .collect::<Vec<u16>>();
+        assert!(words.iter().all(|word| word & 0xE000 == 0xA000));
+        let disassembled = words
+            .iter()
+            .map(|&word| disassemble_for(word, InstructionSet::Extended).unwrap())
+            .collect::<Vec<String>>();
+        assert_eq!(disassembled, src.lines().collect::<Vec<&str>>());
+
+        // Without the extension, the CPU ignores the prefix
+        assert_eq!(disassemble(words[0]).unwrap(), "D=A");
+    }
+
+    #[test]
+    fn test_disassemble_program() {
+        let mut program = Hackx::from_rom(vec![16, 0b1111110111001000, 0, 0b1110111110000111]);
+        assert_eq!(
+            disassemble_program(&program),
+            "    @16\n    M=M+1\n    @0\n    // invalid instruction 1110111110000111\n"
+        );
+
+        program.files = vec!["main.asm".to_owned()];
+        program.labels = vec![("LOOP".to_owned(), 0), ("END".to_owned(), 4)];
+        program.lines = vec![
+            LineEntry {
+                address: 0,
+                file: 0,
+                line: 2,
+            },
+            LineEntry {
+                address: 2,
+                file: 0,
+                line: 5,
+            },
+            LineEntry {
+                address: 3,
+                file: 0,
+                line: 6,
+            },
+        ];
+        assert_eq!(
+            disassemble_program(&program),
+            "(LOOP)\n    // main.asm:2\n    @16\n    M=M+1\n    // main.asm:5\n    @0\n    \
+             // invalid instruction 1110111110000111\n(END)\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_comp() {
+        assert_eq!(disassemble(0b1110111110000000), None);
+    }
+}
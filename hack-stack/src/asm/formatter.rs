@@ -0,0 +1,219 @@
+//! Formats assembly canonically: labels and directives start at column 0, instructions are
+//! indented by four spaces, and C-instructions are spelled the standard way, e.g. `D+A` rather
+//! than `A+D` and `AMD` rather than `DAM`. Comments are kept, runs of blank lines are collapsed
+//! to one, and comments on their own line are indented like the line they're above.
+//!
+//! Preprocessor directives and macro calls are kept as they are, apart from their indentation.
+
+use super::{
+    ast, disassemble_for, preprocessor::comment_start, tokens::Kind, Codegen, Parser, Tokenizer,
+};
+use crate::common::{InstructionSet, SpanError};
+
+const INDENT: &str = "    ";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum LineKind {
+    Blank,
+    Comment,
+    Directive,
+    MacroCall,
+    Instruction,
+}
+
+/// Formats a source file, or returns the errors that prevent it being parsed.
+pub fn format(src: &str) -> Result<String, Vec<SpanError>> {
+    let mut lines = vec![];
+    let mut start = 0;
+    for line in src.split('\n') {
+        lines.push((start, line.trim_end_matches('\r')));
+        start += line.len() + 1;
+    }
+    let kinds = lines
+        .iter()
+        .map(|&(_, line)| line_kind(line))
+        .collect::<Vec<LineKind>>();
+
+    // Anything the parser doesn't understand is blanked out, so the instructions stay where they
+    // are and errors point at the right place
+    let mut masked = String::with_capacity(src.len());
+    for (&(_, line), &kind) in lines.iter().zip(&kinds) {
+        if kind == LineKind::Instruction {
+            masked.push_str(line);
+        } else {
+            masked.push_str(&" ".repeat(line.len()));
+        }
+        masked.push('\n');
+    }
+    let instructions = Parser::new(Tokenizer::new(&masked)).parse()?;
+
+    let mut code = vec![None; lines.len()];
+    // Shifts are formatted like any other instruction, whether or not they'll be assembled
+    let mut gen = Codegen::new();
+    gen.set_instruction_set(InstructionSet::Extended);
+    let mut errors = vec![];
+    for instruction in &instructions {
+        let span = instruction.span();
+        let line = lines.partition_point(|&(start, _)| start <= span.start) - 1;
+        let text = match instruction {
+            ast::Instruction::Label(_) | ast::Instruction::A(_) => src[span.start..span.end]
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .collect(),
+            ast::Instruction::C(inst) => match gen.c_instruction(inst) {
+                Ok(word) => disassemble_for(word, InstructionSet::Extended).unwrap(),
+                Err(err) => {
+                    errors.push(SpanError::new(err, span));
+                    continue;
+                }
+            },
+        };
+        let indent = match instruction {
+            ast::Instruction::Label(_) => "",
+            _ => INDENT,
+        };
+        code[line] = Some(format!("{}{}", indent, text));
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut out: Vec<String> = vec![];
+    for (i, (&(_, line), &kind)) in lines.iter().zip(&kinds).enumerate() {
+        let (text, comment) = split_comment(line);
+        let formatted = match kind {
+            LineKind::Blank => {
+                if out.last().is_some_and(|line| !line.is_empty()) {
+                    out.push(String::new());
+                }
+                continue;
+            }
+            LineKind::Comment => {
+                // Comments right above some code are indented like it
+                let next = (i..lines.len()).find(|&j| kinds[j] != LineKind::Comment);
+                let indent = match next.map(|j| (kinds[j], &code[j])) {
+                    Some((LineKind::Instruction, Some(code))) if code.starts_with(INDENT) => INDENT,
+                    Some((LineKind::MacroCall, _)) => INDENT,
+                    _ => "",
+                };
+                out.push(format!("{}{}", indent, comment.unwrap_or("")));
+                continue;
+            }
+            LineKind::Directive => text.trim().to_owned(),
+            LineKind::MacroCall => format!("{}{}", INDENT, text.trim()),
+            LineKind::Instruction => code[i].clone().unwrap_or_default(),
+        };
+        out.push(match comment {
+            Some(comment) => format!("{} {}", formatted, comment),
+            None => formatted,
+        });
+    }
+    while out.last().is_some_and(String::is_empty) {
+        out.pop();
+    }
+
+    let mut formatted = out.join("\n");
+    if !formatted.is_empty() {
+        formatted.push('\n');
+    }
+    Ok(formatted)
+}
+
+fn line_kind(line: &str) -> LineKind {
+    let (text, comment) = split_comment(line);
+    let text = text.trim();
+    if text.is_empty() {
+        return match comment {
+            Some(_) => LineKind::Comment,
+            None => LineKind::Blank,
+        };
+    }
+    if text.starts_with('.') {
+        return LineKind::Directive;
+    }
+
+    // A macro call is a name that isn't a register, followed by its arguments if it has any
+    let mut tokens = Tokenizer::new(text).map(|token| token.kind);
+    match (tokens.next(), tokens.next()) {
+        (Some(Kind::Identifier("A" | "D" | "M")), None) => LineKind::Instruction,
+        (
+            Some(Kind::Identifier(_)),
+            None | Some(Kind::Identifier(_) | Kind::Number(_) | Kind::AtSign),
+        ) => LineKind::MacroCall,
+        _ => LineKind::Instruction,
+    }
+}
+
+// Splits a line into its code and comment, trimming the end of each. Directives can contain
+// strings, so this uses the preprocessor's idea of where a comment starts.
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    match comment_start(line) {
+        Some(i) => (line[..i].trim_end(), Some(line[i..].trim_end())),
+        None => (line.trim_end(), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm::preprocess, common::SourceFile};
+
+    #[test]
+    fn test_format() {
+        let src = "\n\n// Adds 1 to R0\n  @R0 // counter\nM = M + 1\n(LOOP)\n\n\n   \
+                   // Jumps back\n@ LOOP\n0 ; JMP\nDA=A+D\nM=M&D;JGT\nD = D <<\n\n";
+        let expected = "    // Adds 1 to R0\n    @R0 // counter\n    M=M+1\n(LOOP)\n\n    \
+                        // Jumps back\n    @LOOP\n    0;JMP\n    AD=D+A\n    M=D&M;JGT\n    D=D<<\n";
+        assert_eq!(format(src).unwrap(), expected);
+        assert_eq!(format(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_directives_and_macros() {
+        let src = ".include \"util.asm\"\n  .macro INC addr\n@addr\n  M=M+1\n .endm\n(main)\n\
+                   INC   R0 // count\n   PUSH\nD\n(.loop)\n@.loop\n@1f\n(1)\n.data 100: 1, 2\n";
+        let expected = ".include \"util.asm\"\n.macro INC addr\n    @addr\n    M=M+1\n.endm\n\
+                        (main)\n    INC   R0 // count\n    PUSH\n    D\n(.loop)\n    @.loop\n    \
+                        @1f\n(1)\n.data 100: 1, 2\n";
+        assert_eq!(format(src).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_strings() {
+        let src = ".string 200: \"http://x\" // url\n  .include \"a//b.asm\"\n";
+        let expected = ".string 200: \"http://x\" // url\n.include \"a//b.asm\"\n";
+        assert_eq!(format(src).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_same_output() {
+        // Formatting only changes how the source looks, never what it assembles to
+        let srcs = [
+            ".define N 3\n.macro ADD a, b // adds\n@a\nD = M\n  @b\nM = D + M\n.endm\n\
+             (main)\n  ADD R0 , R1\n@N\nD=A;JGT\n(.loop)\n@.loop\nD = D - 1 ; JGT\n",
+            ".string 200: \"http://x\" // url\n.data 300: 1, -2 // data\n.fill 400: 20, 7\n\
+             @1f\n0;JMP\n(1)\n@SCREEN + 32 * 2 // row 2\nAM = M - 1\nDA=A+D\nM=M&D;JGT\n",
+        ];
+        for src in srcs.iter() {
+            let formatted = format(src).unwrap();
+            assert_eq!(assemble(&formatted), assemble(src), "{}", formatted);
+        }
+    }
+
+    fn assemble(src: &str) -> String {
+        let source_file = SourceFile::new(src.to_owned(), "main.asm".to_owned());
+        let expanded = preprocess(&source_file, |_| Err(String::new())).unwrap();
+        let instructions = Parser::new(Tokenizer::new(&expanded.src)).parse().unwrap();
+        Codegen::new().generate(&instructions).unwrap()
+    }
+
+    #[test]
+    fn test_errors() {
+        let errors = format("@R0\nD=D+D\nM=\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].span.start, 12);
+
+        let errors = format("@R0\nD=D+D\n").unwrap_err();
+        assert_eq!(errors[0].span.start, 4);
+    }
+}
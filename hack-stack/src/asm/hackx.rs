@@ -0,0 +1,272 @@
+//! The `.hackx` format, which holds a program along with the symbols and source positions that
+//! the plain `.hack` format loses, so emulators and disassemblers can show where each
+//! instruction came from.
+//!
+//! The format is little-endian throughout. Strings are a u32 length followed by that many bytes
+//! of UTF-8.
+//!
+//! ```text
+//! magic      b"HACKX"
+//! version    u16
+//! isa        u8   (0 = hack, 1 = extended)
+//! rom        u32 length, followed by that many u16 words
+//! files      u32 count, followed by that many strings
+//! labels     u32 count, each a string followed by a u16 ROM address
+//! variables  u32 count, each a string followed by a u16 RAM address
+//! lines      u32 count, each a u16 ROM address, u16 file index and u32 line number
+//! ```
+//!
+//! The line table has an entry for each ROM address where the instructions from a new source
+//! line start, sorted by address.
+
+use super::{preprocessor::Expanded, Codegen};
+use crate::common::{InstructionSet, Reader};
+
+const MAGIC: &[u8] = b"HACKX";
+const VERSION: u16 = 1;
+
+/// Returns true if `data` looks like a `.hackx` file rather than a `.hack` one.
+pub fn is_hackx(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Hackx {
+    pub instruction_set: InstructionSet,
+    pub rom: Vec<u16>,
+    /// The names of the source files the program was assembled from.
+    pub files: Vec<String>,
+    pub labels: Vec<(String, u16)>,
+    pub variables: Vec<(String, u16)>,
+    pub lines: Vec<LineEntry>,
+}
+
+/// The first ROM address holding instructions from a line of source.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LineEntry {
+    pub address: u16,
+    /// An index into `Hackx::files`.
+    pub file: u16,
+    pub line: u32,
+}
+
+impl Hackx {
+    /// Returns a program without any symbols or source positions, e.g. from a `.hack` file.
+    pub fn from_rom(rom: Vec<u16>) -> Self {
+        Self {
+            instruction_set: InstructionSet::Hack,
+            rom,
+            files: vec![],
+            labels: vec![],
+            variables: vec![],
+            lines: vec![],
+        }
+    }
+
+    /// Collects the program that `gen` last generated from the `expanded` source.
+    pub fn from_codegen(
+        gen: &Codegen,
+        expanded: &Expanded,
+        instruction_set: InstructionSet,
+    ) -> Self {
+        // The position of each newline in each file, to find line numbers quickly
+        let newlines = expanded
+            .files
+            .iter()
+            .map(|file| {
+                file.src
+                    .match_indices('\n')
+                    .map(|(pos, _)| pos)
+                    .collect::<Vec<usize>>()
+            })
+            .collect::<Vec<Vec<usize>>>();
+
+        let mut lines: Vec<LineEntry> = vec![];
+        for (address, &(_, span)) in gen.words().iter().enumerate() {
+            let (file, span) = expanded.locate_index(span);
+            let line = newlines[file].partition_point(|&pos| pos < span.start) as u32 + 1;
+            if lines
+                .last()
+                .is_some_and(|last| last.file as usize == file && last.line == line)
+            {
+                continue;
+            }
+            lines.push(LineEntry {
+                address: address as u16,
+                file: file as u16,
+                line,
+            });
+        }
+
+        let to_owned = |symbols: &[(&str, u16)]| {
+            symbols
+                .iter()
+                .map(|&(name, addr)| (name.to_owned(), addr))
+                .collect()
+        };
+        Self {
+            instruction_set,
+            rom: gen.words().iter().map(|&(word, _)| word).collect(),
+            files: expanded
+                .files
+                .iter()
+                .map(|file| file.name.clone())
+                .collect(),
+            labels: to_owned(gen.labels()),
+            variables: to_owned(gen.variables()),
+            lines,
+        }
+    }
+
+    /// Returns the file name and line number of the source an instruction was assembled from.
+    pub fn source_line(&self, address: u16) -> Option<(&str, u32)> {
+        let i = self.lines.partition_point(|entry| entry.address <= address);
+        let entry = self.lines.get(i.checked_sub(1)?)?;
+        let file = self.files.get(entry.file as usize)?;
+        Some((file, entry.line))
+    }
+
+    /// Returns the labels that point to a ROM address.
+    pub fn labels_at(&self, address: u16) -> impl Iterator<Item = &str> {
+        self.labels
+            .iter()
+            .filter(move |&&(_, addr)| addr == address)
+            .map(|(name, _)| name.as_str())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(64 + 2 * self.rom.len() + 8 * self.lines.len());
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+        buf.push(match self.instruction_set {
+            InstructionSet::Hack => 0,
+            InstructionSet::Extended => 1,
+        });
+
+        buf.extend_from_slice(&(self.rom.len() as u32).to_le_bytes());
+        for word in &self.rom {
+            buf.extend_from_slice(&word.to_le_bytes());
+        }
+        buf.extend_from_slice(&(self.files.len() as u32).to_le_bytes());
+        for file in &self.files {
+            write_string(&mut buf, file);
+        }
+        for symbols in [&self.labels, &self.variables].iter() {
+            buf.extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+            for (name, addr) in symbols.iter() {
+                write_string(&mut buf, name);
+                buf.extend_from_slice(&addr.to_le_bytes());
+            }
+        }
+        buf.extend_from_slice(&(self.lines.len() as u32).to_le_bytes());
+        for entry in &self.lines {
+            buf.extend_from_slice(&entry.address.to_le_bytes());
+            buf.extend_from_slice(&entry.file.to_le_bytes());
+            buf.extend_from_slice(&entry.line.to_le_bytes());
+        }
+        buf
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader::new(data, ".hackx file");
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err("Not a .hackx file".to_owned());
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(format!("Unsupported .hackx version {}", version));
+        }
+        let instruction_set = match reader.u8()? {
+            0 => InstructionSet::Hack,
+            1 => InstructionSet::Extended,
+            isa => return Err(format!("Invalid instruction set {}", isa)),
+        };
+
+        let rom = reader.words()?;
+        let files = (0..reader.u32()?)
+            .map(|_| reader.string())
+            .collect::<Result<Vec<String>, String>>()?;
+        let mut symbols = || {
+            (0..reader.u32()?)
+                .map(|_| Ok((reader.string()?, reader.u16()?)))
+                .collect::<Result<Vec<(String, u16)>, String>>()
+        };
+        let labels = symbols()?;
+        let variables = symbols()?;
+        let lines = (0..reader.u32()?)
+            .map(|_| {
+                Ok(LineEntry {
+                    address: reader.u16()?,
+                    file: reader.u16()?,
+                    line: reader.u32()?,
+                })
+            })
+            .collect::<Result<Vec<LineEntry>, String>>()?;
+
+        if !reader.is_at_end() {
+            return Err("Unexpected data after the end of the .hackx file".to_owned());
+        }
+        Ok(Self {
+            instruction_set,
+            rom,
+            files,
+            labels,
+            variables,
+            lines,
+        })
+    }
+}
+
+fn write_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        asm::{preprocess, Parser, Tokenizer},
+        common::SourceFile,
+    };
+
+    #[test]
+    fn test_round_trip() {
+        let src = ".include \"lib.asm\"\n(LOOP)\n@x\nINC\n\n@LOOP\n0;JMP\n";
+        let lib = ".macro INC\n    M=M+1\n    M=M+1\n.endm\n";
+        let source_file = SourceFile::new(src.to_owned(), "main.asm".to_owned());
+        let expanded = preprocess(&source_file, |_| Ok(lib.to_owned())).unwrap();
+        let instructions = Parser::new(Tokenizer::new(&expanded.src)).parse().unwrap();
+        let mut gen = Codegen::new();
+        gen.generate(&instructions).unwrap();
+
+        let hackx = Hackx::from_codegen(&gen, &expanded, InstructionSet::Hack);
+        assert_eq!(hackx.rom.len(), 5);
+        assert_eq!(hackx.files, vec!["main.asm", "lib.asm"]);
+        assert_eq!(hackx.labels, vec![("LOOP".to_owned(), 0)]);
+        assert_eq!(hackx.variables, vec![("x".to_owned(), 16)]);
+        assert_eq!(hackx.source_line(0), Some(("main.asm", 3)));
+        assert_eq!(hackx.source_line(1), Some(("lib.asm", 2)));
+        assert_eq!(hackx.source_line(2), Some(("lib.asm", 3)));
+        assert_eq!(hackx.source_line(3), Some(("main.asm", 6)));
+        assert_eq!(hackx.source_line(4), Some(("main.asm", 7)));
+        assert_eq!(hackx.labels_at(0).collect::<Vec<&str>>(), vec!["LOOP"]);
+
+        let bytes = hackx.to_bytes();
+        assert!(is_hackx(&bytes));
+        assert_eq!(Hackx::from_bytes(&bytes).unwrap(), hackx);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let bytes = Hackx::from_rom(vec![1, 2]).to_bytes();
+        assert_eq!(
+            Hackx::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err(),
+            "Unexpected end of .hackx file"
+        );
+        assert_eq!(
+            Hackx::from_bytes(b"0000000000000001\n").unwrap_err(),
+            "Not a .hackx file"
+        );
+    }
+}
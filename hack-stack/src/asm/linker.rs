@@ -0,0 +1,283 @@
+//! Combines separately assembled objects into one program. Objects are placed in ROM in the order
+//! they're given, so the first one should contain the program's entry point.
+//!
+//! A symbol referred to in an object is resolved, in order, to:
+//!
+//! - a label in the same object, or a variable it declares with `.var`
+//! - a predefined symbol, like `SCREEN`
+//! - a label in another object, which is an error if more than one object defines it
+//! - otherwise, a variable shared by every object that refers to it, as if the objects had been
+//!   assembled together
+//!
+//! A symbol that's jumped to must be a label, so one that isn't defined anywhere, like a misspelt
+//! function name, is an error rather than a variable.
+//!
+//! Anonymous labels can only be used by the object they're in. Declared variables are allocated
+//! first, then shared variables in the order they're first used.
+
+use std::collections::HashMap;
+
+use super::{
+    codegen::{a_instruction_value, PREDEFINED_SYMBOLS, ROM_SIZE},
+    object::{Object, Word},
+};
+
+/// A linked program, the ROM address of each label in it, and the RAM address of each variable.
+#[derive(Debug)]
+pub struct LinkedProgram {
+    pub words: Vec<u16>,
+    pub labels: Vec<(String, u16)>,
+    pub variables: Vec<(String, u16)>,
+}
+
+impl LinkedProgram {
+    /// Returns the program in the `.hack` format.
+    pub fn hack(&self) -> String {
+        let mut buf = String::with_capacity(self.words.len() * 17);
+        for word in &self.words {
+            buf.push_str(&format!("{:016b}\n", word));
+        }
+        buf
+    }
+}
+
+/// Links named objects into a program. Errors are prefixed with the name of the object they're
+/// in.
+pub fn link(objects: &[(String, Object)]) -> Result<LinkedProgram, Vec<String>> {
+    let mut bases = Vec::with_capacity(objects.len());
+    let mut size = 0;
+    for (_, object) in objects {
+        bases.push(size as u16);
+        size += object.code.len();
+    }
+    if size > ROM_SIZE {
+        return Err(vec![format!(
+            "the program is {} words, but ROM only holds {}",
+            size, ROM_SIZE
+        )]);
+    }
+
+    let mut labels = vec![];
+    let mut object_labels = vec![];
+    // The objects that define each label other objects can use
+    let mut exports: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, (_, object)) in objects.iter().enumerate() {
+        let mut own = HashMap::new();
+        for (name, addr) in &object.labels {
+            own.insert(name.as_str(), bases[i] + addr);
+            labels.push((name.clone(), bases[i] + addr));
+            if !name.contains('#') {
+                exports.entry(name).or_default().push(i);
+            }
+        }
+        object_labels.push(own);
+    }
+
+    let mut allocator = Allocator {
+        fixed: objects
+            .iter()
+            .flat_map(|(_, object)| object.variables.iter().filter_map(|&(_, addr)| addr))
+            .collect(),
+        next_addr: 0x10,
+        variables: vec![],
+    };
+    let mut declared = vec![HashMap::new(); objects.len()];
+    for (i, (_, object)) in objects.iter().enumerate() {
+        for (name, addr) in &object.variables {
+            if let Some(addr) = *addr {
+                declared[i].insert(name.as_str(), addr);
+                allocator.variables.push((name.clone(), addr));
+            }
+        }
+    }
+    for (i, (_, object)) in objects.iter().enumerate() {
+        for (name, addr) in &object.variables {
+            if addr.is_none() {
+                declared[i].insert(name.as_str(), allocator.allocate(name));
+            }
+        }
+    }
+
+    let mut errors = vec![];
+    let mut shared: HashMap<&str, u16> = HashMap::new();
+    let mut words = Vec::with_capacity(size);
+    for (i, (object_name, object)) in objects.iter().enumerate() {
+        for (k, word) in object.code.iter().enumerate() {
+            // The A-instruction before a jump loads its target
+            let is_jump_target = matches!(
+                object.code.get(k + 1),
+                Some(&Word::Instruction(next)) if next & 0x8000 != 0 && next & 0b111 != 0
+            );
+            let expr = match word {
+                Word::Instruction(word) => {
+                    words.push(*word);
+                    continue;
+                }
+                Word::Reference(expr) => expr,
+            };
+            let value = expr.evaluate(&mut |symbol| {
+                let addr = if let Some(&addr) = object_labels[i].get(symbol) {
+                    addr
+                } else if let Some(&addr) = declared[i].get(symbol) {
+                    addr
+                } else if let Some(&(_, addr)) =
+                    PREDEFINED_SYMBOLS.iter().find(|&&(name, _)| name == symbol)
+                {
+                    addr
+                } else if let Some(definitions) = exports.get(symbol) {
+                    if let [j] = definitions[..] {
+                        object_labels[j][symbol]
+                    } else {
+                        let names = definitions
+                            .iter()
+                            .map(|&j| objects[j].0.as_str())
+                            .collect::<Vec<&str>>();
+                        return Err(format!(
+                            "`{}' is defined in more than one object: {}",
+                            symbol,
+                            names.join(", ")
+                        ));
+                    }
+                } else if is_jump_target {
+                    return Err(format!(
+                        "`{}' is jumped to, but it isn't a label in any object",
+                        symbol
+                    ));
+                } else {
+                    *shared
+                        .entry(symbol)
+                        .or_insert_with(|| allocator.allocate(symbol))
+                };
+                Ok(addr as i64)
+            });
+            match value.and_then(a_instruction_value) {
+                Ok(word) => words.push(word),
+                Err(err) => {
+                    let err = format!("{}: {}", object_name, err);
+                    if !errors.contains(&err) {
+                        errors.push(err);
+                    }
+                    words.push(0);
+                }
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(LinkedProgram {
+        words,
+        labels,
+        variables: allocator.variables,
+    })
+}
+
+struct Allocator {
+    /// Addresses given to variables with `.var`, which other variables are allocated around.
+    fixed: Vec<u16>,
+    next_addr: u16,
+    variables: Vec<(String, u16)>,
+}
+
+impl Allocator {
+    fn allocate(&mut self, name: &str) -> u16 {
+        while self.fixed.contains(&self.next_addr) {
+            self.next_addr += 1;
+        }
+        let addr = self.next_addr;
+        self.next_addr += 1;
+        self.variables.push((name.to_owned(), addr));
+        addr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{Codegen, Parser, Tokenizer};
+
+    fn object(src: &str, variables: &[(&str, Option<u16>)]) -> Object {
+        let instructions = Parser::new(Tokenizer::new(src)).parse().unwrap();
+        let mut gen = Codegen::new();
+        for &(name, addr) in variables {
+            gen.declare_variable(name, addr);
+        }
+        gen.generate_object(&instructions).unwrap()
+    }
+
+    fn link_objects(objects: Vec<Object>) -> Result<LinkedProgram, Vec<String>> {
+        let objects = objects
+            .into_iter()
+            .enumerate()
+            .map(|(i, object)| (format!("{}.hobj", i), object))
+            .collect::<Vec<(String, Object)>>();
+        link(&objects)
+    }
+
+    #[test]
+    fn test_link() {
+        let main = "@f\n0;JMP\n(loop)\n@loop\n0;JMP\n(1)\n@1b\n@count\n@total";
+        let lib = "(f)\n@loop\n@total\n@SCREEN+1\n(loop)\n@1f\n(1)\n@count";
+        let program = link_objects(vec![
+            object(main, &[]),
+            object(lib, &[("count", None), ("buf", Some(16))]),
+        ])
+        .unwrap();
+        assert_eq!(
+            program.words,
+            vec![
+                7,
+                0b1110101010000111,
+                2,
+                0b1110101010000111,
+                4,
+                18,
+                19,
+                10,
+                19,
+                16385,
+                11,
+                17
+            ]
+        );
+        assert_eq!(
+            program.labels,
+            vec![
+                ("loop".to_owned(), 2),
+                ("1#1".to_owned(), 4),
+                ("f".to_owned(), 7),
+                ("loop".to_owned(), 10),
+                ("1#1".to_owned(), 11)
+            ]
+        );
+        assert_eq!(
+            program.variables,
+            vec![
+                ("buf".to_owned(), 16),
+                ("count".to_owned(), 17),
+                ("count".to_owned(), 18),
+                ("total".to_owned(), 19)
+            ]
+        );
+        assert_eq!(program.hack().lines().next(), Some("0000000000000111"));
+    }
+
+    #[test]
+    fn test_link_errors() {
+        let errors = link_objects(vec![
+            object("@f\n@f\n@32767+x\n@Sys.iint\n0;JMP\n@Sys.iint\nD;JGT", &[]),
+            object("(f)", &[]),
+            object("(f)", &[]),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                "0.hobj: `f' is defined in more than one object: 1.hobj, 2.hobj",
+                "0.hobj: value 32783 doesn't fit in an A-instruction, which takes 0-32767",
+                "0.hobj: `Sys.iint' is jumped to, but it isn't a label in any object",
+            ]
+        );
+    }
+}
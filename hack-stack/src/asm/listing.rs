@@ -0,0 +1,261 @@
+//! Assembly listings, which show each line of source next to the address and encoding of the
+//! instructions assembled from it, followed by the symbol table. Lines are those of the original
+//! files, found through the preprocessor's map, so the code a macro or data directive expands to
+//! is listed next to it, and an included file's lines follow a line naming the file.
+//!
+//! ```text
+//!     0  0000000000000010  0002      1  @2
+//!     1  1110110000010000  EC10      2  D=A
+//!     2                              3  (LOOP)
+//!     2  0000000000000010  0002      4  @LOOP
+//! ```
+
+use std::fmt::Write;
+
+use super::{preprocessor::Expanded, Codegen};
+
+/// Formats a listing of the program that `gen` last generated from the `expanded` source.
+/// `optimized` notes that the code was optimized, so it may not match the source line for line.
+pub fn listing(expanded: &Expanded, gen: &Codegen, optimized: bool) -> String {
+    let mut out = String::new();
+    if optimized {
+        out.push_str("Optimized: instructions may have moved or been removed, so they may not\n");
+        out.push_str("match the source they're listed next to.\n\n");
+    }
+
+    let files = expanded
+        .files
+        .iter()
+        .map(|file| {
+            let mut lines = file.src.split('\n').collect::<Vec<&str>>();
+            if lines.last() == Some(&"") {
+                lines.pop();
+            }
+            lines
+        })
+        .collect::<Vec<Vec<&str>>>();
+    // The position of each newline in each file, to find line numbers quickly
+    let newlines = expanded
+        .files
+        .iter()
+        .map(|file| {
+            file.src
+                .match_indices('\n')
+                .map(|(pos, _)| pos)
+                .collect::<Vec<usize>>()
+        })
+        .collect::<Vec<Vec<usize>>>();
+
+    // The end of each line of the expanded source, and the file and line number it came from
+    let mut lines = vec![];
+    let mut line_start = 0;
+    for (i, line) in expanded.src.split('\n').enumerate() {
+        let line_end = line_start + line.len();
+        let (file, pos) = expanded.origin(i);
+        let line_number = newlines[file].partition_point(|&nl| nl < pos) + 1;
+        lines.push((line_end, file, line_number));
+        line_start = line_end + 1;
+    }
+    if expanded.src.ends_with('\n') {
+        lines.pop();
+    }
+
+    let mut listing = Listing {
+        out: &mut out,
+        files: &files,
+        names: expanded.files.iter().map(|f| f.name.as_str()).collect(),
+        next_line: vec![1; files.len()],
+        last: None,
+    };
+    let words = gen.words();
+    let mut next_word = 0;
+    for (i, &(line_end, file, line)) in lines.iter().enumerate() {
+        // Lines the preprocessor removed, like macro definitions and includes, are listed when
+        // the lines after them are. When the source moves into an included file, that includes
+        // the line which included it.
+        let last_file = listing.last.map_or(0, |(last_file, _)| last_file);
+        if last_file != file {
+            let resume = lines[i + 1..]
+                .iter()
+                .find(|&&(_, f, l)| f == last_file && l >= listing.next_line[last_file]);
+            if let Some(&(_, _, resume_line)) = resume {
+                listing.skip_to(last_file, resume_line);
+            }
+        }
+        listing.skip_to(file, line);
+
+        let mut listed = false;
+        while let Some(&(word, span)) = words.get(next_word) {
+            if span.start > line_end {
+                break;
+            }
+            let columns = format!("{:5}  {:016b}  {:04X}", next_word, word, word);
+            listing.line(&columns, file, line, !listed);
+            next_word += 1;
+            listed = true;
+        }
+        // Lines without code are only listed once, and the end of a file isn't a line
+        let text = files[file].get(line - 1);
+        if let (false, Some(text)) = (listed || listing.last == Some((file, line)), text) {
+            // A label's address is the address of the instruction after it
+            let columns = if text.trim_start().starts_with('(') {
+                format!("{:5}", next_word)
+            } else {
+                String::new()
+            };
+            listing.line(&columns, file, line, true);
+        }
+    }
+    listing.skip_to(0, files[0].len() + 1);
+
+    out.push('\n');
+    out.push_str(&symbol_table(gen));
+    out
+}
+
+struct Listing<'a> {
+    out: &'a mut String,
+    files: &'a [Vec<&'a str>],
+    names: Vec<&'a str>,
+    // The line after the last one listed from each file
+    next_line: Vec<usize>,
+    // The file and line last listed
+    last: Option<(usize, usize)>,
+}
+
+impl Listing<'_> {
+    // Lists line `line` of `file` after `columns`, with its text unless it was just listed
+    fn line(&mut self, columns: &str, file: usize, line: usize, with_text: bool) {
+        if self.last.is_some_and(|(last_file, _)| last_file != file)
+            || (self.last.is_none() && file != 0)
+        {
+            writeln!(self.out, "{:31}{:5}  {}:", "", "", self.names[file]).unwrap();
+        }
+        let repeated = self.last == Some((file, line));
+        let text = self.files[file].get(line - 1).copied().unwrap_or("");
+        let text = text.trim_end_matches('\r');
+        let entry = if with_text && !repeated {
+            format!("{:31}{:5}  {}", columns, line, text)
+        } else {
+            format!("{:31}", columns)
+        };
+        self.out.push_str(entry.trim_end());
+        self.out.push('\n');
+        self.last = Some((file, line));
+        self.next_line[file] = self.next_line[file].max(line + 1);
+    }
+
+    // Lists the lines of `file` that haven't been listed, up to `line`
+    fn skip_to(&mut self, file: usize, line: usize) {
+        let end = line.min(self.files[file].len() + 1);
+        for skipped in self.next_line[file]..end {
+            self.line("", file, skipped, true);
+        }
+    }
+}
+
+/// Formats the address of every label and variable in the program `gen` last generated, sorted
+/// by address, which shows where each variable was allocated.
+pub fn symbol_table(gen: &Codegen) -> String {
+    let mut out = String::new();
+    let mut labels = gen.labels().to_vec();
+    labels.sort_by_key(|&(_, addr)| addr);
+    let mut variables = gen.variables().to_vec();
+    variables.sort_by_key(|&(_, addr)| addr);
+    let symbols = labels
+        .iter()
+        .map(|&(name, addr)| (name, addr, "label"))
+        .chain(
+            variables
+                .iter()
+                .map(|&(name, addr)| (name, addr, "variable")),
+        )
+        .collect::<Vec<(&str, u16, &str)>>();
+    let width = symbols
+        .iter()
+        .map(|(name, _, _)| name.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    out.push_str("Symbol table:\n");
+    for (name, addr, kind) in symbols {
+        writeln!(
+            out,
+            "  {:width$}  {:5}  {}",
+            name,
+            addr,
+            kind,
+            width = width
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        asm::{preprocess, Parser, Tokenizer},
+        common::SourceFile,
+    };
+
+    fn list(src: &str, lib: &str) -> String {
+        let source_file = SourceFile::new(src.to_owned(), "main.asm".to_owned());
+        let expanded = preprocess(&source_file, |_| Ok(lib.to_owned())).unwrap();
+        let instructions = Parser::new(Tokenizer::new(&expanded.src)).parse().unwrap();
+        let mut gen = Codegen::new();
+        gen.generate(&instructions).unwrap();
+        listing(&expanded, &gen, false)
+    }
+
+    #[test]
+    fn test_listing() {
+        let src =
+            "// Count down\n\n@10\nD=A\n(LOOP)\n  D=D-1 // decrement\n@count\nM=D\n@LOOP\nD;JGT\n";
+        let expected = [
+            "                                   1  // Count down",
+            "                                   2",
+            "    0  0000000000001010  000A      3  @10",
+            "    1  1110110000010000  EC10      4  D=A",
+            "    2                              5  (LOOP)",
+            "    2  1110001110010000  E390      6    D=D-1 // decrement",
+            "    3  0000000000010000  0010      7  @count",
+            "    4  1110001100001000  E308      8  M=D",
+            "    5  0000000000000010  0002      9  @LOOP",
+            "    6  1110001100000001  E301     10  D;JGT",
+            "",
+            "Symbol table:",
+            "  LOOP       2  label",
+            "  count     16  variable",
+            "",
+        ];
+        assert_eq!(list(src, ""), expected.join("\n"));
+    }
+
+    #[test]
+    fn test_original_lines() {
+        let src = ".macro INC x\n  @x\n  M=M+1\n.endm\n.include \"lib.asm\"\nINC n\n.data 100: 7\n";
+        let expected = [
+            "                                   1  .macro INC x",
+            "                                   2    @x",
+            "                                   3    M=M+1",
+            "                                   4  .endm",
+            "                                   5  .include \"lib.asm\"",
+            "                                      lib.asm:",
+            "    0  0000000000000001  0001      1  @1",
+            "                                      main.asm:",
+            "    1  0000000000010000  0010      6  INC n",
+            "    2  1111110111001000  FDC8",
+            "    3  0000000000000111  0007      7  .data 100: 7",
+            "    4  1110110000010000  EC10",
+            "    5  0000000001100100  0064",
+            "    6  1110001100001000  E308",
+            "",
+            "Symbol table:",
+            "  n     16  variable",
+            "",
+        ];
+        assert_eq!(list(src, "@1\n"), expected.join("\n"));
+    }
+}
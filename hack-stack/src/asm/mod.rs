@@ -1,9 +1,28 @@
+pub mod analysis;
 pub mod ast;
 pub mod codegen;
+pub mod data;
+pub mod diagnostics;
+pub mod disassembler;
+pub mod formatter;
+pub mod hackx;
+pub mod linker;
+pub mod listing;
+pub mod object;
+pub mod optimize;
 pub mod parser;
+pub mod preprocessor;
 pub mod tokenizer;
 mod tokens;
 
+pub use analysis::analyze;
 pub use codegen::Codegen;
+pub use diagnostics::{check, check_object};
+pub use disassembler::{disassemble, disassemble_for, disassemble_program};
+pub use formatter::format;
+pub use linker::link;
+pub use listing::{listing, symbol_table};
+pub use optimize::optimize;
 pub use parser::Parser;
+pub use preprocessor::preprocess;
 pub use tokenizer::Tokenizer;
@@ -0,0 +1,218 @@
+//! Object files, which hold part of a program assembled on its own, to be combined with others
+//! by `link`. An object's code can be placed anywhere in ROM, so A-instructions that refer to
+//! labels or variables are kept as expressions until the program is linked.
+//!
+//! Objects are text, one item per line. Labels are relative to the start of the object, and
+//! references are written in postfix, with `~` for negation:
+//!
+//! ```text
+//! hack-object 1
+//! label LOOP 0
+//! var count
+//! var buffer 4096
+//! ref count
+//! word 1111110000010000
+//! ref LOOP 2 +
+//! word 1110101010000111
+//! ```
+
+use std::{borrow::Cow, fmt};
+
+use super::ast::{Expression, ExpressionOperator};
+
+const HEADER: &str = "hack-object 1";
+
+#[derive(Debug, PartialEq)]
+pub struct Object {
+    /// Every label in the object, and its address relative to the start of the object.
+    pub labels: Vec<(String, u16)>,
+    /// Variables declared with `.var`, which belong to this object, and their address if one
+    /// was given.
+    pub variables: Vec<(String, Option<u16>)>,
+    pub code: Vec<Word>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Word {
+    /// An instruction that doesn't depend on where anything is placed.
+    Instruction(u16),
+    /// An A-instruction whose value is only known once the program is linked.
+    Reference(Expression<'static>),
+}
+
+impl Object {
+    pub fn parse(src: &str) -> Result<Object, String> {
+        let mut lines = src.lines().enumerate();
+        if lines.next().map(|(_, line)| line.trim_end()) != Some(HEADER) {
+            return Err("not an object file".to_owned());
+        }
+
+        let mut object = Object {
+            labels: vec![],
+            variables: vec![],
+            code: vec![],
+        };
+        for (i, line) in lines {
+            let mut fields = line.split_whitespace();
+            let result = match (fields.next(), fields.next(), fields.next()) {
+                (None, _, _) => continue,
+                (Some("label"), Some(name), Some(addr)) => {
+                    parse_u16(addr).map(|addr| object.labels.push((name.to_owned(), addr)))
+                }
+                (Some("var"), Some(name), addr) => match addr.map(parse_u16).transpose() {
+                    Ok(addr) => {
+                        object.variables.push((name.to_owned(), addr));
+                        Ok(())
+                    }
+                    Err(err) => Err(err),
+                },
+                (Some("word"), Some(word), None) => u16::from_str_radix(word, 2)
+                    .map(|word| object.code.push(Word::Instruction(word)))
+                    .map_err(|_| format!("invalid word `{}'", word)),
+                (Some("ref"), Some(_), _) => parse_postfix(&line.trim()[3..])
+                    .map(|expr| object.code.push(Word::Reference(expr))),
+                _ => Err(format!("invalid line `{}'", line.trim())),
+            };
+            result.map_err(|err| format!("line {}: {}", i + 1, err))?;
+        }
+        Ok(object)
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        for (name, addr) in &self.labels {
+            writeln!(f, "label {} {}", name, addr)?;
+        }
+        for (name, addr) in &self.variables {
+            match addr {
+                Some(addr) => writeln!(f, "var {} {}", name, addr)?,
+                None => writeln!(f, "var {}", name)?,
+            }
+        }
+        for word in &self.code {
+            match word {
+                Word::Instruction(word) => writeln!(f, "word {:016b}", word)?,
+                Word::Reference(expr) => {
+                    f.write_str("ref")?;
+                    write_postfix(f, expr)?;
+                    f.write_str("\n")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_postfix(f: &mut fmt::Formatter<'_>, expr: &Expression) -> fmt::Result {
+    match expr {
+        Expression::Value(n) => write!(f, " {}", n),
+        Expression::Symbol(s) => write!(f, " {}", s),
+        Expression::Negate(expr) => {
+            write_postfix(f, expr)?;
+            f.write_str(" ~")
+        }
+        Expression::Binary(lhs, op, rhs) => {
+            write_postfix(f, lhs)?;
+            write_postfix(f, rhs)?;
+            f.write_str(match op {
+                ExpressionOperator::Plus => " +",
+                ExpressionOperator::Minus => " -",
+                ExpressionOperator::Times => " *",
+            })
+        }
+    }
+}
+
+fn parse_postfix(src: &str) -> Result<Expression<'static>, String> {
+    let mut stack = vec![];
+    for field in src.split_whitespace() {
+        let op = match field {
+            "+" => ExpressionOperator::Plus,
+            "-" => ExpressionOperator::Minus,
+            "*" => ExpressionOperator::Times,
+            "~" => {
+                let expr = stack.pop().ok_or("`~' is missing its operand")?;
+                stack.push(Expression::Negate(Box::new(expr)));
+                continue;
+            }
+            _ => {
+                // Symbols can't start with a digit, apart from anonymous labels like `1#2`
+                stack.push(match field.parse::<u16>() {
+                    Ok(n) => Expression::Value(n),
+                    Err(_) => Expression::Symbol(Cow::Owned(field.to_owned())),
+                });
+                continue;
+            }
+        };
+        let (rhs, lhs) = match (stack.pop(), stack.pop()) {
+            (Some(rhs), Some(lhs)) => (rhs, lhs),
+            _ => return Err(format!("`{}' is missing an operand", field)),
+        };
+        stack.push(Expression::Binary(Box::new(lhs), op, Box::new(rhs)));
+    }
+    match (stack.pop(), stack.is_empty()) {
+        (Some(expr), true) => Ok(expr),
+        _ => Err("invalid reference".to_owned()),
+    }
+}
+
+fn parse_u16(s: &str) -> Result<u16, String> {
+    s.parse().map_err(|_| format!("invalid address `{}'", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{Codegen, Parser, Tokenizer};
+
+    #[test]
+    fn test_generate_object() {
+        let src = "(start)\n@count\nM=M+1\n@SCREEN+32\n@.end-1\n@-(KBD-1)*2+Main.0\n\
+                   (.end)\n@1f\n(1)\n";
+        let instructions = Parser::new(Tokenizer::new(src)).parse().unwrap();
+        let mut gen = Codegen::new();
+        gen.declare_variable("count", None);
+        gen.declare_variable("buffer", Some(4096));
+        let object = gen.generate_object(&instructions).unwrap();
+
+        let text = object.to_string();
+        assert_eq!(
+            text,
+            "hack-object 1\n\
+             label start 0\n\
+             label start.end 5\n\
+             label 1#1 6\n\
+             var count\n\
+             var buffer 4096\n\
+             ref count\n\
+             word 1111110111001000\n\
+             word 0100000000100000\n\
+             ref start.end 1 -\n\
+             ref KBD 1 - ~ 2 * Main.0 +\n\
+             ref 1#1\n"
+        );
+        assert_eq!(Object::parse(&text).unwrap(), object);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Object::parse("0000000000000001\n").unwrap_err(),
+            "not an object file"
+        );
+        assert_eq!(
+            Object::parse("hack-object 1\nword 2\n").unwrap_err(),
+            "line 2: invalid word `2'"
+        );
+        assert_eq!(
+            Object::parse("hack-object 1\n\nref a +\n").unwrap_err(),
+            "line 3: `+' is missing an operand"
+        );
+        assert_eq!(
+            Object::parse("hack-object 1\nlabel a\n").unwrap_err(),
+            "line 2: invalid line `label a'"
+        );
+    }
+}
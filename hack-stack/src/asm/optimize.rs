@@ -0,0 +1,402 @@
+//! A peephole optimizer that removes and simplifies instructions without changing what a program
+//! does. It only looks at straight-line code between labels, so it makes no assumptions about how
+//! control reaches a label.
+//!
+//! Removing unreachable code can change the order in which variables are first used, and so the
+//! addresses they're allocated. Programs that refer to variables by name aren't affected.
+
+use std::collections::{HashMap, HashSet};
+
+use super::ast::{
+    AInstruction, Address, BinaryOperation, Bit, CInstruction, Comp, Dest, Instruction, Jump,
+    Operand, Register, UnaryOperation,
+};
+
+/// Optimizes a program until none of the optimizations apply:
+///
+/// - Code after an unconditional jump is removed, up to the next label.
+/// - Jumps to a label that's immediately followed by another jump go straight to the final
+///   target.
+/// - `@0` or `@1` followed by e.g. `D=A` becomes `D=0` or `D=1`, if A is overwritten next.
+/// - Assignments to D are removed if D is overwritten before it's read.
+/// - A-instructions that load the value A already holds are removed.
+///
+/// Programs that depend on where code is placed are returned unchanged, as moving their code would
+/// break them. See `uses_fixed_addresses`.
+pub fn optimize(mut instructions: Vec<Instruction>) -> Vec<Instruction> {
+    if uses_fixed_addresses(&instructions) {
+        return instructions;
+    }
+    loop {
+        let len = instructions.len();
+        instructions = remove_unreachable(instructions);
+        instructions = thread_jumps(instructions);
+        instructions = fold_constants(instructions);
+        instructions = remove_dead_d_stores(instructions);
+        instructions = remove_redundant_loads(instructions);
+        if instructions.len() == len {
+            return instructions;
+        }
+    }
+}
+
+/// Returns the number of words a program assembles to.
+pub fn count_words(instructions: &[Instruction]) -> usize {
+    instructions
+        .iter()
+        .filter(|inst| !matches!(inst, Instruction::Label(_)))
+        .count()
+}
+
+/// Returns true if the program jumps to a numeric ROM address, like `@7` followed by `0;JMP`, or
+/// does arithmetic with a label, like `@LOOP+4`. Either would point somewhere else once
+/// instructions are removed.
+pub fn uses_fixed_addresses(instructions: &[Instruction]) -> bool {
+    let labels = instructions
+        .iter()
+        .filter_map(|inst| match inst {
+            Instruction::Label(label) => Some(&*label.name),
+            _ => None,
+        })
+        .collect::<HashSet<&str>>();
+
+    // Whether A was last loaded with a number
+    let mut numeric_a = false;
+    for inst in instructions {
+        match inst {
+            Instruction::Label(_) => numeric_a = false,
+            Instruction::A(a) => {
+                numeric_a = match &a.addr {
+                    Address::Value(_) => true,
+                    Address::Symbol(_) => false,
+                    Address::Expression(expr) => {
+                        let mut uses_label = false;
+                        let _ = expr.evaluate(&mut |symbol| {
+                            uses_label |= labels.contains(symbol);
+                            Ok(0)
+                        });
+                        if uses_label {
+                            return true;
+                        }
+                        true
+                    }
+                };
+            }
+            Instruction::C(c) => {
+                if c.jump.is_some() && numeric_a {
+                    return true;
+                }
+                if c.dest.as_ref().is_some_and(|dest| dest.a) {
+                    numeric_a = false;
+                }
+            }
+        }
+    }
+    false
+}
+
+fn remove_unreachable(instructions: Vec<Instruction>) -> Vec<Instruction> {
+    let mut reachable = true;
+    let mut out = Vec::with_capacity(instructions.len());
+    for inst in instructions {
+        match &inst {
+            Instruction::Label(_) => reachable = true,
+            _ if !reachable => continue,
+            Instruction::C(c) if c.jump == Some(Jump::JMP) => reachable = false,
+            _ => {}
+        }
+        out.push(inst);
+    }
+    out
+}
+
+fn thread_jumps(mut instructions: Vec<Instruction>) -> Vec<Instruction> {
+    // Labels that are immediately followed by `@TARGET` and `0;JMP`
+    let mut forwards = HashMap::new();
+    for (i, inst) in instructions.iter().enumerate() {
+        if let Instruction::Label(label) = inst {
+            let mut rest = instructions[i + 1..]
+                .iter()
+                .filter(|inst| !matches!(inst, Instruction::Label(_)));
+            if let (Some(Instruction::A(a)), Some(Instruction::C(c))) = (rest.next(), rest.next()) {
+                if let Address::Symbol(target) = &a.addr {
+                    if is_plain_jump(c) && c.jump == Some(Jump::JMP) && *target != label.name {
+                        forwards.insert(label.name.clone(), target.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    for i in 0..instructions.len().saturating_sub(1) {
+        let target = match (&instructions[i], &instructions[i + 1]) {
+            (Instruction::A(a), Instruction::C(c)) if is_plain_jump(c) => match &a.addr {
+                Address::Symbol(label) => label.clone(),
+                _ => continue,
+            },
+            _ => continue,
+        };
+        // A conditional jump falls through with the target still in A, so it can only be changed
+        // if A is reloaded straight away
+        let unconditional =
+            matches!(&instructions[i + 1], Instruction::C(c) if c.jump == Some(Jump::JMP));
+        if !unconditional && !matches!(instructions.get(i + 2), Some(Instruction::A(_))) {
+            continue;
+        }
+
+        let mut final_target = &target;
+        let mut hops = 0;
+        while let Some(next) = forwards.get(final_target) {
+            final_target = next;
+            hops += 1;
+            // Jumps that go round in a circle never get anywhere, so leave them alone
+            if hops > forwards.len() {
+                final_target = &target;
+                break;
+            }
+        }
+        if let Instruction::A(a) = &mut instructions[i] {
+            a.addr = Address::Symbol(final_target.clone());
+        }
+    }
+    instructions
+}
+
+// A jump that doesn't read A or M, or store anything, so it does nothing but jump
+fn is_plain_jump(c: &CInstruction) -> bool {
+    let (reads_a, reads_m) = (reads(&c.comp, Register::A), reads(&c.comp, Register::M));
+    c.jump.is_some() && c.dest.is_none() && !reads_a && !reads_m
+}
+
+fn fold_constants(instructions: Vec<Instruction>) -> Vec<Instruction> {
+    let mut out = Vec::with_capacity(instructions.len());
+    let mut iter = instructions.into_iter().peekable();
+    while let Some(inst) = iter.next() {
+        let bit = match &inst {
+            Instruction::A(AInstruction {
+                addr: Address::Value(0),
+                ..
+            }) => Bit::Zero,
+            Instruction::A(AInstruction {
+                addr: Address::Value(1),
+                ..
+            }) => Bit::One,
+            _ => {
+                out.push(inst);
+                continue;
+            }
+        };
+        let foldable = match iter.peek() {
+            Some(Instruction::C(CInstruction {
+                dest: Some(dest),
+                comp: Comp::Register(Register::A),
+                jump: None,
+                ..
+            })) => !dest.m,
+            _ => false,
+        };
+        if !foldable {
+            out.push(inst);
+            continue;
+        }
+
+        let c = match iter.next() {
+            Some(Instruction::C(c)) => c,
+            _ => unreachable!(),
+        };
+        let dest = c.dest.unwrap();
+        // Without the A-instruction, A keeps its old value, which is fine as long as nothing reads
+        // it before it's next set
+        if !dest.a && !matches!(iter.peek(), Some(Instruction::A(_))) {
+            out.push(inst);
+            out.push(Instruction::C(CInstruction {
+                dest: Some(dest),
+                ..c
+            }));
+            continue;
+        }
+        out.push(Instruction::C(CInstruction {
+            dest: Some(dest),
+            comp: Comp::Bit(bit),
+            jump: None,
+            span: inst.span().merge(&c.span),
+        }));
+    }
+    out
+}
+
+fn remove_dead_d_stores(instructions: Vec<Instruction>) -> Vec<Instruction> {
+    let dead = (0..instructions.len())
+        .map(|i| is_dead_d_store(&instructions, i))
+        .collect::<Vec<bool>>();
+    instructions
+        .into_iter()
+        .zip(dead)
+        .filter(|(_, dead)| !dead)
+        .map(|(inst, _)| inst)
+        .collect()
+}
+
+fn is_dead_d_store(instructions: &[Instruction], i: usize) -> bool {
+    let only_d = Dest {
+        a: false,
+        d: true,
+        m: false,
+    };
+    match &instructions[i] {
+        Instruction::C(c) if c.dest.as_ref() == Some(&only_d) && c.jump.is_none() => {}
+        _ => return false,
+    }
+    for inst in &instructions[i + 1..] {
+        match inst {
+            Instruction::A(_) => {}
+            // D might be read wherever control goes next
+            Instruction::Label(_) => return false,
+            Instruction::C(c) => {
+                if reads(&c.comp, Register::D) || c.jump.is_some() {
+                    return false;
+                }
+                if c.dest.as_ref().is_some_and(|dest| dest.d) {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+fn remove_redundant_loads(instructions: Vec<Instruction>) -> Vec<Instruction> {
+    // The value in A, if it was loaded by an A-instruction in the same block
+    let mut loaded: Option<Address> = None;
+    let mut out = Vec::with_capacity(instructions.len());
+    for inst in instructions {
+        match &inst {
+            Instruction::Label(_) => loaded = None,
+            Instruction::A(a) => {
+                let known = match a.addr {
+                    Address::Value(_) | Address::Symbol(_) => Some(a.addr.clone()),
+                    Address::Expression(_) => None,
+                };
+                if known.is_some() && known == loaded {
+                    continue;
+                }
+                loaded = known;
+            }
+            Instruction::C(c) => {
+                if c.dest.as_ref().is_some_and(|dest| dest.a) {
+                    loaded = None;
+                }
+            }
+        }
+        out.push(inst);
+    }
+    out
+}
+
+fn reads(comp: &Comp, register: Register) -> bool {
+    let operand_reads =
+        |operand: &Operand| matches!(operand, Operand::Register(r) if *r == register);
+    match comp {
+        Comp::Bit(_) => false,
+        Comp::Register(r) => *r == register,
+        Comp::UnaryOperation(UnaryOperation { operand, .. }) => operand_reads(operand),
+        Comp::BinaryOperation(BinaryOperation { lhs, rhs, .. }) => {
+            *lhs == register || operand_reads(rhs)
+        }
+        Comp::Shift(shift) => shift.register == register,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{Codegen, Parser, Tokenizer};
+
+    fn optimize_src(src: &str) -> String {
+        let instructions = Parser::new(Tokenizer::new(src)).parse().unwrap();
+        let optimized = optimize(instructions);
+        let expected = Parser::new(Tokenizer::new(src)).parse().unwrap();
+        assert!(count_words(&optimized) <= count_words(&expected));
+        Codegen::new().generate(&optimized).unwrap()
+    }
+
+    fn assemble(src: &str) -> String {
+        let instructions = Parser::new(Tokenizer::new(src)).parse().unwrap();
+        Codegen::new().generate(&instructions).unwrap()
+    }
+
+    #[test]
+    fn test_redundant_loads() {
+        assert_eq!(
+            optimize_src("@SP\nM=M+1\n@SP\nA=M\n@SP\n(L)\n@SP\n"),
+            assemble("@SP\nM=M+1\nA=M\n@SP\n(L)\n@SP\n")
+        );
+    }
+
+    #[test]
+    fn test_unreachable_code() {
+        assert_eq!(
+            optimize_src("@END\n0;JMP\nD=M\n@5\n(END)\n@END\nD;JGT\nM=1\n"),
+            assemble("@END\n0;JMP\n(END)\n@END\nD;JGT\nM=1\n")
+        );
+    }
+
+    #[test]
+    fn test_jump_threading() {
+        assert_eq!(
+            optimize_src(
+                "@A\n0;JMP\n(A)\n(B)\n@C\n0;JMP\n(C)\n@A\nD;JGT\n@A\nD;JLT\nM=1\n(LOOP)\n@LOOP\n0;JMP\n"
+            ),
+            assemble(
+                "@C\n0;JMP\n(A)\n(B)\n@C\n0;JMP\n(C)\n@C\nD;JGT\n@A\nD;JLT\nM=1\n(LOOP)\n@LOOP\n0;JMP\n"
+            )
+        );
+        // Jumps that read A or M aren't changed, since the value they read would change
+        assert_eq!(
+            optimize_src("@A\nM;JMP\n(A)\n@B\n0;JMP\n(B)\n@A\n"),
+            assemble("@A\nM;JMP\n(A)\n@B\n0;JMP\n(B)\n@A\n")
+        );
+    }
+
+    #[test]
+    fn test_jumps_that_store() {
+        // Skipping the jump at B would skip setting D
+        let src = "@A\n0;JMP\n(A)\n@B\nD=0;JMP\n(B)\nM=D\n";
+        assert_eq!(optimize_src(src), assemble(src));
+    }
+
+    #[test]
+    fn test_fixed_addresses() {
+        // Removing code would move the instruction these jump to
+        for src in [
+            "(S)\n@S+4\n0;JMP\nD=M\nD=D+1\n@7\nM=D\n",
+            "@4\n0;JMP\nD=M\nD=D+1\n@7\nM=D\n",
+            "@3\nD;JGT\n@SP\n@SP\nM=D\n",
+        ]
+        .iter()
+        {
+            assert_eq!(optimize_src(src), assemble(src));
+        }
+        // Expressions that don't use labels are fine
+        assert_eq!(
+            optimize_src("@SCREEN+1\nM=0\n@END\n0;JMP\nD=M\n(END)\n"),
+            assemble("@SCREEN+1\nM=0\n@END\n0;JMP\n(END)\n")
+        );
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        assert_eq!(
+            optimize_src("@0\nD=A\n@SP\nM=D\n@1\nAD=A\nM=D\n@1\nD=A\nM=D\n"),
+            assemble("D=0\n@SP\nM=D\nAD=1\nM=D\n@1\nD=A\nM=D\n")
+        );
+    }
+
+    #[test]
+    fn test_dead_d_stores() {
+        assert_eq!(
+            optimize_src("D=M\n@5\nD=A\nM=D\nD=M\n(L)\nD=D+1\nD=1\nD;JGT\n"),
+            assemble("@5\nD=A\nM=D\nD=M\n(L)\nD=1\nD;JGT\n")
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use std::convert::TryFrom;
+use std::{borrow::Cow, collections::HashMap, convert::TryFrom};
 
 use super::ast;
 use super::tokenizer::Tokenizer;
@@ -12,6 +12,13 @@ pub struct Parser<'a> {
     token: Token<'a>,
     prev_token: Token<'a>,
     peeked_token: Option<Token<'a>>,
+    /// The last global label, which local labels like `.loop` belong to.
+    scope: Option<&'a str>,
+    /// How many times each anonymous label, e.g. `(1)`, has been defined so far.
+    anonymous_labels: HashMap<&'a str, usize>,
+    /// References to anonymous labels that haven't been defined yet, and the definition they
+    /// refer to.
+    forward_refs: Vec<(&'a str, usize, Span)>,
 }
 
 impl<'a> Parser<'a> {
@@ -22,10 +29,24 @@ impl<'a> Parser<'a> {
             token,
             prev_token: Token::invalid('\0', 0),
             peeked_token: None,
+            scope: None,
+            anonymous_labels: HashMap::new(),
+            forward_refs: vec![],
         }
     }
 
     pub fn parse(&mut self) -> Result<Vec<ast::Instruction<'a>>, Vec<SpanError>> {
+        let (instructions, errors) = self.parse_with_errors();
+        if errors.is_empty() {
+            Ok(instructions)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parses as much of the program as possible, returning the instructions that parsed along
+    /// with errors for the lines that didn't, so later passes can still check the rest.
+    pub fn parse_with_errors(&mut self) -> (Vec<ast::Instruction<'a>>, Vec<SpanError>) {
         let mut instructions = vec![];
 
         let mut errors = vec![];
@@ -43,11 +64,16 @@ impl<'a> Parser<'a> {
             }
         }
 
-        if errors.is_empty() {
-            Ok(instructions)
-        } else {
-            Err(errors)
+        for &(label, n, span) in &self.forward_refs {
+            if self.anonymous_labels.get(label).copied().unwrap_or(0) < n {
+                errors.push(self.span_error(
+                    format!("there's no anonymous label `({})' after this", label),
+                    span,
+                ));
+            }
         }
+        errors.sort_by_key(|err| err.span.start);
+        (instructions, errors)
     }
 
     fn parse_instruction(&mut self) -> ParseResult<Option<ast::Instruction<'a>>> {
@@ -70,29 +96,65 @@ impl<'a> Parser<'a> {
         let start = self.token.span.start;
         self.expect(Kind::LParen)?;
 
-        if let Kind::Identifier(name) = self.token.kind {
-            self.advance();
+        let name = match self.token.kind {
+            Kind::Identifier(name) if name.starts_with('.') => self.resolve_symbol(name),
+            Kind::Identifier(name) => {
+                self.scope = Some(name);
+                Cow::Borrowed(name)
+            }
+            Kind::Number(label) if label.len() == 1 => {
+                let n = self.anonymous_labels.entry(label).or_insert(0);
+                *n += 1;
+                Cow::Owned(format!("{}#{}", label, n))
+            }
+            _ => return Err(self.unexpected_token_error("label name")),
+        };
+        self.advance();
 
-            let span = Span::new(start, self.token.span.end);
-            let label = ast::Instruction::Label(ast::Label { name, span });
+        let span = Span::new(start, self.token.span.end);
+        let label = ast::Instruction::Label(ast::Label { name, span });
 
-            self.expect(Kind::RParen)?;
-            self.eat_terminator()?;
+        self.expect(Kind::RParen)?;
+        self.eat_terminator()?;
 
-            Ok(label)
-        } else {
-            Err(self.unexpected_token_error("label name"))
+        Ok(label)
+    }
+
+    /// Returns the full name of a symbol, which for a local label like `.loop` includes the
+    /// global label before it.
+    fn resolve_symbol(&self, name: &'a str) -> Cow<'a, str> {
+        match self.scope {
+            Some(scope) if name.starts_with('.') => Cow::Owned(format!("{}{}", scope, name)),
+            _ => Cow::Borrowed(name),
         }
     }
 
+    /// Returns the name of the anonymous label that a reference like `1f` or `1b` refers to.
+    fn resolve_anonymous_label(&mut self, reference: &'a str) -> ParseResult<Cow<'a, str>> {
+        let (label, direction) = reference.split_at(reference.len() - 1);
+        let defined = self.anonymous_labels.get(label).copied().unwrap_or(0);
+        let n = if direction == "b" {
+            if defined == 0 {
+                return Err(self.error(format!(
+                    "there's no anonymous label `({})' before this",
+                    label
+                )));
+            }
+            defined
+        } else {
+            self.forward_refs
+                .push((label, defined + 1, self.token.span));
+            defined + 1
+        };
+        Ok(Cow::Owned(format!("{}#{}", label, n)))
+    }
+
     fn parse_a_instruction(&mut self) -> ParseResult<ast::Instruction<'a>> {
         let start = self.token.span.start;
         self.expect(Kind::AtSign)?;
 
-        let span = Span::new(start, self.token.span.end);
-        let addr = match self.token.kind {
-            Kind::Number(_) => {
-                let num = self.parse_number()?;
+        let addr = match self.parse_expression()? {
+            ast::Expression::Value(num) => {
                 // The instruction uses 1 bit so we only have 15 bits available to use
                 if num >= 0x8000 {
                     return Err(self.span_error(
@@ -102,17 +164,68 @@ impl<'a> Parser<'a> {
                 }
                 ast::Address::Value(num)
             }
-            Kind::Identifier(s) => {
-                self.advance();
-                ast::Address::Symbol(s)
-            }
-            _ => return Err(self.unexpected_token_error("number or symbol")),
+            ast::Expression::Symbol(s) => ast::Address::Symbol(s),
+            expr => ast::Address::Expression(expr),
         };
+        let span = Span::new(start, self.prev_token.span.end);
 
         self.eat_terminator()?;
         Ok(ast::Instruction::A(ast::AInstruction { addr, span }))
     }
 
+    fn parse_expression(&mut self) -> ParseResult<ast::Expression<'a>> {
+        let mut expr = self.parse_term()?;
+        loop {
+            let op = match self.token.kind {
+                Kind::Plus => ast::ExpressionOperator::Plus,
+                Kind::Minus => ast::ExpressionOperator::Minus,
+                _ => return Ok(expr),
+            };
+            self.advance();
+            let rhs = self.parse_term()?;
+            expr = ast::Expression::Binary(Box::new(expr), op, Box::new(rhs));
+        }
+    }
+
+    fn parse_term(&mut self) -> ParseResult<ast::Expression<'a>> {
+        let mut expr = self.parse_factor()?;
+        while self.eat(Kind::Star) {
+            let rhs = self.parse_factor()?;
+            expr = ast::Expression::Binary(
+                Box::new(expr),
+                ast::ExpressionOperator::Times,
+                Box::new(rhs),
+            );
+        }
+        Ok(expr)
+    }
+
+    fn parse_factor(&mut self) -> ParseResult<ast::Expression<'a>> {
+        match self.token.kind {
+            Kind::Number(_) => Ok(ast::Expression::Value(self.parse_number()?)),
+            Kind::Identifier(s) => {
+                self.advance();
+                Ok(ast::Expression::Symbol(self.resolve_symbol(s)))
+            }
+            Kind::AnonymousLabel(reference) => {
+                let name = self.resolve_anonymous_label(reference)?;
+                self.advance();
+                Ok(ast::Expression::Symbol(name))
+            }
+            Kind::Minus => {
+                self.advance();
+                Ok(ast::Expression::Negate(Box::new(self.parse_factor()?)))
+            }
+            Kind::LParen => {
+                self.advance();
+                let expr = self.parse_expression()?;
+                self.expect(Kind::RParen)?;
+                Ok(expr)
+            }
+            _ => Err(self.unexpected_token_error("number or symbol")),
+        }
+    }
+
     fn parse_c_instruction(&mut self) -> ParseResult<ast::Instruction<'a>> {
         let start = self.token.span.start;
         let dest = self.parse_dest()?;
@@ -157,6 +270,7 @@ impl<'a> Parser<'a> {
             Kind::Plus | Kind::Minus | Kind::And | Kind::Or => {
                 Ok(ast::Comp::BinaryOperation(self.parse_binary_operation()?))
             }
+            Kind::ShiftLeft | Kind::ShiftRight => Ok(ast::Comp::Shift(self.parse_shift()?)),
             _ => match self.token.kind {
                 Kind::Identifier(_) => Ok(ast::Comp::Register(self.parse_register()?)),
                 Kind::Number(_) => Ok(ast::Comp::Bit(self.parse_bit()?)),
@@ -228,6 +342,20 @@ impl<'a> Parser<'a> {
         Ok(ast::BinaryOperation { lhs, op, rhs })
     }
 
+    fn parse_shift(&mut self) -> ParseResult<ast::Shift> {
+        let register = self.parse_register()?;
+        let direction = match self.token.kind {
+            Kind::ShiftLeft => ast::ShiftDirection::Left,
+            Kind::ShiftRight => ast::ShiftDirection::Right,
+            _ => return Err(self.unexpected_token_error("<< or >>")),
+        };
+        self.advance();
+        Ok(ast::Shift {
+            register,
+            direction,
+        })
+    }
+
     fn parse_bit(&mut self) -> ParseResult<ast::Bit> {
         if let Kind::Number(n) = self.token.kind {
             let bit = ast::Bit::try_from(n).map_err(|e| self.error(e))?;
@@ -297,7 +425,7 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn advance(&mut self) -> Token {
+    fn advance(&mut self) -> Token<'a> {
         self.prev_token = self.token;
         match self.peeked_token {
             Some(token) => {
@@ -365,11 +493,11 @@ mod tests {
             parser.parse(),
             Ok(vec![
                 ast::Instruction::Label(ast::Label {
-                    name: "LOOP",
+                    name: "LOOP".into(),
                     span: Span::new(0, 6)
                 }),
                 ast::Instruction::Label(ast::Label {
-                    name: "END",
+                    name: "END".into(),
                     span: Span::new(7, 12)
                 })
             ])
@@ -385,6 +513,60 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_local_and_anonymous_labels() {
+        let src = "(.start)\n@.start\n(main)\n(.loop)\n@.loop\n(other)\n(.loop)\n\
+                   (1)\n@1b\n@1f\n(1)\n@1b";
+        let instructions = Parser::new(Tokenizer::new(src)).parse().unwrap();
+        let names = instructions
+            .iter()
+            .map(|inst| match inst {
+                ast::Instruction::Label(label) => format!("({})", label.name),
+                ast::Instruction::A(ast::AInstruction {
+                    addr: ast::Address::Symbol(s),
+                    ..
+                }) => format!("@{}", s),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<String>>();
+        assert_eq!(
+            names,
+            vec![
+                "(.start)",
+                "@.start",
+                "(main)",
+                "(main.loop)",
+                "@main.loop",
+                "(other)",
+                "(other.loop)",
+                "(1#1)",
+                "@1#1",
+                "@1#2",
+                "(1#2)",
+                "@1#2"
+            ]
+        );
+
+        let mut parser = Parser::new(Tokenizer::new("@1b\n(1)\n@2f\n@1f\n"));
+        assert_eq!(
+            parser.parse(),
+            Err(vec![
+                SpanError::new(
+                    String::from("there's no anonymous label `(1)' before this"),
+                    Span::new(1, 3)
+                ),
+                SpanError::new(
+                    String::from("there's no anonymous label `(2)' after this"),
+                    Span::new(9, 11)
+                ),
+                SpanError::new(
+                    String::from("there's no anonymous label `(1)' after this"),
+                    Span::new(13, 15)
+                ),
+            ])
+        );
+    }
+
     #[test]
     fn test_a_instruction() {
         let mut parser = Parser::new(Tokenizer::new("@123"));
@@ -400,7 +582,7 @@ mod tests {
         assert_eq!(
             parser.parse(),
             Ok(vec![ast::Instruction::A(ast::AInstruction {
-                addr: ast::Address::Symbol("LOOP"),
+                addr: ast::Address::Symbol("LOOP".into()),
                 span: Span::new(0, 5)
             }),])
         );
@@ -414,6 +596,53 @@ mod tests {
             )])
         );
 
+        let mut parser = Parser::new(Tokenizer::new("@SCREEN+32*(KBD-1)"));
+        assert_eq!(
+            parser.parse(),
+            Ok(vec![ast::Instruction::A(ast::AInstruction {
+                addr: ast::Address::Expression(ast::Expression::Binary(
+                    Box::new(ast::Expression::Symbol("SCREEN".into())),
+                    ast::ExpressionOperator::Plus,
+                    Box::new(ast::Expression::Binary(
+                        Box::new(ast::Expression::Value(32)),
+                        ast::ExpressionOperator::Times,
+                        Box::new(ast::Expression::Binary(
+                            Box::new(ast::Expression::Symbol("KBD".into())),
+                            ast::ExpressionOperator::Minus,
+                            Box::new(ast::Expression::Value(1)),
+                        )),
+                    )),
+                )),
+                span: Span::new(0, 18)
+            })])
+        );
+
+        let mut parser = Parser::new(Tokenizer::new("@-1"));
+        assert_eq!(
+            parser.parse(),
+            Ok(vec![ast::Instruction::A(ast::AInstruction {
+                addr: ast::Address::Expression(ast::Expression::Negate(Box::new(
+                    ast::Expression::Value(1)
+                ))),
+                span: Span::new(0, 3)
+            })])
+        );
+
+        let mut parser = Parser::new(Tokenizer::new("@(1+2\n@3*"));
+        assert_eq!(
+            parser.parse(),
+            Err(vec![
+                SpanError::new(
+                    String::from("unexpected token `<newline>', expected RParen"),
+                    Span::new(5, 6)
+                ),
+                SpanError::new(
+                    String::from("unexpected token `<eof>', expected number or symbol"),
+                    Span::new(9, 9)
+                )
+            ])
+        );
+
         let mut parser = Parser::new(Tokenizer::new("@32768"));
         assert_eq!(
             parser.parse(),
@@ -564,6 +793,39 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_shifts() {
+        let mut parser = Parser::new(Tokenizer::new("M=D<<;JGT"));
+        assert_eq!(
+            parser.parse(),
+            Ok(vec![ast::Instruction::C(ast::CInstruction {
+                dest: Some(ast::Dest {
+                    d: false,
+                    a: false,
+                    m: true
+                }),
+                comp: ast::Comp::Shift(ast::Shift {
+                    register: ast::Register::D,
+                    direction: ast::ShiftDirection::Left
+                }),
+                jump: Some(ast::Jump::JGT),
+                span: Span::new(0, 9)
+            }),])
+        );
+
+        let mut parser = Parser::new(Tokenizer::new("A>>"));
+        assert!(matches!(
+            parser.parse().unwrap()[0],
+            ast::Instruction::C(ast::CInstruction {
+                comp: ast::Comp::Shift(ast::Shift {
+                    register: ast::Register::A,
+                    direction: ast::ShiftDirection::Right
+                }),
+                ..
+            })
+        ));
+    }
+
     #[test]
     fn test_invalid_c_instructions() {
         let mut parser = Parser::new(Tokenizer::new("1=1"));
@@ -583,6 +845,36 @@ mod tests {
                 Span::new(0, 2)
             )])
         );
+
+        let mut parser = Parser::new(Tokenizer::new("MDM=1"));
+        assert_eq!(
+            parser.parse(),
+            Err(vec![SpanError::new(
+                String::from("invalid destination MDM, M is repeated"),
+                Span::new(0, 3)
+            )])
+        );
+    }
+
+    #[test]
+    fn test_error_recovery() {
+        let mut parser = Parser::new(Tokenizer::new(
+            "@1
+D=X
+@2
+(
+M=D
+",
+        ));
+        let (instructions, errors) = parser.parse_with_errors();
+        assert_eq!(instructions.len(), 3);
+        assert_eq!(
+            errors
+                .iter()
+                .map(|err| err.span.start)
+                .collect::<Vec<usize>>(),
+            vec![5, 11]
+        );
     }
 
     #[test]
@@ -0,0 +1,1215 @@
+//! Expands assembler directives before parsing:
+//!
+//! ```text
+//! .include "file.asm"      // inserts another file, relative to this one
+//! .define NAME value       // replaces the identifier NAME with value
+//! .macro NAME a, b         // defines a macro with parameters a and b...
+//!     @a
+//!     D=M
+//!     @b
+//!     M=D
+//! .endm                    // ...up to here
+//! NAME x, y                // expands the macro, replacing a with x and b with y
+//! .data 4096: 1, -2, 0x7f   // stores values in RAM from address 4096 onwards
+//! .string SCREEN: "Hi"      // stores character codes followed by a 0
+//! .fill 2048: 100, 0xffff   // stores 100 copies of a value
+//! .var count                // declares a variable
+//! .var cursor @100          // declares a variable at a fixed address
+//! ```
+//!
+//! Data directives are replaced by code that stores the data when it runs (see [`super::data`]),
+//! so they go where that code should run, typically at the start of the program. Addresses can be
+//! numbers or anything an A-instruction accepts, and values are numbers, which may be negative,
+//! hex (`0x`) or binary (`0b`).
+//!
+//! Defined names, macro names and parameters can't be registers or jump mnemonics (`M`, `AD`,
+//! `JMP`, ...), as substituting them would change what instructions mean, and a defined name
+//! can't also be a label.
+//!
+//! Declared variables are allocated before any others, and the automatically allocated ones skip
+//! the fixed addresses. Declaring variables is optional, unless the program is assembled in strict
+//! mode (see [`super::Codegen::set_strict`]), where any other symbol that isn't a label is an
+//! error.
+//!
+//! The output is a single source that the parser understands, along with a map from each part of
+//! it back to the file and position it came from, so errors can be reported in the original
+//! sources. Text substituted for a parameter maps back to the macro's arguments, and the rest of
+//! a macro's expansion maps back to its definition.
+
+use std::{collections::HashMap, convert::TryFrom, path::Path};
+
+use super::{
+    ast::{Dest, Jump},
+    codegen::PREDEFINED_SYMBOLS,
+    data,
+};
+use crate::common::{Diagnostic, DiagnosticNote, Note, SourceFile, Span, SpanError};
+
+// Expanding more macros within macros than this is almost certainly infinite recursion
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+/// The expanded source, and the files it was expanded from. The first file is the one passed to
+/// `preprocess`.
+#[derive(Debug)]
+pub struct Expanded {
+    pub src: String,
+    pub files: Vec<SourceFile>,
+    /// The data directives, in the order they appear in the expanded source.
+    pub data: Vec<DataDirective>,
+    /// The variables declared with `.var`, in the order they were declared.
+    pub variables: Vec<VariableDeclaration>,
+    segments: Vec<Segment>,
+    // The file and position of the line each line of the expanded source was produced by
+    origins: Vec<(usize, usize)>,
+}
+
+/// A data directive, and the cost of the code generated for it.
+#[derive(Debug)]
+pub struct DataDirective {
+    /// The directive, e.g. `.data`.
+    pub directive: &'static str,
+    /// Where the directive is, in `files[file]`.
+    pub file: usize,
+    pub span: Span,
+    /// The number of words of data stored.
+    pub len: usize,
+    /// The number of instructions generated to store them.
+    pub code_len: usize,
+}
+
+/// A variable declared with `.var`.
+#[derive(Debug)]
+pub struct VariableDeclaration {
+    pub name: String,
+    /// The address the variable was given, if any. Otherwise it's allocated by the assembler.
+    pub address: Option<u16>,
+    /// Where the declaration is, in `files[file]`.
+    pub file: usize,
+    pub span: Span,
+}
+
+// A run of the expanded source that was copied from `files[file]`, starting at `start`
+#[derive(Debug)]
+struct Segment {
+    out_start: usize,
+    file: usize,
+    start: usize,
+    len: usize,
+}
+
+impl Expanded {
+    /// Maps a span in the expanded source back to the file and span it came from.
+    pub fn locate(&self, span: Span) -> (&SourceFile, Span) {
+        let (file, span) = self.locate_index(span);
+        (&self.files[file], span)
+    }
+
+    /// Like `locate`, but returns the index of the file in `files`.
+    pub fn locate_index(&self, span: Span) -> (usize, Span) {
+        if self.segments.is_empty() {
+            return (0, Span::new(0, 0));
+        }
+        let segment = |pos: usize| {
+            let i = self.segments.partition_point(|s| s.out_start <= pos);
+            &self.segments[i.saturating_sub(1)]
+        };
+        let offset = |segment: &Segment, pos: usize| {
+            segment.start + pos.saturating_sub(segment.out_start).min(segment.len)
+        };
+
+        let first = segment(span.start);
+        let start = offset(first, span.start);
+        let end = offset(first, span.end.max(span.start));
+        (first.file, Span::new(start, end))
+    }
+
+    /// Returns the file index and position of the line in that file which produced line `line`
+    /// (counting from 0) of the expanded source. That's the line itself, or the macro call or
+    /// data directive it was expanded from.
+    pub fn origin(&self, line: usize) -> (usize, usize) {
+        self.origins.get(line).copied().unwrap_or((0, 0))
+    }
+
+    /// Resolves an error in the expanded source to a position in the original sources.
+    pub fn diagnostic(&self, err: &SpanError) -> Diagnostic {
+        let (source_file, span) = self.locate(err.span);
+        let mut diagnostic = Diagnostic::from_span_error(
+            source_file,
+            &SpanError {
+                msg: err.msg.clone(),
+                span,
+                severity: err.severity,
+                notes: vec![],
+            },
+        );
+        diagnostic.notes = err
+            .notes
+            .iter()
+            .map(|note| {
+                let (source_file, span) = self.locate(note.span);
+                DiagnosticNote::from_note(
+                    source_file,
+                    &Note {
+                        msg: note.msg.clone(),
+                        span,
+                    },
+                )
+            })
+            .collect();
+        diagnostic
+    }
+}
+
+/// Expands the directives in `source_file`. `load` is called with the path of each included
+/// file, relative to the working directory, and returns its contents.
+pub fn preprocess<F>(source_file: &SourceFile, load: F) -> Result<Expanded, Vec<Diagnostic>>
+where
+    F: FnMut(&str) -> Result<String, String>,
+{
+    let mut preprocessor = Preprocessor {
+        load,
+        files: vec![SourceFile::new(
+            source_file.src.clone(),
+            source_file.name.clone(),
+        )],
+        defines: HashMap::new(),
+        macros: HashMap::new(),
+        include_stack: vec![],
+        lines: vec![],
+        origins: vec![],
+        origin: (0, 0),
+        data: vec![],
+        variables: vec![],
+        errors: vec![],
+    };
+    preprocessor.process_file(0);
+
+    let Preprocessor {
+        files,
+        lines,
+        origins,
+        data,
+        variables,
+        errors,
+        ..
+    } = preprocessor;
+    if !errors.is_empty() {
+        return Err(errors
+            .iter()
+            .map(|(file, err)| Diagnostic::from_span_error(&files[*file], err))
+            .collect());
+    }
+
+    let mut src = String::new();
+    let mut segments = vec![];
+    for line in lines {
+        for piece in line {
+            segments.push(Segment {
+                out_start: src.len(),
+                file: piece.file,
+                start: piece.start,
+                len: if piece.generated { 0 } else { piece.text.len() },
+            });
+            src.push_str(&piece.text);
+        }
+        src.push('\n');
+    }
+    Ok(Expanded {
+        src,
+        files,
+        data,
+        variables,
+        segments,
+        origins,
+    })
+}
+
+// Text copied from `start` in one of the files, or generated for a directive there
+#[derive(Debug, Clone)]
+struct Piece {
+    file: usize,
+    start: usize,
+    text: String,
+    generated: bool,
+}
+
+impl Piece {
+    fn slice(&self, start: usize, end: usize) -> Piece {
+        Piece {
+            file: self.file,
+            start: self.start + start,
+            text: self.text[start..end].to_owned(),
+            generated: self.generated,
+        }
+    }
+
+    fn span(&self) -> Span {
+        Span::new(self.start, self.start + self.text.len())
+    }
+}
+
+// A line of text, made up of pieces from different places once it's been expanded
+type Line = Vec<Piece>;
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Piece>,
+}
+
+struct Preprocessor<F> {
+    load: F,
+    files: Vec<SourceFile>,
+    defines: HashMap<String, Line>,
+    macros: HashMap<String, Macro>,
+    // The files currently being processed, to catch files that include themselves
+    include_stack: Vec<usize>,
+    lines: Vec<Line>,
+    origins: Vec<(usize, usize)>,
+    // The file and position of the line being processed
+    origin: (usize, usize),
+    data: Vec<DataDirective>,
+    variables: Vec<VariableDeclaration>,
+    errors: Vec<(usize, SpanError)>,
+}
+
+impl<F> Preprocessor<F>
+where
+    F: FnMut(&str) -> Result<String, String>,
+{
+    fn process_file(&mut self, file: usize) {
+        self.include_stack.push(file);
+
+        let mut lines = split_lines(file, &self.files[file].src).into_iter();
+        while let Some(line) = lines.next() {
+            self.origin = (file, line.start);
+            match directive(&line) {
+                Some(".include") => self.include(&line),
+                Some(".define") => self.define(&line),
+                Some(".macro") => {
+                    let mut body = vec![];
+                    let mut terminated = false;
+                    for body_line in lines.by_ref() {
+                        match directive(&body_line) {
+                            Some(".endm") => {
+                                terminated = true;
+                                break;
+                            }
+                            Some(name) => self.error(
+                                &body_line,
+                                format!("`{}' can't be used inside a macro", name),
+                            ),
+                            None => body.push(body_line),
+                        }
+                    }
+                    if terminated {
+                        self.define_macro(&line, body);
+                    } else {
+                        self.error(&line, "macro is missing `.endm'".to_owned());
+                    }
+                }
+                Some(".endm") => self.error(&line, "`.endm' without `.macro'".to_owned()),
+                Some(directive @ ".data")
+                | Some(directive @ ".string")
+                | Some(directive @ ".fill") => self.data(&line, directive),
+                Some(".var") => self.var(&line),
+                _ => self.process_line(vec![line], 0),
+            }
+        }
+
+        self.include_stack.pop();
+    }
+
+    fn include(&mut self, line: &Piece) {
+        let (_, rest) = split_word(line, 0);
+        let arg = code(&line.text[rest..]).trim();
+        let path = match arg.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+            Some(path) if !path.is_empty() => path,
+            _ => {
+                return self.error(
+                    line,
+                    "expected a quoted file name after `.include'".to_owned(),
+                )
+            }
+        };
+
+        let including = &self.files[line.file].name;
+        let path = match Path::new(including).parent() {
+            Some(dir) => dir.join(path).to_string_lossy().into_owned(),
+            None => path.to_owned(),
+        };
+        if let Some(&file) = self
+            .include_stack
+            .iter()
+            .find(|&&file| self.files[file].name == path)
+        {
+            let name = self.files[file].name.clone();
+            return self.error(line, format!("`{}' includes itself", name));
+        }
+
+        match (self.load)(&path) {
+            Ok(src) => {
+                self.files.push(SourceFile::new(src, path));
+                self.process_file(self.files.len() - 1);
+            }
+            Err(err) => self.error(line, format!("including {}: {}", path, err)),
+        }
+    }
+
+    fn define(&mut self, line: &Piece) {
+        let (_, rest) = split_word(line, 0);
+        let (name, rest) = split_word(line, rest);
+        let name = match name {
+            Some(name) => name,
+            None => return self.error(line, "expected a name after `.define'".to_owned()),
+        };
+        let value = trim(&line.slice(rest, rest + code(&line.text[rest..]).len()));
+        if value.text.is_empty() {
+            return self.error(line, format!("expected a value for `{}'", name.text));
+        }
+        if is_reserved(&name.text) {
+            return self.error(&name, reserved_error(&name.text));
+        }
+        if self.defines.contains_key(&name.text) {
+            return self.error(&name, format!("`{}' is already defined", name.text));
+        }
+
+        let value = self.substitute(&[value], &HashMap::new());
+        self.defines.insert(name.text, value);
+    }
+
+    fn data(&mut self, line: &Piece, directive: &'static str) {
+        let (_, rest) = split_word(line, 0);
+        let args = line.slice(rest, rest + code(&line.text[rest..]).len());
+        let colon = match args.text.find(':') {
+            Some(colon) => colon,
+            None => {
+                return self.error(
+                    line,
+                    format!("expected an address and `:' after `{}'", directive),
+                )
+            }
+        };
+
+        let address = trim(&args.slice(0, colon));
+        if address.text.is_empty() {
+            return self.error(line, format!("expected an address after `{}'", directive));
+        }
+        let address_text = text(&self.substitute(std::slice::from_ref(&address), &HashMap::new()));
+        let address = match parse_value(&address_text) {
+            Some(n) if !(0..0x8000).contains(&n) => {
+                return self.error(&address, format!("address {} is out of range", n))
+            }
+            Some(n) => Some(n),
+            None => None,
+        };
+
+        let values_piece = args.slice(colon + 1, args.text.len());
+        let mut values = vec![];
+        match directive {
+            ".string" => {
+                let quoted = trim(&values_piece);
+                let string = match quoted.text.strip_prefix('"') {
+                    Some(rest) if rest.ends_with('"') => &rest[..rest.len() - 1],
+                    _ => return self.error(line, "expected a quoted string after `:'".to_owned()),
+                };
+                for c in string.chars() {
+                    if !c.is_ascii() {
+                        return self.error(&quoted, format!("`{}' isn't an ASCII character", c));
+                    }
+                    values.push(c as u16);
+                }
+                values.push(0);
+            }
+            _ => {
+                let values_line = self.substitute(&[values_piece], &HashMap::new());
+                let args = split_args(&values_line);
+                let mut numbers = vec![];
+                for arg in &args {
+                    match parse_value(&text(arg)) {
+                        Some(n) if (-0x8000..=0xffff).contains(&n) => numbers.push(n),
+                        _ => {
+                            let piece = arg.first().unwrap_or(line);
+                            return self.error(piece, "expected a 16-bit number".to_owned());
+                        }
+                    }
+                }
+                match (directive, numbers.as_slice()) {
+                    (".fill", &[count, value]) if (0..=0x8000).contains(&count) => {
+                        values = vec![value as u16; count as usize]
+                    }
+                    (".fill", _) => {
+                        return self
+                            .error(line, "expected a count and a value after `:'".to_owned())
+                    }
+                    (_, []) => return self.error(line, "expected values after `:'".to_owned()),
+                    _ => values = numbers.iter().map(|&n| n as u16).collect(),
+                }
+            }
+        }
+
+        if let Some(address) = address {
+            if address + values.len() as i64 > 0x8000 {
+                return self.error(line, "the data goes past address 32767".to_owned());
+            }
+        }
+        let address_text = address.map_or(address_text, |n| n.to_string());
+        let label = format!(".$data{}", self.data.len() + 1);
+        let code = data::store(&address_text, &values, &label);
+
+        let start = trim(line).start;
+        let generated = |text: String| {
+            vec![Piece {
+                file: line.file,
+                start,
+                text,
+                generated: true,
+            }]
+        };
+        self.data.push(DataDirective {
+            directive,
+            file: line.file,
+            span: trim(line).span(),
+            len: values.len(),
+            code_len: code.iter().filter(|inst| !inst.starts_with('(')).count(),
+        });
+        for text in code {
+            self.push_line(generated(text));
+        }
+    }
+
+    fn var(&mut self, line: &Piece) {
+        let (_, rest) = split_word(line, 0);
+        let (name, rest) = split_word(line, rest);
+        let name = match name {
+            Some(name) if !name.text.starts_with('.') => name,
+            _ => return self.error(line, "expected a variable name after `.var'".to_owned()),
+        };
+        if PREDEFINED_SYMBOLS
+            .iter()
+            .any(|&(symbol, _)| symbol == name.text)
+        {
+            return self.error(&name, format!("`{}' is a predefined symbol", name.text));
+        }
+        if self.variables.iter().any(|var| var.name == name.text) {
+            return self.error(
+                &name,
+                format!("variable `{}' is already declared", name.text),
+            );
+        }
+
+        let rest = trim(&line.slice(rest, rest + code(&line.text[rest..]).len()));
+        let address = if rest.text.is_empty() {
+            None
+        } else {
+            let address = match rest.text.strip_prefix('@') {
+                Some(_) => rest.slice(1, rest.text.len()),
+                None => return self.error(&rest, "expected `@' and an address".to_owned()),
+            };
+            let address_text = text(&self.substitute(&[address], &HashMap::new()));
+            match parse_value(&address_text) {
+                Some(n) if (0..0x8000).contains(&n) => Some(n as u16),
+                Some(n) => return self.error(&rest, format!("address {} is out of range", n)),
+                None => return self.error(&rest, "expected an address".to_owned()),
+            }
+        };
+        self.variables.push(VariableDeclaration {
+            name: name.text,
+            address,
+            file: line.file,
+            span: trim(line).span(),
+        });
+    }
+
+    fn define_macro(&mut self, line: &Piece, body: Vec<Piece>) {
+        let (_, rest) = split_word(line, 0);
+        let (name, rest) = split_word(line, rest);
+        let name = match name {
+            Some(name) => name,
+            None => return self.error(line, "expected a name after `.macro'".to_owned()),
+        };
+        if is_reserved(&name.text) {
+            return self.error(&name, reserved_error(&name.text));
+        }
+
+        let mut params = vec![];
+        for param in split_args(&[line.slice(rest, line.text.len())]) {
+            match param.as_slice() {
+                [param] if is_reserved(&param.text) => {
+                    return self.error(param, reserved_error(&param.text))
+                }
+                [param] if is_identifier(&param.text) => params.push(param.text.clone()),
+                _ => {
+                    let piece = param.first().unwrap_or(line);
+                    return self.error(piece, "expected a parameter name".to_owned());
+                }
+            }
+        }
+        if self.macros.contains_key(&name.text) {
+            return self.error(&name, format!("macro `{}' is already defined", name.text));
+        }
+        self.macros.insert(name.text, Macro { params, body });
+    }
+
+    fn process_line(&mut self, line: Line, depth: usize) {
+        // Substituting a label's name would quietly define a different label
+        if let Some(label) = line.first().and_then(label_name) {
+            if self.defines.contains_key(&label.text) {
+                let msg = format!(
+                    "`{}' is defined with `.define', so it can't be a label",
+                    label.text
+                );
+                return self.error(&label, msg);
+            }
+        }
+        let line = self.substitute(&line, &HashMap::new());
+
+        let name = line
+            .first()
+            .and_then(first_word)
+            .filter(|word| self.macros.contains_key(&word.text));
+        let name = match name {
+            Some(name) => name,
+            None => return self.push_line(line),
+        };
+        if depth >= MAX_EXPANSION_DEPTH {
+            return self.error(
+                &name,
+                format!(
+                    "macro expansion is too deep (is `{}' recursive?)",
+                    name.text
+                ),
+            );
+        }
+
+        let mut rest = line.clone();
+        let name_end = name.start - rest[0].start + name.text.len();
+        rest[0] = rest[0].slice(name_end, rest[0].text.len());
+        let args = split_args(&rest);
+
+        let mac = &self.macros[&name.text];
+        if args.len() != mac.params.len() {
+            let msg = format!(
+                "macro `{}' expects {} argument{}, found {}",
+                name.text,
+                mac.params.len(),
+                if mac.params.len() == 1 { "" } else { "s" },
+                args.len()
+            );
+            return self.error(&name, msg);
+        }
+        let args = mac
+            .params
+            .iter()
+            .cloned()
+            .zip(args)
+            .collect::<HashMap<String, Line>>();
+        let body = mac
+            .body
+            .iter()
+            .map(|body_line| self.substitute(std::slice::from_ref(body_line), &args))
+            .collect::<Vec<Line>>();
+        for body_line in body {
+            self.process_line(body_line, depth + 1);
+        }
+    }
+
+    /// Replaces identifiers that are macro parameters or defined constants with their values,
+    /// leaving comments alone.
+    fn substitute(&self, line: &[Piece], params: &HashMap<String, Line>) -> Line {
+        let mut out = vec![];
+        let mut in_comment = false;
+        for piece in line {
+            if in_comment {
+                out.push(piece.clone());
+                continue;
+            }
+            let code_len = code(&piece.text).len();
+            in_comment = code_len < piece.text.len();
+
+            let mut copied = 0;
+            let mut pos = 0;
+            while pos < code_len {
+                let (word, end) = split_word(piece, pos);
+                let word = match word {
+                    Some(word) => word,
+                    None => break,
+                };
+                let value = params
+                    .get(&word.text)
+                    .or_else(|| self.defines.get(&word.text));
+                if let Some(value) = value {
+                    let word_start = word.start - piece.start;
+                    out.push(piece.slice(copied, word_start));
+                    out.extend(value.iter().cloned());
+                    copied = end;
+                }
+                pos = end;
+            }
+            out.push(piece.slice(copied, piece.text.len()));
+        }
+        out.retain(|piece| !piece.text.is_empty());
+        if out.is_empty() {
+            out.extend(line.first().map(|piece| piece.slice(0, 0)));
+        }
+        out
+    }
+
+    fn push_line(&mut self, line: Line) {
+        self.lines.push(line);
+        self.origins.push(self.origin);
+    }
+
+    fn error(&mut self, piece: &Piece, msg: String) {
+        self.errors
+            .push((piece.file, SpanError::new(msg, trim(piece).span())));
+    }
+}
+
+fn split_lines(file: usize, src: &str) -> Vec<Piece> {
+    let mut start = 0;
+    src.split('\n')
+        .map(|text| {
+            let piece = Piece {
+                file,
+                start,
+                text: text.trim_end_matches('\r').to_owned(),
+                generated: false,
+            };
+            start += text.len() + 1;
+            piece
+        })
+        .collect()
+}
+
+/// Returns the directive a line starts with, if any.
+fn directive(line: &Piece) -> Option<&'static str> {
+    let word = first_word(line)?;
+    [
+        ".include", ".define", ".macro", ".endm", ".data", ".string", ".fill", ".var",
+    ]
+    .iter()
+    .copied()
+    .find(|&directive| directive == word.text)
+}
+
+/// Returns the identifier a line starts with, if it starts with one rather than e.g. `@` or `(`.
+fn first_word(line: &Piece) -> Option<Piece> {
+    let start = line.text.len() - line.text.trim_start().len();
+    match split_word(line, start) {
+        (Some(word), _) if word.start == line.start + start => Some(word),
+        _ => None,
+    }
+}
+
+/// Finds the next identifier at or after `pos`, skipping anything else. Returns it and the
+/// position just after it.
+fn split_word(piece: &Piece, pos: usize) -> (Option<Piece>, usize) {
+    let code_len = code(&piece.text).len();
+    let text = &piece.text[..code_len];
+    let mut chars = text[pos..].char_indices().map(|(i, c)| (pos + i, c));
+
+    let start = loop {
+        match chars.next() {
+            Some((i, c)) if ident_start_char(c) => break i,
+            // Skip numbers whole, so the digits of e.g. `2x` aren't split from the letters
+            Some((i, c)) if ident_char(c) => {
+                let end = text[i..]
+                    .find(|c| !ident_char(c))
+                    .map_or(code_len, |n| i + n);
+                return split_word(piece, end);
+            }
+            Some(_) => continue,
+            None => return (None, code_len),
+        }
+    };
+    let end = text[start..]
+        .find(|c| !ident_char(c))
+        .map_or(code_len, |n| start + n);
+    (Some(piece.slice(start, end)), end)
+}
+
+/// Splits the arguments of a macro on commas, ignoring any comment. No arguments is an empty
+/// list rather than a single empty argument.
+fn split_args(line: &[Piece]) -> Vec<Line> {
+    let mut args = vec![vec![]];
+    for piece in line {
+        let code_len = code(&piece.text).len();
+        let mut start = 0;
+        for (i, c) in piece.text[..code_len].char_indices() {
+            if c == ',' {
+                args.last_mut().unwrap().push(piece.slice(start, i));
+                args.push(vec![]);
+                start = i + 1;
+            }
+        }
+        args.last_mut().unwrap().push(piece.slice(start, code_len));
+        if code_len < piece.text.len() {
+            break;
+        }
+    }
+
+    let args = args
+        .into_iter()
+        .map(|arg| {
+            let arg = arg
+                .iter()
+                .map(trim)
+                .filter(|piece| !piece.text.is_empty())
+                .collect::<Line>();
+            arg
+        })
+        .collect::<Vec<Line>>();
+    if args.len() == 1 && args[0].is_empty() {
+        vec![]
+    } else {
+        args
+    }
+}
+
+fn text(line: &[Piece]) -> String {
+    line.iter().map(|piece| piece.text.as_str()).collect()
+}
+
+/// Parses a decimal, hex (`0x`) or binary (`0b`) number, which may be negative.
+fn parse_value(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.trim().strip_prefix('-') {
+        Some(digits) => (true, digits.trim_start()),
+        None => (false, text.trim()),
+    };
+    if !digits.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2)
+    } else {
+        digits.parse()
+    };
+    // Numbers too big to parse are out of range anyway
+    let value = value.ok().filter(|n| *n <= 0x10000)?;
+    Some(if negative { -value } else { value })
+}
+
+// Strips a trailing comment
+fn code(text: &str) -> &str {
+    comment_start(text).map_or(text, |i| &text[..i])
+}
+
+/// Returns where the comment in a line starts, if it has one. A `//` in a quoted string, like
+/// `.string 200: "a//b"`, doesn't start a comment.
+pub(crate) fn comment_start(text: &str) -> Option<usize> {
+    let mut in_string = false;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => in_string = !in_string,
+            '/' if !in_string && matches!(chars.peek(), Some((_, '/'))) => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn trim(piece: &Piece) -> Piece {
+    let start = piece.text.len() - piece.text.trim_start().len();
+    let end = piece.text.trim_end().len().max(start);
+    piece.slice(start, end)
+}
+
+// Returns the name in a label definition like `(LOOP)`
+fn label_name(line: &Piece) -> Option<Piece> {
+    let code = code(&line.text);
+    let start = code.find(|c: char| !c.is_whitespace())?;
+    let len = code[start..]
+        .trim_end()
+        .strip_prefix('(')?
+        .strip_suffix(')')?
+        .len();
+    Some(trim(&line.slice(start + 1, start + 1 + len)))
+}
+
+// Registers and jump mnemonics can't be substituted without changing what instructions mean
+fn is_reserved(name: &str) -> bool {
+    Dest::try_from(name).is_ok() || Jump::try_from(name).is_ok()
+}
+
+fn reserved_error(name: &str) -> String {
+    format!(
+        "`{}' names a register or jump, so it can't be defined or used as a macro name or parameter",
+        name
+    )
+}
+
+fn is_identifier(s: &str) -> bool {
+    s.starts_with(ident_start_char) && s.chars().all(ident_char)
+}
+
+// The same as the tokenizer's identifiers
+fn ident_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '.' | '$' | ':')
+}
+
+fn ident_start_char(c: char) -> bool {
+    c.is_alphabetic() || matches!(c, '_' | '.' | '$' | ':')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(path: &str) -> Result<String, String> {
+        match path {
+            "lib/util.asm" => {
+                Ok(".include \"consts.asm\"\n.macro INC addr\n@addr\nM=M+1\n.endm\n".to_owned())
+            }
+            "lib/consts.asm" => Ok(".define COUNTER 100\n".to_owned()),
+            "lib/loop.asm" => Ok(".include \"loop.asm\"\n".to_owned()),
+            _ => Err("No such file or directory".to_owned()),
+        }
+    }
+
+    fn expand(src: &str) -> Result<Expanded, Vec<Diagnostic>> {
+        preprocess(
+            &SourceFile::new(src.to_owned(), "lib/main.asm".to_owned()),
+            load,
+        )
+    }
+
+    fn normalize(src: &str) -> Vec<&str> {
+        src.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn test_expansion() {
+        let expanded = expand(
+            ".define TWO 2 // two\n\
+             .include \"util.asm\"\n\
+             .macro COPY from, to\n\
+             @from // comment mentioning from\n\
+             D=M\n\
+             @to\n\
+             M=D\n\
+             .endm\n\
+             (START)\n\
+             COPY TWO, COUNTER\n\
+             INC COUNTER\n\
+             @START\n",
+        )
+        .unwrap();
+        assert_eq!(
+            normalize(&expanded.src),
+            vec![
+                "(START)",
+                "@2 // comment mentioning from",
+                "D=M",
+                "@100",
+                "M=D",
+                "@100",
+                "M=M+1",
+                "@START",
+            ]
+        );
+        assert_eq!(
+            expanded
+                .files
+                .iter()
+                .map(|f| f.name.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["lib/main.asm", "lib/util.asm", "lib/consts.asm"]
+        );
+    }
+
+    #[test]
+    fn test_symbols_named_like_macros() {
+        // Only a macro's name at the start of a line calls it
+        let expanded = expand(
+            ".macro INC\n\
+             M=M+1\n\
+             .endm\n\
+             (INC)\n\
+             @INC\n\
+             INC // INC\n\
+             (.data)\n\
+             @.data\n",
+        )
+        .unwrap();
+        assert_eq!(
+            normalize(&expanded.src),
+            vec!["(INC)", "@INC", "M=M+1", "(.data)", "@.data"]
+        );
+    }
+
+    #[test]
+    fn test_reserved_names() {
+        let errors = |src: &str| {
+            expand(src)
+                .unwrap_err()
+                .into_iter()
+                .map(|d| (d.line, d.msg))
+                .collect::<Vec<(usize, String)>>()
+        };
+        let reserved = |name: &str| {
+            format!(
+                "`{}' names a register or jump, so it can't be defined or used as a macro name or parameter",
+                name
+            )
+        };
+        assert_eq!(
+            errors(
+                ".define M 1\n.macro A\nD=0\n.endm\n.macro SET JMP\n.endm\n.define MD 2\nA=M\nM=M+1\n"
+            ),
+            vec![
+                (1, reserved("M")),
+                (2, reserved("A")),
+                (5, reserved("JMP")),
+                (7, reserved("MD")),
+            ]
+        );
+        // A defined name can't also be a label, which would be renamed
+        assert_eq!(
+            errors(".define LOOP 5\n( LOOP )\n@LOOP\n0;JMP\n"),
+            vec![(
+                2,
+                "`LOOP' is defined with `.define', so it can't be a label".to_owned()
+            )]
+        );
+        // Names that merely contain registers are fine
+        let expanded = expand(".define ADD 1\n.macro MAX\n@ADD\n.endm\nMAX\nAM=M-1\n").unwrap();
+        assert_eq!(normalize(&expanded.src), vec!["@1", "AM=M-1"]);
+    }
+
+    #[test]
+    fn test_comments_in_strings() {
+        let expanded = expand(".string 200: \"a//b\" // a // comment\n").unwrap();
+        assert_eq!(expanded.data[0].len, 5);
+        assert_eq!(normalize(&expanded.src)[..4], ["@97", "D=A", "@200", "M=D"]);
+        assert!(normalize(&expanded.src).contains(&"@47"));
+    }
+
+    #[test]
+    fn test_data() {
+        let expanded = expand(
+            ".define TABLE 4096\n\
+             .data TABLE: 1, -2, 0x7f, 0b101 // table\n\
+             .string SCREEN+1: \"Hi\"\n\
+             .fill 2048: 20, 0\n",
+        )
+        .unwrap();
+        assert_eq!(
+            normalize(&expanded.src),
+            vec![
+                "@4096",
+                "M=1",
+                "@1",
+                "D=!A",
+                "@4097",
+                "M=D",
+                "@127",
+                "D=A",
+                "@4098",
+                "M=D",
+                "@5",
+                "D=A",
+                "@4099",
+                "M=D",
+                "@72",
+                "D=A",
+                "@SCREEN+1",
+                "M=D",
+                "@105",
+                "D=A",
+                "@SCREEN+1+1",
+                "M=D",
+                "@SCREEN+1+2",
+                "M=0",
+                "@19",
+                "D=A",
+                "(.$data3.1)",
+                "@2048",
+                "A=D+A",
+                "M=0",
+                "D=D-1",
+                "@.$data3.1",
+                "D;JGE",
+            ]
+        );
+        assert_eq!(
+            expanded
+                .data
+                .iter()
+                .map(|d| (d.directive, d.len, d.code_len))
+                .collect::<Vec<(&str, usize, usize)>>(),
+            vec![(".data", 4, 14), (".string", 3, 10), (".fill", 20, 8)]
+        );
+
+        // Generated code maps back to the directive
+        let pos = expanded.src.find("@SCREEN+1+1").unwrap();
+        let diagnostic = expanded.diagnostic(&SpanError::new("x".to_owned(), Span::new(pos, pos)));
+        assert_eq!((diagnostic.line, diagnostic.column), (3, 1));
+    }
+
+    #[test]
+    fn test_variables() {
+        let expanded = expand(
+            ".define BUFFER 0x100
+.var count // loop counter
+@count
+.var buffer @BUFFER
+",
+        )
+        .unwrap();
+        assert_eq!(normalize(&expanded.src), vec!["@count"]);
+        assert_eq!(
+            expanded
+                .variables
+                .iter()
+                .map(|var| (var.name.as_str(), var.address))
+                .collect::<Vec<(&str, Option<u16>)>>(),
+            vec![("count", None), ("buffer", Some(256))]
+        );
+
+        let errors = expand(
+            ".var
+.var x 5
+.var y @32768
+.var x
+.var x
+.var SP
+",
+        )
+        .unwrap_err()
+        .into_iter()
+        .map(|d| (d.line, d.msg))
+        .collect::<Vec<(usize, String)>>();
+        assert_eq!(
+            errors,
+            vec![
+                (1, "expected a variable name after `.var'".to_owned()),
+                (2, "expected `@' and an address".to_owned()),
+                (3, "address 32768 is out of range".to_owned()),
+                (5, "variable `x' is already declared".to_owned()),
+                (6, "`SP' is a predefined symbol".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_source_map() {
+        let src = ".macro JUMP target\n0;JMP\n@target\n.endm\nJUMP FOO\n";
+        let expanded = expand(src).unwrap();
+        let pos = expanded.src.find("FOO").unwrap();
+        let (file, span) = expanded.locate(Span::new(pos, pos + 3));
+        assert_eq!(file.name, "lib/main.asm");
+        assert_eq!(span, Span::new(src.rfind("FOO").unwrap(), src.len() - 1));
+
+        // Parts of the body map back to the definition
+        let pos = expanded.src.find("0;JMP").unwrap();
+        let diagnostic = expanded.diagnostic(&SpanError::new("x".to_owned(), Span::new(pos, pos)));
+        assert_eq!((diagnostic.line, diagnostic.column), (2, 1));
+
+        // Errors at the very end map to the end of the file
+        let diagnostic = expanded.diagnostic(&SpanError::new(
+            "x".to_owned(),
+            Span::new(expanded.src.len(), expanded.src.len()),
+        ));
+        assert_eq!(diagnostic.line, 6);
+    }
+
+    #[test]
+    fn test_errors() {
+        let errors = |src: &str| {
+            expand(src)
+                .unwrap_err()
+                .into_iter()
+                .map(|d| (d.file, d.line, d.msg))
+                .collect::<Vec<(String, usize, String)>>()
+        };
+        assert_eq!(
+            errors(".include \"missing.asm\"\n.endm\n.define X\n"),
+            vec![
+                (
+                    "lib/main.asm".to_owned(),
+                    1,
+                    "including lib/missing.asm: No such file or directory".to_owned()
+                ),
+                (
+                    "lib/main.asm".to_owned(),
+                    2,
+                    "`.endm' without `.macro'".to_owned()
+                ),
+                (
+                    "lib/main.asm".to_owned(),
+                    3,
+                    "expected a value for `X'".to_owned()
+                ),
+            ]
+        );
+        assert_eq!(
+            errors(".data 100 1\n.data 40000: 1\n.data 0: 70000\n.fill 0: 1\n.string 0: Hi\n.data 32767: 1, 2\n"),
+            vec![
+                (
+                    "lib/main.asm".to_owned(),
+                    1,
+                    "expected an address and `:' after `.data'".to_owned()
+                ),
+                (
+                    "lib/main.asm".to_owned(),
+                    2,
+                    "address 40000 is out of range".to_owned()
+                ),
+                (
+                    "lib/main.asm".to_owned(),
+                    3,
+                    "expected a 16-bit number".to_owned()
+                ),
+                (
+                    "lib/main.asm".to_owned(),
+                    4,
+                    "expected a count and a value after `:'".to_owned()
+                ),
+                (
+                    "lib/main.asm".to_owned(),
+                    5,
+                    "expected a quoted string after `:'".to_owned()
+                ),
+                (
+                    "lib/main.asm".to_owned(),
+                    6,
+                    "the data goes past address 32767".to_owned()
+                ),
+            ]
+        );
+        assert_eq!(
+            errors(".include \"loop.asm\""),
+            vec![(
+                "lib/loop.asm".to_owned(),
+                1,
+                "`lib/loop.asm' includes itself".to_owned()
+            )]
+        );
+        assert_eq!(
+            errors(".macro MOVE a\n@a\n.endm\nMOVE 1, 2\n.macro R\nR\n.endm\nR\n.macro U\n"),
+            vec![
+                (
+                    "lib/main.asm".to_owned(),
+                    4,
+                    "macro `MOVE' expects 1 argument, found 2".to_owned()
+                ),
+                (
+                    "lib/main.asm".to_owned(),
+                    6,
+                    "macro expansion is too deep (is `R' recursive?)".to_owned()
+                ),
+                (
+                    "lib/main.asm".to_owned(),
+                    9,
+                    "macro is missing `.endm'".to_owned()
+                ),
+            ]
+        );
+    }
+}
@@ -1,5 +1,5 @@
 use super::tokens::{Kind, Token};
-use crate::common::{Cursor, EOF_CHAR};
+use crate::common::{Cursor, Span, EOF_CHAR};
 
 pub struct Tokenizer<'a> {
     src: &'a str,
@@ -18,11 +18,27 @@ impl<'a> Tokenizer<'a> {
         self.eat_whitespace();
 
         let token = match self.cursor.c {
-            '\n' | '@' | '=' | '+' | '-' | '&' | '|' | '!' | ';' | '(' | ')' => {
+            '\n' | '@' | '=' | '+' | '-' | '*' | '&' | '|' | '!' | ';' | '(' | ')' => {
                 let token = Token::from_char(self.cursor.pos, self.cursor.c);
                 self.cursor.advance();
                 token
             }
+            c @ ('<' | '>') => {
+                let start = self.cursor.pos;
+                self.cursor.advance();
+                if self.cursor.c != c {
+                    return Token::invalid(c, start);
+                }
+                self.cursor.advance();
+                Token {
+                    kind: if c == '<' {
+                        Kind::ShiftLeft
+                    } else {
+                        Kind::ShiftRight
+                    },
+                    span: Span::new(start, self.cursor.pos),
+                }
+            }
             '/' => {
                 let token = match self.cursor.peek() {
                     '/' => self.tokenize_comment(),
@@ -52,7 +68,19 @@ impl<'a> Tokenizer<'a> {
     }
 
     fn tokenize_number(&mut self) -> Token<'a> {
-        let span = self.cursor.eat_while(|c| c.is_numeric());
+        let mut span = self.cursor.eat_while(|c| c.is_numeric());
+        // Anonymous labels are single digits
+        if span.end - span.start == 1
+            && matches!(self.cursor.c, 'f' | 'b')
+            && !ident_char(self.cursor.peek())
+        {
+            self.cursor.advance();
+            span.end += 1;
+            return Token {
+                kind: Kind::AnonymousLabel(&self.src[span.start..span.end]),
+                span,
+            };
+        }
         Token {
             kind: Kind::Number(&self.src[span.start..span.end]),
             span,
@@ -116,7 +144,7 @@ mod tests {
     use super::*;
     use crate::common::Span;
 
-    fn tokenize(s: &str) -> Vec<Token> {
+    fn tokenize(s: &str) -> Vec<Token<'_>> {
         Tokenizer::new(s).collect()
     }
 
@@ -197,6 +225,26 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_shifts() {
+        let tokens = tokenize("D=D<< M=A>> D<");
+        assert_eq!(
+            tokens.iter().map(|t| t.kind).collect::<Vec<Kind>>(),
+            vec![
+                Kind::Identifier("D"),
+                Kind::Equals,
+                Kind::Identifier("D"),
+                Kind::ShiftLeft,
+                Kind::Identifier("M"),
+                Kind::Equals,
+                Kind::Identifier("A"),
+                Kind::ShiftRight,
+                Kind::Identifier("D"),
+                Kind::Invalid('<'),
+            ]
+        );
+    }
+
     #[test]
     fn test_labels() {
         let tokens = tokenize("(LOOP)\n@1\n (END) ");
@@ -217,6 +265,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_anonymous_labels() {
+        let tokens = tokenize("@1f @2b+1 @12f @3bar");
+        assert_eq!(
+            tokens.iter().map(|t| t.kind).collect::<Vec<Kind>>(),
+            vec![
+                Kind::AtSign,
+                Kind::AnonymousLabel("1f"),
+                Kind::AtSign,
+                Kind::AnonymousLabel("2b"),
+                Kind::Plus,
+                Kind::Number("1"),
+                Kind::AtSign,
+                Kind::Number("12"),
+                Kind::Identifier("f"),
+                Kind::AtSign,
+                Kind::Number("3"),
+                Kind::Identifier("bar"),
+            ]
+        );
+    }
+
     #[test]
     fn test_comments() {
         let tokens = tokenize("// foo\n// bar");
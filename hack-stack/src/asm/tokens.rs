@@ -7,13 +7,18 @@ pub enum Kind<'a> {
     Comment(&'a str),
     Number(&'a str),
     Identifier(&'a str),
+    /// A reference to the next or previous anonymous label, e.g. `1f` or `1b`.
+    AnonymousLabel(&'a str),
     AtSign,
     Equals,
     Plus,
     Minus,
+    Star,
     Not,
     And,
     Or,
+    ShiftLeft,
+    ShiftRight,
     Semicolon,
     LParen,
     RParen,
@@ -28,13 +33,17 @@ impl<'a> fmt::Display for Kind<'a> {
             Kind::Comment(v) => v,
             Kind::Number(v) => v,
             Kind::Identifier(v) => v,
+            Kind::AnonymousLabel(v) => v,
             Kind::AtSign => "@",
             Kind::Equals => "=",
             Kind::Plus => "+",
             Kind::Minus => "-",
+            Kind::Star => "*",
             Kind::Not => "!",
             Kind::And => "&",
             Kind::Or => "|",
+            Kind::ShiftLeft => "<<",
+            Kind::ShiftRight => ">>",
             Kind::Semicolon => ";",
             Kind::LParen => "(",
             Kind::RParen => ")",
@@ -62,6 +71,7 @@ impl<'a> Token<'a> {
             '=' => Kind::Equals,
             '+' => Kind::Plus,
             '-' => Kind::Minus,
+            '*' => Kind::Star,
             '!' => Kind::Not,
             '&' => Kind::And,
             '|' => Kind::Or,
@@ -14,40 +14,93 @@ fn main() {
 }
 
 fn assemble_main() -> Result<(), ()> {
-    let args = std::env::args().collect::<Vec<String>>();
-    let source_path = args.get(1).ok_or_else(|| {
-        eprintln!("usage: hack-assemble FILE");
+    let args_and_opts = std::env::args().collect::<Vec<String>>();
+    let (opts, args): (Vec<&String>, Vec<&String>) = args_and_opts
+        .iter()
+        .skip(1)
+        .partition(|&a| a.starts_with('-'));
+
+    let source_path = *args.first().ok_or_else(|| {
+        eprintln!(
+            "usage: hack-assemble [-O] [--listing] [--analyze] [--strict] [--extended-isa] [--object | --hackx] FILE"
+        );
     })?;
+    let write_listing = opts.iter().any(|o| *o == "--listing");
+    let write_analysis = opts.iter().any(|o| *o == "--analyze");
+    let optimize = opts.iter().any(|o| *o == "-O");
+    let strict = opts.iter().any(|o| *o == "--strict");
+    // Objects are linked with `hack-link`, which resolves the symbols they don't define
+    let object = opts.iter().any(|o| *o == "--object");
+    // `.hackx` files keep the symbols and line numbers, for emulators and disassemblers
+    let hackx = opts.iter().any(|o| *o == "--hackx");
+    if object && (write_listing || strict || hackx) {
+        eprintln!("--listing, --strict and --hackx can't be used with --object");
+        return Err(());
+    }
+    let instruction_set = if opts.iter().any(|o| *o == "--extended-isa") {
+        common::InstructionSet::Extended
+    } else {
+        common::InstructionSet::Hack
+    };
 
     let source = fs::read_to_string(source_path).map_err(|err| {
         eprintln!("reading {}: {}", source_path, err);
     })?;
 
     let source_file = common::SourceFile::new(source, source_path.to_owned());
-    let tokenizer = asm::Tokenizer::new(&source_file.src);
+    let expanded = asm::preprocess(&source_file, |path| {
+        fs::read_to_string(path).map_err(|err| err.to_string())
+    })
+    .map_err(|diagnostics| display_diagnostics(&diagnostics))?;
+
+    // Every problem is reported in one go, so whatever parsed is still checked and generated
+    let tokenizer = asm::Tokenizer::new(&expanded.src);
     let mut parser = asm::Parser::new(tokenizer);
-    let instructions = match parser.parse() {
-        Ok(instructions) => instructions,
-        Err(errs) => {
-            display_span_errors(&source_file, errs);
-            return Err(());
-        }
-    };
+    let (mut instructions, mut errors) = parser.parse_with_errors();
+    errors.extend(if object {
+        asm::check_object(&instructions)
+    } else {
+        asm::check(&instructions)
+    });
 
-    let mut gen = asm::Codegen::new();
-    let machine_code = match gen.generate(&instructions) {
-        Ok(output) => output,
-        Err(errs) => {
-            display_span_errors(&source_file, errs);
-            return Err(());
-        }
-    };
+    let mut gen = new_codegen(&expanded, strict, instruction_set);
+    let mut machine_code = generate(&mut gen, &instructions, object).unwrap_or_else(|errs| {
+        errors.extend(errs);
+        String::new()
+    });
+    errors.sort_by_key(|err| err.span.start);
+    let failed = errors.iter().any(common::SpanError::is_error);
+    display_span_errors(&expanded, errors);
+    if failed {
+        return Err(());
+    }
+
+    // Code can't be moved if anything depends on where it is
+    let fixed_addresses = optimize && asm::optimize::uses_fixed_addresses(&instructions);
+    let mut words_saved = None;
+    if optimize && !fixed_addresses {
+        let words = asm::optimize::count_words(&instructions);
+        instructions = asm::optimize(instructions);
+        words_saved = Some(words - asm::optimize::count_words(&instructions));
+
+        gen = new_codegen(&expanded, strict, instruction_set);
+        machine_code = generate(&mut gen, &instructions, object)
+            .map_err(|errs| display_span_errors(&expanded, errs))?;
+    }
 
-    let output_path = source_path.replace(".asm", "") + ".hack";
+    let (extension, output) = if object {
+        (".hobj", machine_code.into_bytes())
+    } else if hackx {
+        let hackx = asm::hackx::Hackx::from_codegen(&gen, &expanded, instruction_set);
+        (".hackx", hackx.to_bytes())
+    } else {
+        (".hack", machine_code.into_bytes())
+    };
+    let output_path = source_path.replace(".asm", "") + extension;
     let mut out_file = File::create(Path::new(&output_path)).map_err(|err| {
         eprintln!("creating {}: {}", output_path, err);
     })?;
-    out_file.write_all(machine_code.as_bytes()).map_err(|err| {
+    out_file.write_all(&output).map_err(|err| {
         eprintln!("writing to {}: {}", output_path, err);
     })?;
 
@@ -55,14 +108,111 @@ fn assemble_main() -> Result<(), ()> {
         "Assembled {} successfully, wrote to {}",
         source_path, output_path
     );
+    if fixed_addresses {
+        println!("Not optimizing, as the program jumps to a numeric address or does arithmetic with a label");
+    }
+    if let Some(words_saved) = words_saved {
+        println!("Optimizing saved {} words", words_saved);
+    }
+    for data in &expanded.data {
+        let file = &expanded.files[data.file];
+        let (line, _) = file.loc_for_byte_pos(data.span.start);
+        println!(
+            "{} (line {}): `{}' stores {} words using {} instructions",
+            file.name, line, data.directive, data.len, data.code_len
+        );
+    }
+
+    if write_listing {
+        let listing_path = source_path.replace(".asm", "") + ".lst";
+        fs::write(
+            &listing_path,
+            asm::listing(&expanded, &gen, words_saved.is_some()),
+        )
+        .map_err(|err| {
+            eprintln!("writing to {}: {}", listing_path, err);
+        })?;
+        println!("Wrote listing to {}", listing_path);
+    }
+
+    // Strict mode is about knowing where every variable is, so show where they went
+    if strict {
+        let symbols_path = source_path.replace(".asm", "") + ".sym";
+        fs::write(&symbols_path, asm::symbol_table(&gen)).map_err(|err| {
+            eprintln!("writing to {}: {}", symbols_path, err);
+        })?;
+        println!("Wrote symbol table to {}", symbols_path);
+    }
+
+    if write_analysis {
+        let analysis_path = source_path.replace(".asm", "") + ".analysis";
+        let analysis = asm::analyze(&instructions);
+        fs::write(&analysis_path, analysis.to_string()).map_err(|err| {
+            eprintln!("writing to {}: {}", analysis_path, err);
+        })?;
+        println!(
+            "Wrote analysis of {} blocks and {} loops to {}",
+            analysis.blocks.len(),
+            analysis.loops.len(),
+            analysis_path
+        );
+    }
 
     Ok(())
 }
 
-fn display_span_errors(source_file: &common::SourceFile, errs: Vec<common::SpanError>) {
-    for err in errs {
-        let (line, col) = source_file.loc_for_byte_pos(err.span.start);
-        eprintln!("line {}, char {}: {}", line, col, err.msg);
+fn new_codegen(
+    expanded: &asm::preprocessor::Expanded,
+    strict: bool,
+    instruction_set: common::InstructionSet,
+) -> asm::Codegen<'_> {
+    let mut gen = asm::Codegen::new();
+    gen.set_strict(strict);
+    gen.set_instruction_set(instruction_set);
+    for var in &expanded.variables {
+        gen.declare_variable(&var.name, var.address);
+    }
+    gen
+}
+
+// Assembles the program to the `.hack` format, or to an object to be linked
+fn generate<'a>(
+    gen: &mut asm::Codegen<'a>,
+    instructions: &'a [asm::ast::Instruction],
+    object: bool,
+) -> Result<String, Vec<common::SpanError>> {
+    if object {
+        gen.generate_object(instructions)
+            .map(|object| object.to_string())
+    } else {
+        gen.generate(instructions)
+    }
+}
+
+fn display_span_errors(expanded: &asm::preprocessor::Expanded, errs: Vec<common::SpanError>) {
+    let diagnostics = errs
+        .iter()
+        .map(|err| expanded.diagnostic(err))
+        .collect::<Vec<common::Diagnostic>>();
+    display_diagnostics(&diagnostics);
+}
+
+fn display_diagnostics(diagnostics: &[common::Diagnostic]) {
+    for d in diagnostics {
+        match d.severity {
+            common::Severity::Error => {
+                eprintln!("{} (line {}, char {}): {}", d.file, d.line, d.column, d.msg)
+            }
+            common::Severity::Warning => eprintln!(
+                "{} (line {}, char {}): warning: {}",
+                d.file, d.line, d.column, d.msg
+            ),
+        }
+        for note in &d.notes {
+            eprintln!(
+                "{} (line {}, char {}): note: {}",
+                note.file, note.line, note.column, note.msg
+            );
+        }
     }
-    std::process::exit(1);
 }
@@ -0,0 +1,50 @@
+use std::fs;
+use std::io::{self, Write};
+
+use hack_stack::asm::{
+    self,
+    hackx::{self, Hackx},
+};
+use hack_stack::common::InstructionSet;
+use hack_stack::emulator;
+
+fn main() {
+    if disassemble_main().is_err() {
+        std::process::exit(1);
+    }
+}
+
+fn disassemble_main() -> Result<(), ()> {
+    let args_and_opts = std::env::args().collect::<Vec<String>>();
+    let (opts, args): (Vec<&String>, Vec<&String>) = args_and_opts
+        .iter()
+        .skip(1)
+        .partition(|&a| a.starts_with("--"));
+
+    let path = *args.first().ok_or_else(|| {
+        eprintln!("usage: hack-disassemble [--extended-isa] FILE");
+    })?;
+    let data = fs::read(path).map_err(|err| {
+        eprintln!("reading {}: {}", path, err);
+    })?;
+
+    // `.hackx` files say which instruction set they use, and have symbols and line numbers
+    let program = if hackx::is_hackx(&data) {
+        Hackx::from_bytes(&data).map_err(|err| {
+            eprintln!("{}: {}", path, err);
+        })?
+    } else {
+        let rom = emulator::parse_rom(&String::from_utf8_lossy(&data)).map_err(|err| {
+            eprintln!("{}: {}", path, err);
+        })?;
+        let mut program = Hackx::from_rom(rom);
+        if opts.iter().any(|o| *o == "--extended-isa") {
+            program.instruction_set = InstructionSet::Extended;
+        }
+        program
+    };
+
+    // Write errors are ignored, so piping into e.g. `head` doesn't panic
+    let _ = io::stdout().write_all(asm::disassemble_program(&program).as_bytes());
+    Ok(())
+}
@@ -0,0 +1,229 @@
+use std::{
+    fs,
+    io::stdin,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use hack_stack::asm::hackx::{self, Hackx};
+use hack_stack::common::InstructionSet;
+use hack_stack::emulator::{
+    self,
+    batch::{self, Outcome},
+    Clock, Emulator, EmulatorConfig,
+};
+
+mod terminal;
+mod tui;
+
+fn main() {
+    if emulate_main().is_err() {
+        std::process::exit(1);
+    }
+}
+
+fn emulate_main() -> Result<(), ()> {
+    let args_and_opts = std::env::args().collect::<Vec<String>>();
+    let (opts, args): (Vec<&String>, Vec<&String>) = args_and_opts
+        .iter()
+        .skip(1)
+        .partition(|&a| a.starts_with("--"));
+
+    let source_path = args.first().ok_or_else(|| {
+        eprintln!(
+            "usage: hack-emulate [--trace] [--tui] [--clock=FREQ] [--lenient] [--extended-isa] [--save-state=OUT] FILE"
+        );
+        eprintln!("       hack-emulate --batch [--report=junit|json] [--threads=N] MANIFEST");
+    })?;
+
+    if opts.iter().any(|o| *o == "--batch") {
+        return batch_main(source_path, &opts);
+    }
+
+    let trace = opts.iter().any(|o| *o == "--trace");
+    let tui = opts.iter().any(|o| *o == "--tui");
+    // Lenient mode makes the whole 32K address space usable as RAM
+    let mut config = if opts.iter().any(|o| *o == "--lenient") {
+        EmulatorConfig::extended()
+    } else {
+        EmulatorConfig::hack()
+    };
+    if opts.iter().any(|o| *o == "--extended-isa") {
+        config.instruction_set = InstructionSet::Extended;
+    }
+    let frequency = match opts.iter().find_map(|o| o.strip_prefix("--clock=")) {
+        Some(freq) => Some(emulator::parse_frequency(freq).map_err(|err| {
+            eprintln!("{}", err);
+        })?),
+        None => None,
+    };
+
+    let save_state_path = opts.iter().find_map(|o| o.strip_prefix("--save-state="));
+
+    let (mut emulator, program) = load_emulator(source_path, config)?;
+    let result = run(&mut emulator, program.as_ref(), tui, trace, frequency);
+
+    if let Some(path) = save_state_path {
+        fs::write(path, emulator.save_state()).map_err(|err| {
+            eprintln!("writing {}: {}", path, err);
+        })?;
+    }
+    result
+}
+
+/// Loads either a program or a saved emulator state. Saved states include the memory
+/// configuration, so `config` only applies to programs. Programs in the `.hackx` format are
+/// returned too, for their symbols and line numbers.
+fn load_emulator(path: &str, config: EmulatorConfig) -> Result<(Emulator, Option<Hackx>), ()> {
+    let data = fs::read(path).map_err(|err| {
+        eprintln!("reading {}: {}", path, err);
+    })?;
+
+    if emulator::is_state(&data) {
+        let emulator = Emulator::from_state(&data).map_err(|err| {
+            eprintln!("{}: {}", path, err);
+        })?;
+        return Ok((emulator, None));
+    }
+
+    let program = if hackx::is_hackx(&data) {
+        Hackx::from_bytes(&data).map_err(|err| {
+            eprintln!("{}: {}", path, err);
+        })?
+    } else {
+        let rom = emulator::parse_rom(&String::from_utf8_lossy(&data)).map_err(|err| {
+            eprintln!("{}: {}", path, err);
+        })?;
+        Hackx::from_rom(rom)
+    };
+    let mut emulator = Emulator::with_config(program.rom.clone(), config).map_err(|err| {
+        eprintln!("{}: {}", path, err);
+    })?;
+    // Programs assembled for the extended instruction set can't run without it
+    if program.instruction_set == InstructionSet::Extended {
+        emulator.set_instruction_set(InstructionSet::Extended);
+    }
+    Ok((emulator, Some(program)))
+}
+
+fn run(
+    emulator: &mut Emulator,
+    program: Option<&Hackx>,
+    tui: bool,
+    trace: bool,
+    frequency: Option<u32>,
+) -> Result<(), ()> {
+    if tui {
+        let mut clock = Clock::new(frequency.unwrap_or(emulator::DEFAULT_FREQUENCY));
+        return tui::run(emulator, program, &mut clock).map_err(|err| {
+            eprintln!("terminal error: {}", err);
+        });
+    }
+
+    if trace {
+        println!("|     D |     A |    PC | Memory");
+    }
+
+    if let Some(frequency) = frequency {
+        return run_clocked(emulator, &mut Clock::new(frequency), trace);
+    }
+
+    for _ in 0..20000000 {
+        if trace {
+            print_trace_row(emulator);
+        }
+
+        emulator.step().map_err(|err| {
+            eprintln!("emulator error: {}", err);
+        })?;
+
+        let mut buf = String::new();
+        if stdin().read_line(&mut buf).expect("reading line") == 0 {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn batch_main(manifest_path: &str, opts: &[&String]) -> Result<(), ()> {
+    let report = match opts.iter().find_map(|o| o.strip_prefix("--report=")) {
+        None | Some("junit") => batch::junit_report,
+        Some("json") => batch::json_report,
+        Some(format) => {
+            eprintln!("unknown report format `{}'", format);
+            return Err(());
+        }
+    };
+    let threads = match opts.iter().find_map(|o| o.strip_prefix("--threads=")) {
+        Some(n) => n.parse::<usize>().map_err(|_| {
+            eprintln!("invalid thread count `{}'", n);
+        })?,
+        None => thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+
+    let manifest = fs::read_to_string(manifest_path).map_err(|err| {
+        eprintln!("reading {}: {}", manifest_path, err);
+    })?;
+    let entries = batch::parse_manifest(&manifest).map_err(|err| {
+        eprintln!("{}: {}", manifest_path, err);
+    })?;
+
+    // ROM paths are relative to the manifest
+    let manifest_dir = Path::new(manifest_path).parent().unwrap_or(Path::new(""));
+    let mut jobs = vec![];
+    for entry in &entries {
+        let rom_path = manifest_dir.join(&entry.rom_path);
+        let rom = fs::read_to_string(&rom_path)
+            .map_err(|err| err.to_string())
+            .and_then(|src| emulator::parse_rom(&src))
+            .map_err(|err| {
+                eprintln!("{}: {}", rom_path.display(), err);
+            })?;
+        jobs.push(batch::Job::from_manifest_entry(entry, rom));
+    }
+
+    let results = batch::run_batch(&jobs, threads);
+    print!("{}", report(&results));
+
+    let passed = results
+        .iter()
+        .filter(|r| r.outcome == Outcome::Passed)
+        .count();
+    eprintln!("{} passed, {} failed", passed, results.len() - passed);
+    if passed == results.len() {
+        Ok(())
+    } else {
+        Err(())
+    }
+}
+
+fn run_clocked(emulator: &mut Emulator, clock: &mut Clock, trace: bool) -> Result<(), ()> {
+    let start = Instant::now();
+    loop {
+        for _ in 0..clock.tick(start.elapsed().as_secs_f64()) {
+            if trace {
+                print_trace_row(emulator);
+            }
+            emulator.step().map_err(|err| {
+                eprintln!("emulator error: {}", err);
+            })?;
+        }
+
+        let delay = clock.frame_delay(start.elapsed().as_secs_f64());
+        thread::sleep(Duration::from_secs_f64(delay));
+    }
+}
+
+fn print_trace_row(emulator: &Emulator) {
+    let memory = emulator.memory()[0..16]
+        .iter()
+        .map(|x: &u16| format!("{:04X}", x))
+        .collect::<Vec<String>>()
+        .join(" ");
+    println!(
+        "| {:5} | {:5} | {:5} | {} |",
+        emulator.cpu.d, emulator.cpu.a, emulator.cpu.pc, memory
+    );
+}
@@ -0,0 +1,254 @@
+use std::{
+    io::{self, Read, Stdout, Write},
+    process::{Command, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use hack_stack::emulator::keyboard::{
+    KEY_BACKSPACE, KEY_DELETE, KEY_DOWN, KEY_END, KEY_ESCAPE, KEY_F1, KEY_HOME, KEY_INSERT,
+    KEY_LEFT, KEY_NEWLINE, KEY_PAGE_DOWN, KEY_PAGE_UP, KEY_RIGHT, KEY_UP,
+};
+
+const SIZE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, PartialEq)]
+pub enum Key {
+    Quit,
+    Hack(u16),
+}
+
+pub struct Terminal {
+    out: Stdout,
+    saved_mode: String,
+    size: (usize, usize),
+    size_checked_at: Instant,
+}
+
+impl Terminal {
+    pub fn enter() -> io::Result<Self> {
+        let saved_mode = stty(&["-g"])?;
+        // Disable line buffering, echo and signals so keys (including Ctrl-C) reach us directly
+        stty(&["-icanon", "-echo", "-isig", "-ixon", "min", "1"])?;
+
+        let mut term = Terminal {
+            out: io::stdout(),
+            saved_mode,
+            size: (0, 0),
+            size_checked_at: Instant::now(),
+        };
+        term.size = query_size()?;
+
+        // Switch to the alternate screen, hide the cursor, and clear the screen
+        term.out.write_all(b"\x1b[?1049h\x1b[?25l\x1b[2J")?;
+        term.out.flush()?;
+        Ok(term)
+    }
+
+    /// Returns the terminal size as (rows, columns).
+    pub fn size(&mut self) -> io::Result<(usize, usize)> {
+        if self.size_checked_at.elapsed() > SIZE_POLL_INTERVAL {
+            self.size = query_size()?;
+            self.size_checked_at = Instant::now();
+        }
+        Ok(self.size)
+    }
+
+    pub fn draw(&mut self, lines: &[String]) -> io::Result<()> {
+        let mut buf = String::from("\x1b[H");
+        for (i, line) in lines.iter().enumerate() {
+            if i > 0 {
+                buf.push_str("\r\n");
+            }
+            buf.push_str(line);
+            buf.push_str("\x1b[K");
+        }
+        buf.push_str("\x1b[J");
+
+        self.out.write_all(buf.as_bytes())?;
+        self.out.flush()
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.out.write_all(b"\x1b[?25h\x1b[?1049l");
+        let _ = self.out.flush();
+        let _ = stty(&[self.saved_mode.as_str()]);
+    }
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+fn query_size() -> io::Result<(usize, usize)> {
+    let size = stty(&["size"])?;
+    let mut parts = size.split_whitespace().map(|p| p.parse::<usize>());
+    match (parts.next(), parts.next()) {
+        (Some(Ok(rows)), Some(Ok(cols))) => Ok((rows, cols)),
+        _ => Err(io::Error::other(format!(
+            "unexpected terminal size `{}'",
+            size
+        ))),
+    }
+}
+
+pub fn spawn_key_reader() -> mpsc::Receiver<Key> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdin = io::stdin();
+        let mut buf = [0u8; 64];
+        loop {
+            let n = match stdin.read(&mut buf) {
+                Ok(0) | Err(_) => return,
+                Ok(n) => n,
+            };
+            for key in parse_keys(&buf[..n]) {
+                if tx.send(key).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    rx
+}
+
+fn parse_keys(bytes: &[u8]) -> Vec<Key> {
+    let mut keys = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let key = match bytes[i] {
+            0x03 => Some(Key::Quit),
+            b'\r' | b'\n' => Some(Key::Hack(KEY_NEWLINE)),
+            0x7f | 0x08 => Some(Key::Hack(KEY_BACKSPACE)),
+            0x1b => {
+                // Escape sequences (arrows, function keys, etc.) arrive in a single read, so a
+                // lone escape byte at the end of the buffer is the escape key itself
+                let len = escape_sequence_len(&bytes[i..]);
+                let seq = &bytes[i..i + len];
+                i += len - 1;
+                if len == 1 {
+                    Some(Key::Hack(KEY_ESCAPE))
+                } else {
+                    escape_sequence_key(seq).map(Key::Hack)
+                }
+            }
+            b @ 0x20..=0x7e => Some(Key::Hack(b as u16)),
+            _ => None,
+        };
+        keys.extend(key);
+        i += 1;
+    }
+    keys
+}
+
+fn escape_sequence_len(bytes: &[u8]) -> usize {
+    match bytes.get(1) {
+        Some(b'[') => match bytes[2..].iter().position(|b| (0x40..=0x7e).contains(b)) {
+            Some(pos) => pos + 3,
+            None => bytes.len(),
+        },
+        Some(b'O') if bytes.len() > 2 => 3,
+        _ => 1,
+    }
+}
+
+fn escape_sequence_key(seq: &[u8]) -> Option<u16> {
+    let key = match seq {
+        b"\x1b[A" => KEY_UP,
+        b"\x1b[B" => KEY_DOWN,
+        b"\x1b[C" => KEY_RIGHT,
+        b"\x1b[D" => KEY_LEFT,
+        b"\x1b[H" | b"\x1b[1~" | b"\x1bOH" => KEY_HOME,
+        b"\x1b[F" | b"\x1b[4~" | b"\x1bOF" => KEY_END,
+        b"\x1b[5~" => KEY_PAGE_UP,
+        b"\x1b[6~" => KEY_PAGE_DOWN,
+        b"\x1b[2~" => KEY_INSERT,
+        b"\x1b[3~" => KEY_DELETE,
+        b"\x1bOP" => KEY_F1,
+        b"\x1bOQ" => KEY_F1 + 1,
+        b"\x1bOR" => KEY_F1 + 2,
+        b"\x1bOS" => KEY_F1 + 3,
+        b"\x1b[15~" => KEY_F1 + 4,
+        b"\x1b[17~" => KEY_F1 + 5,
+        b"\x1b[18~" => KEY_F1 + 6,
+        b"\x1b[19~" => KEY_F1 + 7,
+        b"\x1b[20~" => KEY_F1 + 8,
+        b"\x1b[21~" => KEY_F1 + 9,
+        b"\x1b[23~" => KEY_F1 + 10,
+        b"\x1b[24~" => KEY_F1 + 11,
+        _ => return None,
+    };
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_keys() {
+        assert_eq!(
+            parse_keys(b"a Z~\r\n\x7f\x08\x03"),
+            vec![
+                Key::Hack(b'a' as u16),
+                Key::Hack(b' ' as u16),
+                Key::Hack(b'Z' as u16),
+                Key::Hack(b'~' as u16),
+                Key::Hack(KEY_NEWLINE),
+                Key::Hack(KEY_NEWLINE),
+                Key::Hack(KEY_BACKSPACE),
+                Key::Hack(KEY_BACKSPACE),
+                Key::Quit,
+            ]
+        );
+        // Other control characters and non-ASCII bytes have no Hack key
+        assert_eq!(parse_keys(b"\x01\t\xc3\xa9"), vec![]);
+    }
+
+    #[test]
+    fn test_parse_escape_sequences() {
+        assert_eq!(
+            parse_keys(b"\x1b[A\x1b[Dx\x1b[5~\x1bOH\x1b[3~"),
+            vec![
+                Key::Hack(KEY_UP),
+                Key::Hack(KEY_LEFT),
+                Key::Hack(b'x' as u16),
+                Key::Hack(KEY_PAGE_UP),
+                Key::Hack(KEY_HOME),
+                Key::Hack(KEY_DELETE),
+            ]
+        );
+        assert_eq!(
+            parse_keys(b"\x1bOP\x1b[15~\x1b[24~"),
+            vec![
+                Key::Hack(KEY_F1),
+                Key::Hack(KEY_F1 + 4),
+                Key::Hack(KEY_F1 + 11),
+            ]
+        );
+
+        // A lone escape is the escape key, whether or not anything follows it
+        assert_eq!(parse_keys(b"\x1b"), vec![Key::Hack(KEY_ESCAPE)]);
+        assert_eq!(
+            parse_keys(b"\x1bq"),
+            vec![Key::Hack(KEY_ESCAPE), Key::Hack(b'q' as u16)]
+        );
+        // Unknown sequences are skipped whole, and an unfinished one takes the rest of the read
+        assert_eq!(
+            parse_keys(b"\x1b[99~a\x1b[1;5"),
+            vec![Key::Hack(b'a' as u16)]
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    io,
+    sync::mpsc::TryRecvError,
+    thread,
+    time::{Duration, Instant},
+};
+
+use hack_stack::{
+    asm::{self, hackx::Hackx},
+    emulator::{
+        screen::{SCREEN_HEIGHT, SCREEN_WIDTH, WORDS_PER_ROW},
+        Clock, Emulator,
+    },
+};
+
+use crate::terminal::{self, Key, Terminal};
+
+// Terminals only report key presses, not releases, so we treat a key as held until it hasn't
+// been seen for a while. Holding a key down keeps it pressed via the terminal's auto-repeat.
+const KEY_HOLD_TIME: Duration = Duration::from_millis(150);
+
+const PANEL_WIDTH: usize = 46;
+const DISASSEMBLY_BEFORE_PC: usize = 4;
+const DISASSEMBLY_AFTER_PC: usize = 10;
+const RAM_WORDS_PER_ROW: usize = 8;
+
+/// Runs the emulator in the terminal. If `program` has symbols and line numbers, they're shown
+/// alongside the disassembly.
+pub fn run(emulator: &mut Emulator, program: Option<&Hackx>, clock: &mut Clock) -> io::Result<()> {
+    let mut term = Terminal::enter()?;
+    let keys = terminal::spawn_key_reader();
+    let start = Instant::now();
+
+    let mut screen = BrailleScreen::new();
+    let mut held_key: Option<(u16, Instant)> = None;
+    let mut error = None;
+    let mut cycles = 0u64;
+
+    loop {
+        let frame_start = Instant::now();
+
+        loop {
+            match keys.try_recv() {
+                Ok(Key::Quit) | Err(TryRecvError::Disconnected) => return Ok(()),
+                Ok(Key::Hack(code)) => held_key = Some((code, frame_start)),
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+        if matches!(held_key, Some((_, pressed_at)) if pressed_at.elapsed() > KEY_HOLD_TIME) {
+            held_key = None;
+        }
+        emulator.set_keyboard(held_key.map_or(0, |(code, _)| code));
+
+        if error.is_none() {
+            let steps = clock.tick(start.elapsed().as_secs_f64());
+            for _ in 0..steps {
+                if let Err(err) = emulator.step() {
+                    error = Some(err);
+                    break;
+                }
+                cycles += 1;
+            }
+        }
+
+        let (rows, cols) = term.size()?;
+        screen.update(emulator, screen_scale(rows, cols));
+        term.draw(&render(
+            emulator,
+            program,
+            &screen,
+            clock,
+            cycles,
+            error.as_deref(),
+            (rows, cols),
+        ))?;
+
+        let delay = clock.frame_delay(start.elapsed().as_secs_f64());
+        thread::sleep(Duration::from_secs_f64(delay));
+    }
+}
+
+fn render(
+    emulator: &Emulator,
+    program: Option<&Hackx>,
+    screen: &BrailleScreen,
+    clock: &Clock,
+    cycles: u64,
+    error: Option<&str>,
+    (rows, cols): (usize, usize),
+) -> Vec<String> {
+    let (screen_cols, _) = screen_size(screen.scale);
+    let screen = &screen.lines;
+    let mut panels = render_cpu_panel(emulator, clock, cycles);
+    panels.extend(render_disassembly_panel(emulator, program));
+    panels.extend(render_ram_panel(emulator.memory()));
+
+    let height = screen.len().max(panels.len()).min(rows.saturating_sub(1));
+    let mut lines = (0..height)
+        .map(|i| {
+            let left = screen
+                .get(i)
+                .cloned()
+                .unwrap_or_else(|| " ".repeat(screen_cols));
+            let right = panels.get(i).map(String::as_str).unwrap_or("");
+            truncate(&format!("{} {}", left, right), cols)
+        })
+        .collect::<Vec<String>>();
+
+    let status = match error {
+        Some(err) => format!("emulator error: {} (Ctrl-C to quit)", err),
+        None => String::from("Ctrl-C to quit"),
+    };
+    lines.push(truncate(&status, cols));
+    lines
+}
+
+// Use the most detailed rendering that fits alongside the side panels. Each braille character
+// covers 2x4 dots, and each dot covers a scale x scale block of pixels.
+fn screen_scale(rows: usize, cols: usize) -> usize {
+    [1, 2, 4]
+        .iter()
+        .copied()
+        .find(|scale| {
+            let (screen_cols, screen_rows) = screen_size(*scale);
+            screen_cols + 1 + PANEL_WIDTH <= cols && screen_rows < rows
+        })
+        .unwrap_or(4)
+}
+
+fn screen_size(scale: usize) -> (usize, usize) {
+    (SCREEN_WIDTH / (2 * scale), SCREEN_HEIGHT / (4 * scale))
+}
+
+/// The screen rendered as lines of braille characters, which are only re-rendered when the
+/// pixels they cover change.
+struct BrailleScreen {
+    scale: usize,
+    lines: Vec<String>,
+}
+
+impl BrailleScreen {
+    fn new() -> Self {
+        Self {
+            scale: 0,
+            lines: vec![],
+        }
+    }
+
+    fn update(&mut self, emulator: &mut Emulator, scale: usize) {
+        let changes = emulator.take_screen_changes();
+        let screen_memory = emulator.screen();
+
+        if scale != self.scale {
+            let (_, rows) = screen_size(scale);
+            self.scale = scale;
+            self.lines = (0..rows)
+                .map(|row| render_screen_line(screen_memory, scale, row))
+                .collect();
+            return;
+        }
+
+        let pixel_rows_per_line = 4 * scale;
+        let mut prev_line = None;
+        for pixel_row in changes.dirty_rows() {
+            let line = pixel_row / pixel_rows_per_line;
+            if prev_line != Some(line) {
+                self.lines[line] = render_screen_line(screen_memory, scale, line);
+                prev_line = Some(line);
+            }
+        }
+    }
+}
+
+fn render_screen_line(screen: &[u16], scale: usize, line: usize) -> String {
+    let pixel = |x: usize, y: usize| {
+        let word = screen.get(y * WORDS_PER_ROW + x / 16).unwrap_or(&0);
+        (word >> (x % 16)) & 1 == 1
+    };
+    // A dot is lit if any of the pixels it covers are lit
+    let dot = |dot_x: usize, dot_y: usize| {
+        (0..scale).any(|dy| (0..scale).any(|dx| pixel(dot_x * scale + dx, dot_y * scale + dy)))
+    };
+
+    // Bit offsets of each dot within a braille character, indexed by [y][x]
+    const BRAILLE_DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+    let (cols, _) = screen_size(scale);
+    (0..cols)
+        .map(|col| {
+            let mut bits = 0;
+            for (y, row_dots) in BRAILLE_DOTS.iter().enumerate() {
+                for (x, bit) in row_dots.iter().enumerate() {
+                    if dot(col * 2 + x, line * 4 + y) {
+                        bits |= bit;
+                    }
+                }
+            }
+            char::from_u32(0x2800 + bits).unwrap()
+        })
+        .collect()
+}
+
+fn render_cpu_panel(emulator: &Emulator, clock: &Clock, cycles: u64) -> Vec<String> {
+    let cpu = &emulator.cpu;
+    let kbd_addr = emulator.config().keyboard_addr as usize;
+    let kbd = emulator.memory().get(kbd_addr).copied().unwrap_or(0);
+    vec![
+        panel_heading("CPU"),
+        format!(
+            " PC  {:04X}           D  {:04X} {:>6}",
+            cpu.pc, cpu.d, cpu.d as i16
+        ),
+        format!(
+            " A   {:04X} {:>6}    M  {:04X} {:>6}",
+            cpu.a, cpu.a as i16, cpu.m, cpu.m as i16
+        ),
+        format!(" KBD {:04X}           cycles {}", kbd, cycles),
+        format!(
+            " clock {:.2} MHz (target {:.2} MHz)",
+            clock.effective_frequency() / 1e6,
+            clock.frequency() as f64 / 1e6
+        ),
+    ]
+}
+
+fn render_disassembly_panel(emulator: &Emulator, program: Option<&Hackx>) -> Vec<String> {
+    let rom = emulator.rom();
+    let pc = emulator.cpu.pc as usize;
+    let start = pc.saturating_sub(DISASSEMBLY_BEFORE_PC);
+    let end = (pc + DISASSEMBLY_AFTER_PC).min(rom.len());
+
+    // The heading shows where the current instruction came from, if it's known
+    let heading = match program.and_then(|program| program.source_line(pc as u16)) {
+        Some((file, line)) => format!("ROM ({}:{})", file, line),
+        None => "ROM".to_owned(),
+    };
+    let mut lines = vec![panel_heading(&heading)];
+    for (addr, &inst) in rom.iter().enumerate().take(end).skip(start) {
+        let marker = if addr == pc { '>' } else { ' ' };
+        let text = asm::disassemble_for(inst, emulator.cpu.instruction_set)
+            .unwrap_or_else(|| format!("<invalid {:016b}>", inst));
+        let label = program
+            .and_then(|program| program.labels_at(addr as u16).next())
+            .map(|label| format!("({})", label))
+            .unwrap_or_default();
+        lines.push(
+            format!("{}{:04X}  {:14}{}", marker, addr, text, label)
+                .trim_end()
+                .to_owned(),
+        );
+    }
+    lines
+}
+
+fn render_ram_panel(memory: &[u16]) -> Vec<String> {
+    // Show R0-R15, followed by the stack around the stack pointer
+    let sp = memory.first().copied().unwrap_or(0) as usize;
+    let stack_start = (sp - sp % RAM_WORDS_PER_ROW)
+        .saturating_sub(2 * RAM_WORDS_PER_ROW)
+        .max(2 * RAM_WORDS_PER_ROW);
+    let row_starts = [0, RAM_WORDS_PER_ROW]
+        .iter()
+        .copied()
+        .chain((0..5).map(|row| stack_start + row * RAM_WORDS_PER_ROW));
+
+    let mut lines = vec![panel_heading("RAM")];
+    for row_start in row_starts {
+        if row_start >= memory.len() {
+            break;
+        }
+        let row_end = (row_start + RAM_WORDS_PER_ROW).min(memory.len());
+        let words = memory[row_start..row_end]
+            .iter()
+            .map(|w| format!("{:04X}", w))
+            .collect::<Vec<String>>()
+            .join(" ");
+        lines.push(format!(" {:04X}: {}", row_start, words));
+    }
+    lines
+}
+
+fn panel_heading(title: &str) -> String {
+    format!(
+        "── {} {}",
+        title,
+        "─".repeat(PANEL_WIDTH.saturating_sub(title.chars().count() + 4))
+    )
+}
+
+fn truncate(line: &str, cols: usize) -> String {
+    line.chars().take(cols).collect()
+}
@@ -0,0 +1,63 @@
+use std::fs;
+
+use hack_stack::asm;
+use hack_stack::common;
+
+fn main() {
+    if fmt_main().is_err() {
+        std::process::exit(1);
+    }
+}
+
+fn fmt_main() -> Result<(), ()> {
+    let args_and_opts = std::env::args().collect::<Vec<String>>();
+    let (opts, args): (Vec<&String>, Vec<&String>) = args_and_opts
+        .iter()
+        .skip(1)
+        .partition(|&a| a.starts_with("--"));
+
+    if args.is_empty() {
+        eprintln!("usage: hack-fmt [--check] FILE...");
+        return Err(());
+    }
+    let check = opts.iter().any(|o| *o == "--check");
+
+    let mut failed = false;
+    for &path in &args {
+        if format_file(path, check).is_err() {
+            failed = true;
+        }
+    }
+    if failed {
+        Err(())
+    } else {
+        Ok(())
+    }
+}
+
+fn format_file(path: &str, check: bool) -> Result<(), ()> {
+    let source = fs::read_to_string(path).map_err(|err| {
+        eprintln!("reading {}: {}", path, err);
+    })?;
+
+    let formatted = asm::format(&source).map_err(|errs| {
+        let source_file = common::SourceFile::new(source.clone(), path.to_owned());
+        for err in errs {
+            let d = common::Diagnostic::from_span_error(&source_file, &err);
+            eprintln!("{} (line {}, char {}): {}", d.file, d.line, d.column, d.msg);
+        }
+    })?;
+    if formatted == source {
+        return Ok(());
+    }
+
+    if check {
+        eprintln!("{} isn't formatted", path);
+        return Err(());
+    }
+    fs::write(path, formatted).map_err(|err| {
+        eprintln!("writing to {}: {}", path, err);
+    })?;
+    println!("Formatted {}", path);
+    Ok(())
+}
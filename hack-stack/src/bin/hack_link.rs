@@ -0,0 +1,53 @@
+use std::fs;
+
+use hack_stack::asm;
+
+fn main() {
+    if link_main().is_err() {
+        std::process::exit(1);
+    }
+}
+
+fn link_main() -> Result<(), ()> {
+    let args_and_opts = std::env::args().collect::<Vec<String>>();
+    let (opts, args): (Vec<&String>, Vec<&String>) = args_and_opts
+        .iter()
+        .skip(1)
+        .partition(|&a| a.starts_with("--"));
+
+    if args.is_empty() {
+        eprintln!("usage: hack-link [--output=FILE] OBJECT...");
+        return Err(());
+    }
+    let output_path = match opts.iter().find_map(|o| o.strip_prefix("--output=")) {
+        Some(path) => path.to_owned(),
+        None => args[0].replace(".hobj", "") + ".hack",
+    };
+
+    let mut objects = vec![];
+    for &path in &args {
+        let src = fs::read_to_string(path).map_err(|err| {
+            eprintln!("reading {}: {}", path, err);
+        })?;
+        let object = asm::object::Object::parse(&src).map_err(|err| {
+            eprintln!("{}: {}", path, err);
+        })?;
+        objects.push((path.to_owned(), object));
+    }
+
+    let program = asm::link(&objects).map_err(|errs| {
+        for err in errs {
+            eprintln!("{}", err);
+        }
+    })?;
+    fs::write(&output_path, program.hack()).map_err(|err| {
+        eprintln!("writing to {}: {}", output_path, err);
+    })?;
+
+    println!(
+        "Linked {} objects successfully, wrote to {}",
+        objects.len(),
+        output_path
+    );
+    Ok(())
+}
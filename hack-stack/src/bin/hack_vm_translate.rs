@@ -15,10 +15,22 @@ fn main() {
 }
 
 fn translate_main() -> Result<(), ()> {
-    let args = std::env::args().collect::<Vec<String>>();
-    let path_arg = args.get(1).ok_or_else(|| {
-        eprintln!("usage: hack-vm-translate PATH");
+    let args_and_opts = std::env::args().collect::<Vec<String>>();
+    let (opts, args): (Vec<&String>, Vec<&String>) = args_and_opts
+        .iter()
+        .skip(1)
+        .partition(|&a| a.starts_with('-'));
+
+    let path_arg = *args.first().ok_or_else(|| {
+        eprintln!("usage: hack-vm-translate [--extended-isa] [--keep-unused] PATH");
     })?;
+    let instruction_set = if opts.iter().any(|o| *o == "--extended-isa") {
+        common::InstructionSet::Extended
+    } else {
+        common::InstructionSet::Hack
+    };
+    // Code that's linked with a separately assembled OS must keep the functions the OS calls
+    let keep_unused = opts.iter().any(|o| *o == "--keep-unused");
     let source_path = Path::new(path_arg).canonicalize().map_err(|err| {
         eprintln!("reading path {}: {}", path_arg, err);
     })?;
@@ -53,10 +65,12 @@ fn translate_main() -> Result<(), ()> {
         source_files.push(common::SourceFile::new(source, source_file_name.to_owned()));
     }
 
-    let output_asm =
-        vm::translate(&source_files, bootstrap, bootstrap).map_err(|(file, errs)| {
+    let dce = bootstrap && !keep_unused;
+    let output_asm = vm::translate_for(&source_files, bootstrap, dce, instruction_set).map_err(
+        |(file, errs)| {
             display_span_errors(file, errs);
-        })?;
+        },
+    )?;
 
     let output_path = if source_path.is_dir() {
         let dir_name = source_path.file_name().unwrap().to_str().unwrap();
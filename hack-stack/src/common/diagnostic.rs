@@ -0,0 +1,52 @@
+use super::{Note, Severity, SourceFile, SpanError};
+
+/// A `SpanError` resolved to a line and column, for reporting outside the compiler, e.g. in an
+/// editor.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub msg: String,
+    pub severity: Severity,
+    pub notes: Vec<DiagnosticNote>,
+}
+
+/// A `Note` resolved to a line and column.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DiagnosticNote {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub msg: String,
+}
+
+impl DiagnosticNote {
+    pub fn from_note(source_file: &SourceFile, note: &Note) -> Self {
+        let (line, column) = source_file.loc_for_byte_pos(note.span.start);
+        DiagnosticNote {
+            file: source_file.name.clone(),
+            line,
+            column,
+            msg: note.msg.clone(),
+        }
+    }
+}
+
+impl Diagnostic {
+    pub fn from_span_error(source_file: &SourceFile, err: &SpanError) -> Self {
+        let (line, column) = source_file.loc_for_byte_pos(err.span.start);
+        Diagnostic {
+            file: source_file.name.clone(),
+            line,
+            column,
+            msg: err.msg.clone(),
+            severity: err.severity,
+            notes: err
+                .notes
+                .iter()
+                .map(|note| DiagnosticNote::from_note(source_file, note))
+                .collect(),
+        }
+    }
+}
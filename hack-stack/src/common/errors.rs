@@ -1,13 +1,63 @@
+use std::fmt;
+
 use super::Span;
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    /// Something that's probably a mistake, but doesn't stop the program being built.
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        })
+    }
+}
+
+/// Points at another place that helps explain an error, e.g. where something was first defined.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Note {
+    pub msg: String,
+    pub span: Span,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct SpanError {
     pub msg: String,
     pub span: Span,
+    pub severity: Severity,
+    pub notes: Vec<Note>,
 }
 
 impl SpanError {
     pub fn new(msg: String, span: Span) -> Self {
-        SpanError { msg, span }
+        SpanError {
+            msg,
+            span,
+            severity: Severity::Error,
+            notes: vec![],
+        }
+    }
+
+    pub fn warning(msg: String, span: Span) -> Self {
+        SpanError {
+            msg,
+            span,
+            severity: Severity::Warning,
+            notes: vec![],
+        }
+    }
+
+    pub fn with_note(mut self, msg: String, span: Span) -> Self {
+        self.notes.push(Note { msg, span });
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
     }
 }
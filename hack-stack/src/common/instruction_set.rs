@@ -0,0 +1,21 @@
+/// The instructions a Hack CPU understands.
+///
+/// The extended instruction set adds shifts, which are C-instructions that start with `101`
+/// rather than `111`. Bit 12 selects A or M as usual, and the comp field is `c1 c2 0000`, where
+/// `c1` is 1 for a left shift and `c2` is 1 to shift D:
+///
+/// ```text
+/// A>>  101 0 000000    A<<  101 0 100000
+/// D>>  101 0 010000    D<<  101 0 110000
+/// M>>  101 1 000000    M<<  101 1 100000
+/// ```
+///
+/// Shifts move by one bit. Right shifts are arithmetic, so they keep the sign of the value.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum InstructionSet {
+    /// The instructions of the Hack computer built in the course.
+    #[default]
+    Hack,
+    /// The Hack instructions, plus shifts.
+    Extended,
+}
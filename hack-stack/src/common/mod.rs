@@ -1,9 +1,15 @@
 mod cursor;
+mod diagnostic;
 mod errors;
+mod instruction_set;
+mod reader;
 mod source_file;
 mod span;
 
 pub use cursor::{Cursor, EOF_CHAR};
-pub use errors::SpanError;
+pub use diagnostic::{Diagnostic, DiagnosticNote};
+pub use errors::{Note, Severity, SpanError};
+pub use instruction_set::InstructionSet;
+pub use reader::Reader;
 pub use source_file::SourceFile;
 pub use span::{Span, Spanned};
@@ -0,0 +1,87 @@
+/// Reads the little-endian binary formats the tools save, like `.hackx` files and emulator
+/// states. Errors name the format being read, e.g. "Unexpected end of emulator state".
+pub struct Reader<'a> {
+    pos: usize,
+    buf: &'a [u8],
+    format: &'static str,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8], format: &'static str) -> Self {
+        Reader {
+            pos: 0,
+            buf,
+            format,
+        }
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .pos
+            .checked_add(n)
+            .and_then(|end| self.buf.get(self.pos..end))
+            .ok_or_else(|| format!("Unexpected end of {}", self.format))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a u32 length followed by that many bytes of UTF-8.
+    pub fn string(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec())
+            .map_err(|_| format!("Invalid UTF-8 in {}", self.format))
+    }
+
+    /// Reads a u32 length followed by that many u16 words.
+    pub fn words(&mut self) -> Result<Vec<u16>, String> {
+        let len = self.u32()? as usize;
+        let bytes = self.bytes(len.saturating_mul(2))?;
+        Ok(bytes
+            .chunks_exact(2)
+            .map(|w| u16::from_le_bytes([w[0], w[1]]))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reader() {
+        let data = [7, 1, 2, 2, 0, 0, 0, b'h', b'i', 1, 0, 0, 0, 0xff, 0xff];
+        let mut reader = Reader::new(&data, "test file");
+        assert_eq!(reader.u8(), Ok(7));
+        assert_eq!(reader.u16(), Ok(0x0201));
+        assert_eq!(reader.string(), Ok("hi".to_owned()));
+        assert_eq!(reader.words(), Ok(vec![0xffff]));
+        assert!(reader.is_at_end());
+        assert_eq!(reader.u8(), Err("Unexpected end of test file".to_owned()));
+
+        let mut reader = Reader::new(&[1, 0, 0, 0, 0xff], "test file");
+        assert_eq!(
+            reader.string(),
+            Err("Invalid UTF-8 in test file".to_owned())
+        );
+        let mut reader = Reader::new(&[0xff, 0xff, 0xff, 0xff], "test file");
+        assert!(reader.words().is_err());
+    }
+}
@@ -0,0 +1,466 @@
+//! Runs many programs at once, e.g. to grade assignments or regression test a compiler.
+//!
+//! A batch is described by a manifest, which lists the programs to run, the RAM to set up before
+//! running them, how many cycles they may run for, and the RAM values expected afterwards:
+//!
+//! ```text
+//! # Comments start with a hash
+//! [add]
+//! rom Add.hack
+//! cycles 1000
+//! set 0 256
+//! expect 256 15
+//!
+//! [fill]
+//! rom Fill.hack
+//! cycles 100000
+//! keyboard 65
+//! lenient
+//! extended-isa
+//! expect 0x4000 -1
+//! ```
+//!
+//! `lenient` makes the whole address space RAM, and `extended-isa` enables the shift
+//! instructions (see [`crate::common::InstructionSet`]).
+//!
+//! Every program needs a `rom` and a `cycles` budget. Programs run until they reach their cycle
+//! budget or halt (see [`Emulator::is_halted`]), whichever comes first.
+
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{Emulator, EmulatorConfig};
+use crate::common::InstructionSet;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ManifestEntry {
+    pub name: String,
+    /// Path to the `.hack` file, as written in the manifest.
+    pub rom_path: String,
+    pub cycles: u64,
+    pub keyboard: u16,
+    pub lenient: bool,
+    pub extended_isa: bool,
+    pub inputs: Vec<(u16, u16)>,
+    pub expected: Vec<(u16, u16)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub name: String,
+    pub rom: Vec<u16>,
+    pub config: EmulatorConfig,
+    pub cycles: u64,
+    pub keyboard: u16,
+    pub inputs: Vec<(u16, u16)>,
+    pub expected: Vec<(u16, u16)>,
+}
+
+impl Job {
+    pub fn from_manifest_entry(entry: &ManifestEntry, rom: Vec<u16>) -> Self {
+        let mut config = if entry.lenient {
+            EmulatorConfig::extended()
+        } else {
+            EmulatorConfig::hack()
+        };
+        if entry.extended_isa {
+            config.instruction_set = InstructionSet::Extended;
+        }
+        Self {
+            name: entry.name.clone(),
+            rom,
+            config,
+            cycles: entry.cycles,
+            keyboard: entry.keyboard,
+            inputs: entry.inputs.clone(),
+            expected: entry.expected.clone(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Mismatch {
+    pub addr: u16,
+    pub expected: u16,
+    pub actual: u16,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Outcome {
+    Passed,
+    Failed(Vec<Mismatch>),
+    /// The program couldn't be run to completion, e.g. because it accessed memory out of bounds.
+    Error(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct JobResult {
+    pub name: String,
+    pub outcome: Outcome,
+    pub cycles: u64,
+    pub halted: bool,
+    pub duration: Duration,
+}
+
+pub fn run_job(job: &Job) -> JobResult {
+    let start = Instant::now();
+    // The program is loaded separately, so a program too big for the ROM fails the job
+    let mut emu = Emulator::with_config(vec![], job.config.clone()).unwrap();
+    let mut cycles = 0;
+
+    let setup = emu.load_rom(job.rom.clone()).and_then(|_| {
+        emu.set_keyboard(job.keyboard);
+        job.inputs
+            .iter()
+            .try_for_each(|&(addr, val)| emu.set_memory(addr, val))
+    });
+    let run = setup.and_then(|_| {
+        while cycles < job.cycles && !emu.is_halted() {
+            emu.step()?;
+            cycles += 1;
+        }
+        Ok(())
+    });
+
+    let outcome = match run {
+        Ok(()) => {
+            let mismatches = job
+                .expected
+                .iter()
+                .filter_map(|&(addr, expected)| {
+                    let actual = emu.memory().get(addr as usize).copied().unwrap_or(0);
+                    (actual != expected).then_some(Mismatch {
+                        addr,
+                        expected,
+                        actual,
+                    })
+                })
+                .collect::<Vec<Mismatch>>();
+            if mismatches.is_empty() {
+                Outcome::Passed
+            } else {
+                Outcome::Failed(mismatches)
+            }
+        }
+        Err(err) => Outcome::Error(format!("{} (after {} cycles)", err, cycles)),
+    };
+
+    JobResult {
+        name: job.name.clone(),
+        outcome,
+        cycles,
+        halted: emu.is_halted(),
+        duration: start.elapsed(),
+    }
+}
+
+/// Runs the jobs across `threads` threads, each with its own emulator. Results are returned in
+/// the same order as the jobs.
+pub fn run_batch(jobs: &[Job], threads: usize) -> Vec<JobResult> {
+    let next_job = AtomicUsize::new(0);
+    let results = Mutex::new(vec![None; jobs.len()]);
+
+    thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, jobs.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next_job.fetch_add(1, Ordering::Relaxed);
+                let Some(job) = jobs.get(i) else {
+                    return;
+                };
+                let result = run_job(job);
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every job has a result"))
+        .collect()
+}
+
+pub fn parse_manifest(src: &str) -> Result<Vec<ManifestEntry>, String> {
+    let mut entries: Vec<ManifestEntry> = vec![];
+
+    for (i, line) in src.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let err = |msg: &str| format!("line {}: {}", i + 1, msg);
+
+        if let Some(name) = line.strip_prefix('[') {
+            let name = name
+                .strip_suffix(']')
+                .ok_or_else(|| err("expected `]' after test name"))?;
+            entries.push(ManifestEntry {
+                name: name.trim().to_owned(),
+                rom_path: String::new(),
+                cycles: 0,
+                keyboard: 0,
+                lenient: false,
+                extended_isa: false,
+                inputs: vec![],
+                expected: vec![],
+            });
+            continue;
+        }
+
+        let entry = entries
+            .last_mut()
+            .ok_or_else(|| err("expected a `[name]' header before the first setting"))?;
+        let parts = line.split_whitespace().collect::<Vec<&str>>();
+        match parts.as_slice() {
+            ["rom", path] => entry.rom_path = (*path).to_owned(),
+            ["cycles", n] => {
+                entry.cycles = match n.parse() {
+                    Ok(0) => return Err(err("the cycle count must be at least 1")),
+                    Ok(n) => n,
+                    Err(_) => return Err(err("invalid cycle count")),
+                };
+            }
+            ["keyboard", val] => entry.keyboard = parse_word(val).map_err(|e| err(&e))?,
+            ["lenient"] => entry.lenient = true,
+            ["extended-isa"] => entry.extended_isa = true,
+            ["set", addr, val] | ["expect", addr, val] => {
+                let addr = parse_word(addr).map_err(|e| err(&e))?;
+                let val = parse_word(val).map_err(|e| err(&e))?;
+                if parts[0] == "set" {
+                    entry.inputs.push((addr, val));
+                } else {
+                    entry.expected.push((addr, val));
+                }
+            }
+            _ => return Err(err(&format!("unrecognized setting `{}'", line))),
+        }
+    }
+
+    for entry in &entries {
+        if entry.rom_path.is_empty() {
+            return Err(format!("{}: missing `rom' setting", entry.name));
+        }
+        // Running no cycles would check the expectations against the initial RAM
+        if entry.cycles == 0 {
+            return Err(format!("{}: missing `cycles' setting", entry.name));
+        }
+    }
+    Ok(entries)
+}
+
+// Parses a decimal (possibly negative) or 0x-prefixed hex word
+fn parse_word(s: &str) -> Result<u16, String> {
+    let parsed = match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s
+            .parse::<u16>()
+            .ok()
+            .or_else(|| s.parse::<i16>().ok().map(|n| n as u16)),
+    };
+    parsed.ok_or_else(|| format!("invalid value `{}'", s))
+}
+
+pub fn junit_report(results: &[JobResult]) -> String {
+    let count = |f: fn(&Outcome) -> bool| results.iter().filter(|r| f(&r.outcome)).count();
+    let total_time: Duration = results.iter().map(|r| r.duration).sum();
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    writeln!(
+        out,
+        "<testsuite name=\"hack-emulate\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">",
+        results.len(),
+        count(|o| matches!(o, Outcome::Failed(_))),
+        count(|o| matches!(o, Outcome::Error(_))),
+        total_time.as_secs_f64()
+    )
+    .unwrap();
+
+    for result in results {
+        write!(
+            out,
+            "  <testcase name=\"{}\" time=\"{:.3}\"",
+            xml_escape(&result.name),
+            result.duration.as_secs_f64()
+        )
+        .unwrap();
+        match &result.outcome {
+            Outcome::Passed => out.push_str("/>\n"),
+            Outcome::Failed(mismatches) => {
+                let details = mismatches
+                    .iter()
+                    .map(describe_mismatch)
+                    .collect::<Vec<String>>()
+                    .join("\n");
+                writeln!(
+                    out,
+                    ">\n    <failure message=\"{} RAM value(s) differ\">{}</failure>\n  </testcase>",
+                    mismatches.len(),
+                    xml_escape(&details)
+                )
+                .unwrap();
+            }
+            Outcome::Error(err) => {
+                writeln!(
+                    out,
+                    ">\n    <error message=\"{}\"/>\n  </testcase>",
+                    xml_escape(err)
+                )
+                .unwrap();
+            }
+        }
+    }
+    out.push_str("</testsuite>\n");
+    out
+}
+
+pub fn json_report(results: &[JobResult]) -> String {
+    let tests = results
+        .iter()
+        .map(|result| {
+            let (status, details) = match &result.outcome {
+                Outcome::Passed => ("passed", String::new()),
+                Outcome::Failed(mismatches) => {
+                    let mismatches = mismatches
+                        .iter()
+                        .map(|m| {
+                            format!(
+                                "{{\"address\":{},\"expected\":{},\"actual\":{}}}",
+                                m.addr, m.expected, m.actual
+                            )
+                        })
+                        .collect::<Vec<String>>()
+                        .join(",");
+                    ("failed", format!(",\"mismatches\":[{}]", mismatches))
+                }
+                Outcome::Error(err) => ("error", format!(",\"error\":{}", json_string(err))),
+            };
+            format!(
+                "{{\"name\":{},\"status\":\"{}\",\"cycles\":{},\"halted\":{},\"time\":{:.3}{}}}",
+                json_string(&result.name),
+                status,
+                result.cycles,
+                result.halted,
+                result.duration.as_secs_f64(),
+                details
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(",\n    ");
+    format!("{{\n  \"tests\": [\n    {}\n  ]\n}}\n", tests)
+}
+
+fn describe_mismatch(m: &Mismatch) -> String {
+    format!(
+        "RAM[{}]: expected {}, got {}",
+        m.addr, m.expected as i16, m.actual as i16
+    )
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::parse_rom;
+
+    // RAM[2] = RAM[0] + RAM[1], then halt
+    const ADD: &str = "0000000000000000
+1111110000010000
+0000000000000001
+1111000010010000
+0000000000000010
+1110001100001000
+0000000000000110
+1110101010000111";
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = parse_manifest(
+            "# A comment\n[add]\nrom Add.hack # trailing comment\ncycles 100\nset 0 2\nset 1 -3\nexpect 2 0xffff\n\n[kbd]\nrom Kbd.hack\ncycles 50\nkeyboard 65\nlenient\nextended-isa\n",
+        )
+        .unwrap();
+        assert_eq!(manifest.len(), 2);
+        assert_eq!(manifest[0].rom_path, "Add.hack");
+        assert_eq!(manifest[0].cycles, 100);
+        assert_eq!(manifest[0].inputs, vec![(0, 2), (1, 0xfffd)]);
+        assert_eq!(manifest[0].expected, vec![(2, 0xffff)]);
+        assert_eq!(manifest[1].keyboard, 65);
+        assert!(manifest[1].lenient);
+        assert!(manifest[1].extended_isa);
+
+        assert!(parse_manifest("rom Add.hack").is_err());
+        assert!(parse_manifest("[add]\ncycles 10").is_err());
+        assert_eq!(
+            parse_manifest("[add]\nrom Add.hack\n").unwrap_err(),
+            "add: missing `cycles' setting"
+        );
+        assert!(parse_manifest("[add]\nrom Add.hack\ncycles 0").is_err());
+        assert!(parse_manifest("[add]\nrom Add.hack\nset 0").is_err());
+    }
+
+    #[test]
+    fn test_run_batch() {
+        let job = |name: &str, expected: u16| Job {
+            name: name.to_owned(),
+            rom: parse_rom(ADD).unwrap(),
+            config: EmulatorConfig::default(),
+            cycles: 1000,
+            keyboard: 0,
+            inputs: vec![(0, 2), (1, 3)],
+            expected: vec![(2, expected)],
+        };
+        let mut out_of_bounds = job("error", 0);
+        out_of_bounds.inputs.push((0x7000, 1));
+        let jobs = vec![job("pass", 5), job("fail", 6), out_of_bounds];
+
+        let results = run_batch(&jobs, 2);
+        assert_eq!(results[0].name, "pass");
+        assert_eq!(results[0].outcome, Outcome::Passed);
+        assert!(results[0].halted);
+        assert_eq!(results[0].cycles, 6);
+        assert_eq!(
+            results[1].outcome,
+            Outcome::Failed(vec![Mismatch {
+                addr: 2,
+                expected: 6,
+                actual: 5
+            }])
+        );
+        assert!(matches!(results[2].outcome, Outcome::Error(_)));
+
+        let junit = junit_report(&results);
+        assert!(junit.contains("tests=\"3\" failures=\"1\" errors=\"1\""));
+        assert!(junit.contains("RAM[2]: expected 6, got 5"));
+        let json = json_report(&results);
+        assert!(json.contains("\"name\":\"fail\",\"status\":\"failed\""));
+        assert!(json.contains("{\"address\":2,\"expected\":6,\"actual\":5}"));
+    }
+}
@@ -0,0 +1,180 @@
+/// The rate at which front-ends should redraw the screen and poll for input.
+pub const FRAME_RATE: u32 = 60;
+
+pub const DEFAULT_FREQUENCY: u32 = 10_000_000;
+
+// If the host falls behind (e.g. a browser tab was in the background, or the host can't keep up
+// with the target frequency), don't try to catch up on more than this many seconds at once.
+const MAX_CATCH_UP: f64 = 0.25;
+
+// How often the effective frequency measurement is updated, in seconds.
+const MEASUREMENT_WINDOW: f64 = 1.0;
+
+/// Paces emulation to a target instruction rate.
+///
+/// The clock doesn't read the time itself, as there's no portable clock across native and Wasm
+/// builds. Instead, callers pass in the current time in seconds (from any fixed starting point),
+/// and the clock works out how many instructions should run to keep up.
+pub struct Clock {
+    frequency: u32,
+    last_tick: Option<f64>,
+    // Fractional cycles left over from the previous tick, so low frequencies still progress
+    remainder: f64,
+    window_start: Option<f64>,
+    window_cycles: u64,
+    effective_frequency: f64,
+}
+
+impl Clock {
+    pub fn new(frequency: u32) -> Self {
+        Self {
+            frequency,
+            last_tick: None,
+            remainder: 0.0,
+            window_start: None,
+            window_cycles: 0,
+            effective_frequency: 0.0,
+        }
+    }
+
+    pub fn frequency(&self) -> u32 {
+        self.frequency
+    }
+
+    pub fn set_frequency(&mut self, frequency: u32) {
+        self.frequency = frequency;
+    }
+
+    /// Forgets the time of the last tick, e.g. when the emulator is paused, so the time spent
+    /// paused isn't caught up on when it's resumed.
+    pub fn reset(&mut self) {
+        self.last_tick = None;
+        self.remainder = 0.0;
+        self.window_start = None;
+        self.window_cycles = 0;
+        self.effective_frequency = 0.0;
+    }
+
+    /// Returns the number of instructions that should be executed to keep pace with the target
+    /// frequency at time `now`. The first tick after creating or resetting the clock runs one
+    /// frame's worth of instructions.
+    pub fn tick(&mut self, now: f64) -> u64 {
+        let Some(last_tick) = self.last_tick.replace(now) else {
+            self.window_start = Some(now);
+            self.window_cycles = 0;
+            return self.frequency as u64 / FRAME_RATE as u64;
+        };
+
+        let elapsed = (now - last_tick).clamp(0.0, MAX_CATCH_UP);
+        let cycles = self.frequency as f64 * elapsed + self.remainder;
+        // Round away floating point error so e.g. 29.9999999 cycles counts as 30
+        let whole_cycles = (cycles + 1e-6).floor();
+        self.remainder = (cycles - whole_cycles).max(0.0);
+        let cycles = whole_cycles as u64;
+
+        let window_start = *self.window_start.get_or_insert(now);
+        self.window_cycles += cycles;
+        if now - window_start >= MEASUREMENT_WINDOW {
+            self.effective_frequency = self.window_cycles as f64 / (now - window_start);
+            self.window_start = Some(now);
+            self.window_cycles = 0;
+        }
+
+        cycles
+    }
+
+    /// Returns how long to wait, in seconds, before the next frame is due.
+    pub fn frame_delay(&self, now: f64) -> f64 {
+        match self.last_tick {
+            Some(last_tick) => (last_tick + 1.0 / FRAME_RATE as f64 - now).max(0.0),
+            None => 0.0,
+        }
+    }
+
+    /// The instruction rate actually achieved over the last measurement window. This falls
+    /// below the target frequency when the host can't keep up.
+    pub fn effective_frequency(&self) -> f64 {
+        self.effective_frequency
+    }
+}
+
+/// Parses a frequency such as `500000`, `500kHz` or `10MHz` into a number of hertz, which must be
+/// at least 1.
+pub fn parse_frequency(s: &str) -> Result<u32, String> {
+    let lower = s.to_ascii_lowercase();
+    let (num, multiplier) = if let Some(num) = lower.strip_suffix("mhz") {
+        (num, 1_000_000.0)
+    } else if let Some(num) = lower.strip_suffix("khz") {
+        (num, 1_000.0)
+    } else {
+        (lower.strip_suffix("hz").unwrap_or(&lower), 1.0)
+    };
+
+    match num.trim().parse::<f64>() {
+        Ok(n) if n * multiplier >= 1.0 && n * multiplier <= u32::MAX as f64 => {
+            Ok((n * multiplier) as u32)
+        }
+        _ => Err(format!("invalid frequency {}", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick() {
+        let mut clock = Clock::new(6000);
+        assert_eq!(clock.tick(10.0), 100);
+        assert_eq!(clock.tick(10.2), 1200);
+        // Time going backwards runs nothing
+        assert_eq!(clock.tick(10.1), 0);
+        // Long pauses are capped
+        assert_eq!(clock.tick(20.0), 1500);
+
+        clock.reset();
+        assert_eq!(clock.tick(30.0), 100);
+    }
+
+    #[test]
+    fn test_tick_carries_remainder() {
+        let mut clock = Clock::new(30);
+        clock.tick(0.0);
+        let total: u64 = (1..=100).map(|i| clock.tick(i as f64 / 100.0)).sum();
+        assert_eq!(total, 30);
+    }
+
+    #[test]
+    fn test_effective_frequency() {
+        let mut clock = Clock::new(1000);
+        clock.tick(0.0);
+        for i in 1..=10 {
+            clock.tick(i as f64 * 0.1);
+        }
+        assert_eq!(clock.effective_frequency().round(), 1000.0);
+    }
+
+    #[test]
+    fn test_frame_delay() {
+        let mut clock = Clock::new(1000);
+        assert_eq!(clock.frame_delay(0.0), 0.0);
+        clock.tick(1.0);
+        assert!((clock.frame_delay(1.01) - (1.0 / 60.0 - 0.01)).abs() < 1e-9);
+        assert_eq!(clock.frame_delay(2.0), 0.0);
+    }
+
+    #[test]
+    fn test_parse_frequency() {
+        assert_eq!(parse_frequency("1000"), Ok(1000));
+        assert_eq!(parse_frequency("500kHz"), Ok(500_000));
+        assert_eq!(parse_frequency("1.5MHz"), Ok(1_500_000));
+        assert_eq!(parse_frequency("10mhz"), Ok(10_000_000));
+        assert_eq!(parse_frequency("20Hz"), Ok(20));
+        assert!(parse_frequency("fast").is_err());
+        assert!(parse_frequency("0").is_err());
+        // Fractions of a hertz would truncate to a clock that never ticks
+        assert!(parse_frequency("0.5").is_err());
+        assert!(parse_frequency("0.0009kHz").is_err());
+        assert_eq!(parse_frequency("1.5"), Ok(1));
+    }
+}
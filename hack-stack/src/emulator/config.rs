@@ -0,0 +1,63 @@
+use crate::common::InstructionSet;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MemoryMode {
+    /// Only addresses up to and including the keyboard register exist, as on the Hack computer
+    /// built in the course. Reading or writing anywhere above it is an error.
+    Strict,
+    /// Every address below the RAM size is plain RAM, including those above the keyboard.
+    Lenient,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct EmulatorConfig {
+    /// Number of words of RAM, including the memory-mapped screen and keyboard.
+    pub ram_size: usize,
+    /// Maximum number of instructions in ROM. Addresses past the end of a loaded program but
+    /// within the ROM size read as zero, like unprogrammed ROM.
+    pub rom_size: usize,
+    pub screen_base: u16,
+    pub keyboard_addr: u16,
+    pub memory_mode: MemoryMode,
+    pub instruction_set: InstructionSet,
+}
+
+impl EmulatorConfig {
+    /// The Hack computer as specified by the course: 16K words of RAM, followed by the screen
+    /// and keyboard, and a 32K word ROM.
+    pub fn hack() -> Self {
+        Self {
+            ram_size: 0x6001,
+            rom_size: 0x8000,
+            screen_base: 0x4000,
+            keyboard_addr: 0x6000,
+            memory_mode: MemoryMode::Strict,
+            instruction_set: InstructionSet::Hack,
+        }
+    }
+
+    /// The Hack memory map, with the full 15-bit address space usable as RAM.
+    pub fn extended() -> Self {
+        Self {
+            ram_size: 0x8000,
+            memory_mode: MemoryMode::Lenient,
+            ..Self::hack()
+        }
+    }
+
+    /// Returns true if `addr` is in RAM or a memory-mapped register, so it can be read and
+    /// written. Anything else is an error to access.
+    pub fn is_mapped(&self, addr: u16) -> bool {
+        let in_ram = (addr as usize) < self.ram_size;
+        match self.memory_mode {
+            MemoryMode::Strict => in_ram && addr <= self.keyboard_addr,
+            MemoryMode::Lenient => in_ram,
+        }
+    }
+}
+
+impl Default for EmulatorConfig {
+    fn default() -> Self {
+        Self::hack()
+    }
+}
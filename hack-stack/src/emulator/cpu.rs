@@ -1,3 +1,5 @@
+use crate::common::InstructionSet;
+
 #[derive(Debug)]
 pub struct Cpu {
     pub d: u16,
@@ -5,6 +7,7 @@ pub struct Cpu {
     pub m: u16,
     pub pc: u16,
     pub write_m: bool,
+    pub instruction_set: InstructionSet,
 }
 
 impl Cpu {
@@ -15,6 +18,7 @@ impl Cpu {
             m: 0,
             pc: 0,
             write_m: false,
+            instruction_set: InstructionSet::Hack,
         }
     }
 
@@ -30,6 +34,9 @@ impl Cpu {
 
         if instruction & 0x8000 == 0 {
             self.execute_a_instruction(instruction);
+        } else if self.instruction_set == InstructionSet::Extended && instruction & 0xE000 == 0xA000
+        {
+            self.execute_shift_instruction(instruction)?;
         } else {
             self.execute_c_instruction(instruction)?;
         }
@@ -77,6 +84,32 @@ impl Cpu {
             _ => return Err(format!("no such operation {:#b}", comp_bits)),
         };
 
+        self.write_result(instruction, alu_result);
+        Ok(())
+    }
+
+    /// Executes a shift from the extended instruction set.
+    #[allow(clippy::unusual_byte_groupings)]
+    pub fn execute_shift_instruction(&mut self, instruction: u16) -> Result<(), String> {
+        let comp_bits = (instruction >> 6) & 0b1111111;
+        let result = match comp_bits {
+            0b0_000000 => ((self.a as i16) >> 1) as u16, // A>>
+            0b0_010000 => ((self.d as i16) >> 1) as u16, // D>>
+            0b1_000000 => ((self.m as i16) >> 1) as u16, // M>>
+            0b0_100000 => self.a << 1,                   // A<<
+            0b0_110000 => self.d << 1,                   // D<<
+            0b1_100000 => self.m << 1,                   // M<<
+            _ => return Err(format!("no such shift {:#b}", comp_bits)),
+        };
+        self.write_result(instruction, result);
+        Ok(())
+    }
+
+    // Stores the result in the instruction's destinations, and jumps if its condition holds. Like
+    // the Hack hardware, a jump goes to the address A held before the instruction, even if the
+    // instruction also assigns to A.
+    fn write_result(&mut self, instruction: u16, alu_result: u16) {
+        let old_a = self.a;
         let dest_bits = (instruction >> 3) & 0b111;
         if dest_bits & 0b010 != 0 {
             self.d = alu_result
@@ -103,12 +136,10 @@ impl Cpu {
         };
 
         if jump {
-            self.pc = self.a;
+            self.pc = old_a;
         } else {
             self.pc += 1;
         }
-
-        Ok(())
     }
 
     pub fn next_m(&self) -> Option<u16> {
@@ -209,4 +240,16 @@ mod tests {
         assert_eq!(cpu.m, 124);
         assert!(cpu.write_m);
     }
+
+    #[test]
+    fn test_jump_assigning_a() {
+        let mut cpu = Cpu::new();
+        cpu.execute(assemble_one("@100")).unwrap();
+        cpu.execute(assemble_one("A=A+1;JMP")).unwrap();
+        assert_eq!((cpu.a, cpu.pc), (101, 100));
+
+        cpu.d = 5;
+        cpu.execute(assemble_one("AD=D-1;JGT")).unwrap();
+        assert_eq!((cpu.a, cpu.d, cpu.pc), (4, 4, 101));
+    }
 }
@@ -0,0 +1,400 @@
+use std::collections::BTreeSet;
+
+use super::Emulator;
+use crate::jack::{
+    debug_info::{ClassDebugInfo, Variable},
+    symbol_table::SymbolKind,
+};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum StopReason {
+    /// The program counter reached a breakpoint. The instruction there hasn't run yet.
+    Breakpoint,
+    /// A watched address was written to.
+    Watchpoint {
+        addr: u16,
+        old: u16,
+        new: u16,
+    },
+    /// The program reached its final infinite loop (see [`Emulator::is_halted`]).
+    Halted,
+    /// The cycle budget ran out without anything else stopping the program.
+    CycleLimit,
+    Error(String),
+}
+
+/// A function on the call stack, and the ROM address it's currently executing: the program
+/// counter for the innermost frame, and the return address for the others.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Frame {
+    pub function: Option<String>,
+    pub addr: u16,
+}
+
+/// A Jack variable and its current value.
+#[derive(Debug, PartialEq, Clone)]
+pub struct VariableValue {
+    pub name: String,
+    pub ty: String,
+    pub kind: SymbolKind,
+    pub value: u16,
+}
+
+/// The variables of the Jack subroutine that's currently running.
+#[derive(Debug, PartialEq, Clone)]
+pub struct JackFrame {
+    pub function: String,
+    pub args: Vec<VariableValue>,
+    pub locals: Vec<VariableValue>,
+    /// The fields of `this`, for methods and constructors.
+    pub fields: Vec<VariableValue>,
+}
+
+// Frames deeper than this are almost certainly garbage from walking an uninitialised stack
+const MAX_CALL_STACK_DEPTH: usize = 256;
+
+/// Runs an emulator with breakpoints and watchpoints, and maps ROM addresses back to the labels
+/// the program was assembled from.
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: BTreeSet<u16>,
+    // Sorted by address
+    symbols: Vec<(String, u16)>,
+    debug_info: Vec<ClassDebugInfo>,
+    // The breakpoint the last run stopped at, which the next run steps over rather than stopping
+    // at again. It's forgotten as soon as an instruction runs.
+    stopped_at: Option<u16>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            symbols: vec![],
+            debug_info: vec![],
+            stopped_at: None,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    pub fn has_breakpoint(&self, addr: u16) -> bool {
+        self.breakpoints.contains(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, addr: u16) {
+        self.watchpoints.insert(addr);
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: u16) {
+        self.watchpoints.remove(&addr);
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.watchpoints.iter().copied()
+    }
+
+    /// Sets the labels used to name functions, e.g. from `asm::Codegen::labels`.
+    pub fn set_symbols(&mut self, mut symbols: Vec<(String, u16)>) {
+        symbols.sort_by_key(|&(_, addr)| addr);
+        self.symbols = symbols;
+    }
+
+    /// Sets the Jack compiler's debug info, used by `jack_frame` to find variables.
+    pub fn set_debug_info(&mut self, debug_info: Vec<ClassDebugInfo>) {
+        self.debug_info = debug_info;
+    }
+
+    /// Returns the name of the function containing `addr`: the closest preceding label that
+    /// isn't internal to a function. The VM translator names labels within functions
+    /// `Function$label`, so any label containing a `$` is skipped.
+    pub fn function_at(&self, addr: u16) -> Option<&str> {
+        self.symbols
+            .iter()
+            .rev()
+            .filter(|(name, _)| !name.contains('$'))
+            .find(|&&(_, label_addr)| label_addr <= addr)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Runs until a breakpoint or watchpoint is hit, the program halts or fails, or `max_cycles`
+    /// instructions have run. Returns the number of instructions run and why it stopped. If the
+    /// last run stopped at a breakpoint, the next one starts by running the instruction there, so
+    /// the program can be resumed.
+    pub fn run(&mut self, emu: &mut Emulator, max_cycles: u64) -> (u64, StopReason) {
+        for cycles in 0..max_cycles {
+            let pc = emu.cpu.pc;
+            if self.breakpoints.contains(&pc) && self.stopped_at != Some(pc) {
+                self.stopped_at = Some(pc);
+                return (cycles, StopReason::Breakpoint);
+            }
+            if emu.is_halted() {
+                return (cycles, StopReason::Halted);
+            }
+
+            let addr = emu.cpu.a;
+            let old = emu.memory().get(addr as usize).copied().unwrap_or(0);
+            self.stopped_at = None;
+            if let Err(err) = emu.step() {
+                return (cycles, StopReason::Error(err));
+            }
+            if emu.cpu.write_m && self.watchpoints.contains(&addr) {
+                let new = emu.memory()[addr as usize];
+                return (cycles + 1, StopReason::Watchpoint { addr, old, new });
+            }
+        }
+        (max_cycles, StopReason::CycleLimit)
+    }
+
+    /// Runs `n` instructions, ignoring breakpoints and watchpoints.
+    pub fn step(&mut self, emu: &mut Emulator, n: u64) -> Result<(), String> {
+        if n > 0 {
+            self.stopped_at = None;
+        }
+        emu.step_n(n)
+    }
+
+    /// Forgets that the last run stopped at a breakpoint, e.g. because a different program or
+    /// state was loaded, so a breakpoint at the program counter stops the next run.
+    pub fn clear_stop(&mut self) {
+        self.stopped_at = None;
+    }
+
+    /// Reads the variables of the Jack subroutine containing the program counter from its
+    /// segments in RAM. Returns `None` if there's no debug info for the current function.
+    ///
+    /// The values are only meaningful once the function's preamble has run: its locals are
+    /// initialised by the `function` command, and a method's `this` is set by its first few
+    /// instructions.
+    pub fn jack_frame(&self, emu: &Emulator) -> Option<JackFrame> {
+        let function = self.function_at(emu.cpu.pc)?;
+        let class_name = function.split('.').next()?;
+        let class = self
+            .debug_info
+            .iter()
+            .find(|class| class.name == class_name)?;
+        let subroutine = class
+            .subroutines
+            .iter()
+            .find(|subroutine| subroutine.name == function)?;
+
+        let memory = emu.memory();
+        let values = |vars: &[Variable]| {
+            vars.iter()
+                .map(|var| VariableValue {
+                    name: var.name.clone(),
+                    ty: var.ty.clone(),
+                    kind: var.kind,
+                    value: read_variable(memory, var),
+                })
+                .collect::<Vec<VariableValue>>()
+        };
+        let fields = if subroutine.has_this {
+            values(&class.fields)
+        } else {
+            vec![]
+        };
+        Some(JackFrame {
+            function: function.to_owned(),
+            args: values(&subroutine.args),
+            locals: values(&subroutine.locals),
+            fields,
+        })
+    }
+
+    /// Reconstructs the call stack by following the frames laid out by the VM translator's
+    /// calling convention, innermost first. Each frame starts at LCL, preceded by the return
+    /// address and the caller's saved LCL, ARG, THIS and THAT.
+    pub fn call_stack(&self, emu: &Emulator) -> Vec<Frame> {
+        let memory = emu.memory();
+        let word = |addr: usize| memory.get(addr).copied().unwrap_or(0);
+
+        let mut frames = vec![Frame {
+            function: self.function_at(emu.cpu.pc).map(str::to_owned),
+            addr: emu.cpu.pc,
+        }];
+        let mut lcl = word(1) as usize;
+        while lcl >= 5 && frames.len() < MAX_CALL_STACK_DEPTH {
+            let return_addr = word(lcl - 5);
+            let caller_lcl = word(lcl - 4) as usize;
+            frames.push(Frame {
+                function: self.function_at(return_addr).map(str::to_owned),
+                addr: return_addr,
+            });
+            // Frames grow upwards, so anything else means we've walked off the stack
+            if caller_lcl >= lcl {
+                break;
+            }
+            lcl = caller_lcl;
+        }
+        frames
+    }
+}
+
+fn read_variable(memory: &[u16], var: &Variable) -> u16 {
+    let word = |addr: usize| memory.get(addr).copied().unwrap_or(0);
+    // The pointers to the VM segments, as laid out by the VM translator
+    let pointer = match var.kind.segment_name() {
+        "local" => 1,
+        "argument" => 2,
+        "this" => 3,
+        _ => return 0,
+    };
+    word(word(pointer) as usize + var.index as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{common::SourceFile, emulator::parse_rom, toolchain};
+
+    fn build(main_src: &str) -> (Emulator, Debugger) {
+        let files = [
+            SourceFile::new(
+                "class Sys { function void init() { do Main.main(); return; } }".to_owned(),
+                "Sys.jack".to_owned(),
+            ),
+            SourceFile::new(main_src.to_owned(), "Main.jack".to_owned()),
+        ];
+        let program = toolchain::build(&files).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.set_symbols(program.symbols);
+        debugger.set_debug_info(program.debug_info);
+        (Emulator::new(parse_rom(&program.hack).unwrap()), debugger)
+    }
+
+    const MAIN: &str = "class Main {
+        static int x;
+        function void main() { let x = Main.double(21); return; }
+        function int double(int n) { return n + n; }
+    }";
+
+    #[test]
+    fn test_breakpoint_and_call_stack() {
+        let (mut emu, mut debugger) = build(MAIN);
+        let double = debugger
+            .symbols
+            .iter()
+            .find(|(n, _)| n == "Main.double")
+            .unwrap()
+            .1;
+        debugger.add_breakpoint(double);
+
+        let (_, reason) = debugger.run(&mut emu, 10_000);
+        assert_eq!(reason, StopReason::Breakpoint);
+        assert_eq!(emu.cpu.pc, double);
+        assert_eq!(debugger.function_at(emu.cpu.pc), Some("Main.double"));
+
+        let functions = debugger
+            .call_stack(&emu)
+            .into_iter()
+            .map(|frame| frame.function)
+            .collect::<Vec<Option<String>>>();
+        assert_eq!(functions[0].as_deref(), Some("Main.double"));
+        assert_eq!(functions[1].as_deref(), Some("Main.main"));
+        assert_eq!(functions[2].as_deref(), Some("Sys.init"));
+
+        // Resuming from the breakpoint runs to the end
+        let (_, reason) = debugger.run(&mut emu, 10_000);
+        assert_eq!(reason, StopReason::Halted);
+    }
+
+    #[test]
+    fn test_breakpoint_after_cycle_limit() {
+        let (mut emu, mut debugger) = build(MAIN);
+        let mut ahead = build(MAIN).0;
+        ahead.step_n(20).unwrap();
+        let pc = ahead.cpu.pc;
+        debugger.add_breakpoint(pc);
+
+        // A run that ends just as the program counter reaches a breakpoint doesn't skip it
+        assert_eq!(debugger.run(&mut emu, 10), (10, StopReason::CycleLimit));
+        assert_eq!(debugger.run(&mut emu, 10), (10, StopReason::CycleLimit));
+        assert_eq!(emu.cpu.pc, pc);
+        assert_eq!(debugger.run(&mut emu, 10), (0, StopReason::Breakpoint));
+        // Resuming runs the instruction at the breakpoint
+        assert_eq!(debugger.run(&mut emu, 1), (1, StopReason::CycleLimit));
+
+        // Clearing the stop, e.g. when another program is loaded, means it isn't stepped over
+        assert_eq!(debugger.run(&mut ahead, 10), (0, StopReason::Breakpoint));
+        debugger.clear_stop();
+        assert_eq!(debugger.run(&mut ahead, 10), (0, StopReason::Breakpoint));
+        debugger.step(&mut ahead, 1).unwrap();
+        assert_ne!(ahead.cpu.pc, pc);
+    }
+
+    #[test]
+    fn test_watchpoint() {
+        let (mut emu, mut debugger) = build(MAIN);
+        // Main.x is the first static variable
+        debugger.add_watchpoint(16);
+
+        let (_, reason) = debugger.run(&mut emu, 10_000);
+        assert_eq!(
+            reason,
+            StopReason::Watchpoint {
+                addr: 16,
+                old: 0,
+                new: 42
+            }
+        );
+        assert_eq!(debugger.run(&mut emu, 3).1, StopReason::CycleLimit);
+    }
+
+    #[test]
+    fn test_jack_frame() {
+        let (mut emu, mut debugger) = build(
+            "class Main {
+                field int count;
+                field boolean done;
+                function void main() {
+                    var Main m;
+                    let m = 3000;
+                    do m.add(5, 7);
+                    return;
+                }
+                method void add(int a, int b) {
+                    var int sum;
+                    let sum = a + b;
+                    let count = sum;
+                    let done = true;
+                    return;
+                }
+            }",
+        );
+        // Stop once the last variable has been set
+        debugger.add_watchpoint(3001);
+        let (_, reason) = debugger.run(&mut emu, 10_000);
+        assert!(matches!(reason, StopReason::Watchpoint { .. }));
+
+        let frame = debugger.jack_frame(&emu).unwrap();
+        assert_eq!(frame.function, "Main.add");
+        fn values(vars: &[VariableValue]) -> Vec<(&str, &str, u16)> {
+            vars.iter()
+                .map(|var| (var.name.as_str(), var.ty.as_str(), var.value))
+                .collect()
+        }
+        // The arguments start at index 1, after `this`
+        assert_eq!(values(&frame.args), vec![("a", "int", 5), ("b", "int", 7)]);
+        assert_eq!(frame.args[0].kind, SymbolKind::Arg);
+        assert_eq!(values(&frame.locals), vec![("sum", "int", 12)]);
+        assert_eq!(
+            values(&frame.fields),
+            vec![("count", "int", 12), ("done", "boolean", 0xffff)]
+        );
+
+        debugger.set_debug_info(vec![]);
+        assert_eq!(debugger.jack_frame(&emu), None);
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::VecDeque;
+
+// Key codes from the Hack keyboard's character set, for keys that aren't plain ASCII
+pub const KEY_NEWLINE: u16 = 128;
+pub const KEY_BACKSPACE: u16 = 129;
+pub const KEY_LEFT: u16 = 130;
+pub const KEY_UP: u16 = 131;
+pub const KEY_RIGHT: u16 = 132;
+pub const KEY_DOWN: u16 = 133;
+pub const KEY_HOME: u16 = 134;
+pub const KEY_END: u16 = 135;
+pub const KEY_PAGE_UP: u16 = 136;
+pub const KEY_PAGE_DOWN: u16 = 137;
+pub const KEY_INSERT: u16 = 138;
+pub const KEY_DELETE: u16 = 139;
+pub const KEY_ESCAPE: u16 = 140;
+pub const KEY_F1: u16 = 141;
+
+/// Maps a DOM `KeyboardEvent.key` value to a Hack key code.
+pub fn key_code_for_dom_key(key: &str) -> Option<u16> {
+    let code = match key {
+        "Enter" => KEY_NEWLINE,
+        "Backspace" => KEY_BACKSPACE,
+        "ArrowLeft" => KEY_LEFT,
+        "ArrowUp" => KEY_UP,
+        "ArrowRight" => KEY_RIGHT,
+        "ArrowDown" => KEY_DOWN,
+        "Home" => KEY_HOME,
+        "End" => KEY_END,
+        "PageUp" => KEY_PAGE_UP,
+        "PageDown" => KEY_PAGE_DOWN,
+        "Insert" => KEY_INSERT,
+        "Delete" => KEY_DELETE,
+        "Escape" => KEY_ESCAPE,
+        _ => {
+            if let Some(n) = key.strip_prefix('F').and_then(|n| n.parse::<u16>().ok()) {
+                return (1..=12).contains(&n).then(|| KEY_F1 + n - 1);
+            }
+            let mut chars = key.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c @ ' '..='~'), None) => c as u16,
+                _ => return None,
+            }
+        }
+    };
+    Some(code)
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum KeyEvent {
+    Press(u16),
+    Release(u16),
+}
+
+/// Buffers key presses and releases so that each one is visible to the program for a while,
+/// even if the key was pressed and released between two frames. Each change to the keyboard
+/// register is held for at least `min_hold_cycles` before the next queued change is applied.
+pub struct KeyQueue {
+    events: VecDeque<KeyEvent>,
+    // Keys currently held down, in the order they were pressed. The keyboard register shows the
+    // most recently pressed one.
+    held: Vec<u16>,
+    min_hold_cycles: u64,
+    cycles_since_change: u64,
+}
+
+impl KeyQueue {
+    pub fn new(min_hold_cycles: u64) -> Self {
+        Self {
+            events: VecDeque::new(),
+            held: vec![],
+            min_hold_cycles,
+            cycles_since_change: min_hold_cycles,
+        }
+    }
+
+    pub fn set_min_hold_cycles(&mut self, min_hold_cycles: u64) {
+        self.min_hold_cycles = min_hold_cycles;
+    }
+
+    pub fn press(&mut self, code: u16) {
+        self.events.push_back(KeyEvent::Press(code));
+    }
+
+    pub fn release(&mut self, code: u16) {
+        self.events.push_back(KeyEvent::Release(code));
+    }
+
+    /// Releases every key once the queued events have been applied, e.g. when the window loses
+    /// focus and release events won't arrive.
+    pub fn release_all(&mut self) {
+        let held = self
+            .held
+            .iter()
+            .copied()
+            .chain(self.events.iter().filter_map(|event| match *event {
+                KeyEvent::Press(code) => Some(code),
+                KeyEvent::Release(_) => None,
+            }))
+            .collect::<Vec<u16>>();
+        self.events.extend(held.into_iter().map(KeyEvent::Release));
+    }
+
+    /// The value the keyboard register should currently hold.
+    pub fn key_code(&self) -> u16 {
+        self.held.last().copied().unwrap_or(0)
+    }
+
+    /// Applies queued events that are due. Returns the number of cycles until the next queued
+    /// event is due, or `None` if the queue is empty.
+    pub fn apply_due_events(&mut self) -> Option<u64> {
+        while let Some(&event) = self.events.front() {
+            if self.cycles_since_change < self.min_hold_cycles {
+                return Some(self.min_hold_cycles - self.cycles_since_change);
+            }
+            self.events.pop_front();
+
+            let before = self.key_code();
+            match event {
+                KeyEvent::Press(code) => {
+                    if !self.held.contains(&code) {
+                        self.held.push(code);
+                    }
+                }
+                // The case of a letter can change between press and release if shift is
+                // pressed or released in between, so release both cases
+                KeyEvent::Release(code) => self.held.retain(|&held| {
+                    held != code && !(code < 128 && held < 128 && eq_ignore_case(held, code))
+                }),
+            }
+            if self.key_code() != before {
+                self.cycles_since_change = 0;
+            }
+        }
+        None
+    }
+
+    /// Records that `cycles` instructions have run with the current key code.
+    pub fn advance(&mut self, cycles: u64) {
+        self.cycles_since_change = self.cycles_since_change.saturating_add(cycles);
+    }
+}
+
+fn eq_ignore_case(a: u16, b: u16) -> bool {
+    (a as u8).eq_ignore_ascii_case(&(b as u8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_code_for_dom_key() {
+        assert_eq!(key_code_for_dom_key("a"), Some(97));
+        assert_eq!(key_code_for_dom_key("A"), Some(65));
+        assert_eq!(key_code_for_dom_key(" "), Some(32));
+        assert_eq!(key_code_for_dom_key("Enter"), Some(KEY_NEWLINE));
+        assert_eq!(key_code_for_dom_key("ArrowDown"), Some(KEY_DOWN));
+        assert_eq!(key_code_for_dom_key("F1"), Some(141));
+        assert_eq!(key_code_for_dom_key("F12"), Some(152));
+        assert_eq!(key_code_for_dom_key("F13"), None);
+        assert_eq!(key_code_for_dom_key("Shift"), None);
+        assert_eq!(key_code_for_dom_key("é"), None);
+    }
+
+    #[test]
+    fn test_short_presses_are_held() {
+        let mut queue = KeyQueue::new(100);
+        // Two quick taps, all queued before the next frame runs
+        queue.press(65);
+        queue.release(65);
+        queue.press(66);
+        queue.release(66);
+
+        assert_eq!(queue.apply_due_events(), Some(100));
+        assert_eq!(queue.key_code(), 65);
+        queue.advance(60);
+        assert_eq!(queue.apply_due_events(), Some(40));
+        assert_eq!(queue.key_code(), 65);
+        queue.advance(40);
+
+        // The release is applied, and the 0 is held so the program sees the key go up
+        assert_eq!(queue.apply_due_events(), Some(100));
+        assert_eq!(queue.key_code(), 0);
+        queue.advance(100);
+        assert_eq!(queue.apply_due_events(), Some(100));
+        assert_eq!(queue.key_code(), 66);
+        queue.advance(100);
+        assert_eq!(queue.apply_due_events(), None);
+        assert_eq!(queue.key_code(), 0);
+    }
+
+    #[test]
+    fn test_overlapping_keys() {
+        let mut queue = KeyQueue::new(0);
+        queue.press(KEY_LEFT);
+        queue.press(97);
+        queue.release(65);
+        assert_eq!(queue.apply_due_events(), None);
+        assert_eq!(queue.key_code(), KEY_LEFT);
+
+        queue.release_all();
+        queue.apply_due_events();
+        assert_eq!(queue.key_code(), 0);
+    }
+}
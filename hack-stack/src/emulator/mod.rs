@@ -1,30 +1,94 @@
 use self::cpu::Cpu;
+use crate::common::InstructionSet;
 
+pub mod batch;
+mod clock;
+mod config;
 mod cpu;
+pub mod debugger;
+pub mod keyboard;
+pub mod screen;
+mod state;
+
+pub use clock::{parse_frequency, Clock, DEFAULT_FREQUENCY, FRAME_RATE};
+pub use config::{EmulatorConfig, MemoryMode};
+pub use debugger::{Debugger, StopReason};
+pub use keyboard::KeyQueue;
+pub use screen::ScreenChanges;
+pub use state::is_state;
+
+/// Parses a program in the `.hack` format: one 16-digit binary instruction per line.
+pub fn parse_rom(src: &str) -> Result<Vec<u16>, String> {
+    src.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            u16::from_str_radix(line, 2).map_err(|_| format!("invalid instruction `{}'", line))
+        })
+        .collect()
+}
 
 pub struct Emulator {
     pub cpu: Cpu,
+    config: EmulatorConfig,
     rom: Vec<u16>,
     memory: Vec<u16>,
+    screen_changes: ScreenChanges,
 }
 
 impl Emulator {
+    /// Creates an emulator of the Hack computer. Panics if `rom` doesn't fit in its 32K word
+    /// ROM, which no program for it can exceed.
     pub fn new(rom: Vec<u16>) -> Self {
-        Self {
-            cpu: Cpu::new(),
-            rom,
-            memory: vec![0; 0x6001],
-        }
+        Self::with_config(rom, EmulatorConfig::default()).unwrap()
+    }
+
+    /// Creates an emulator of the computer described by `config`, or returns an error if `rom`
+    /// doesn't fit in its ROM.
+    pub fn with_config(rom: Vec<u16>, config: EmulatorConfig) -> Result<Self, String> {
+        let mut cpu = Cpu::new();
+        cpu.instruction_set = config.instruction_set;
+        let mut emu = Self {
+            cpu,
+            memory: vec![0; config.ram_size],
+            config,
+            rom: vec![],
+            screen_changes: ScreenChanges::all(),
+        };
+        emu.load_rom(rom)?;
+        Ok(emu)
+    }
+
+    pub fn config(&self) -> &EmulatorConfig {
+        &self.config
+    }
+
+    /// Sets the instructions the CPU understands, e.g. for a program that uses shifts.
+    pub fn set_instruction_set(&mut self, instruction_set: InstructionSet) {
+        self.config.instruction_set = instruction_set;
+        self.cpu.instruction_set = instruction_set;
     }
 
     pub fn memory(&self) -> &[u16] {
         &self.memory
     }
 
+    /// Returns the screen's memory map, or an empty slice if it doesn't fit in RAM.
+    pub fn screen(&self) -> &[u16] {
+        let start = self.config.screen_base as usize;
+        self.memory
+            .get(start..start + screen::SCREEN_WORDS)
+            .unwrap_or(&[])
+    }
+
+    pub fn rom(&self) -> &[u16] {
+        &self.rom
+    }
+
     pub fn step(&mut self) -> Result<(), String> {
         let instruction = self.fetch_instruction()?;
         let addr = self.cpu.a;
-        self.load_memory(addr as usize);
+        self.load_memory(instruction, addr)?;
         self.cpu.execute(instruction)?;
         if self.cpu.write_m {
             self.set_memory(addr, self.cpu.m)?;
@@ -33,36 +97,192 @@ impl Emulator {
         Ok(())
     }
 
-    pub fn load_rom(&mut self, rom: Vec<u16>) {
+    pub fn step_n(&mut self, n: u64) -> Result<(), String> {
+        for _ in 0..n {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    pub fn load_rom(&mut self, rom: Vec<u16>) -> Result<(), String> {
+        if rom.len() > self.config.rom_size {
+            return Err(format!(
+                "Program is {} instructions, but ROM only holds {}",
+                rom.len(),
+                self.config.rom_size
+            ));
+        }
         self.rom = rom;
         self.cpu.reset();
         self.memory.fill(0);
+        self.screen_changes = ScreenChanges::all();
+        Ok(())
     }
 
     pub fn set_memory(&mut self, addr: u16, val: u16) -> Result<(), String> {
-        match addr {
-            0..=0x6000 => {
-                self.memory[addr as usize] = val;
-                Ok(())
-            }
-            _ => Err(format!("Out of bounds memory access ({:#x})", addr)),
+        if !self.config.is_mapped(addr) {
+            return Err(format!("Out of bounds memory access ({:#x})", addr));
         }
+
+        let screen_offset = addr.wrapping_sub(self.config.screen_base) as usize;
+        if screen_offset < screen::SCREEN_WORDS && self.memory[addr as usize] != val {
+            self.screen_changes.mark_word(screen_offset);
+        }
+        self.memory[addr as usize] = val;
+        Ok(())
     }
 
     pub fn set_keyboard(&mut self, value: u16) {
-        self.memory[0x6000] = value;
+        if let Some(kbd) = self.memory.get_mut(self.config.keyboard_addr as usize) {
+            *kbd = value;
+        }
+    }
+
+    /// Returns true if the program has reached the `@n` / `0;JMP` loop that Hack programs
+    /// conventionally end with, where `n` is the address of the `@n` instruction itself.
+    pub fn is_halted(&self) -> bool {
+        let rom_word = |addr: u16| self.rom.get(addr as usize).copied().unwrap_or(0);
+        // An unconditional jump that doesn't write anything
+        let is_halt_jump = |addr: u16| rom_word(addr) & 0xE03F == 0xE007;
+
+        let pc = self.cpu.pc;
+        (rom_word(pc) == pc && is_halt_jump(pc.wrapping_add(1)))
+            || (pc > 0 && self.cpu.a == pc - 1 && rom_word(pc - 1) == pc - 1 && is_halt_jump(pc))
+    }
+
+    /// Returns the parts of the screen that changed since the last call, so renderers can
+    /// redraw only those parts. Everything is reported as changed after a ROM is loaded.
+    pub fn take_screen_changes(&mut self) -> ScreenChanges {
+        std::mem::replace(&mut self.screen_changes, ScreenChanges::none())
     }
 
     fn fetch_instruction(&self) -> Result<u16, String> {
-        match self.rom.get(self.cpu.pc as usize) {
+        let pc = self.cpu.pc as usize;
+        match self.rom.get(pc) {
             Some(&i) => Ok(i),
+            // The rest of the ROM is zeroed, which is harmless (@0)
+            None if pc < self.config.rom_size => Ok(0),
             None => Err(format!("Out of bounds ROM access ({:#x})", self.cpu.pc)),
         }
     }
 
-    fn load_memory(&mut self, addr: usize) {
-        if let Some(&m) = self.memory.get(addr) {
-            self.cpu.m = m;
+    // A can hold any 15-bit value, so it's only an error to use it as an address when the
+    // instruction reads M, which is when the a-bit of a C-instruction is set
+    fn load_memory(&mut self, instruction: u16, addr: u16) -> Result<(), String> {
+        if self.config.is_mapped(addr) {
+            self.cpu.m = self.memory[addr as usize];
+        } else if instruction & 0x9000 == 0x9000 {
+            return Err(format!("Out of bounds memory access ({:#x})", addr));
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::InstructionSet;
+
+    #[test]
+    fn test_screen_changes() {
+        let mut emu = Emulator::new(vec![]);
+        assert_eq!(emu.take_screen_changes().dirty_rows().count(), 256);
+        assert!(emu.take_screen_changes().is_empty());
+
+        emu.set_memory(100, 1).unwrap();
+        emu.set_memory(0x4000 + 32 * 3 + 1, 0).unwrap();
+        assert!(emu.take_screen_changes().is_empty());
+
+        emu.set_memory(0x4000 + 32 * 3 + 1, 1).unwrap();
+        emu.set_memory(0x4000 + 32 * 4, 1).unwrap();
+        let changes = emu.take_screen_changes();
+        assert_eq!(changes.dirty_rows().collect::<Vec<usize>>(), vec![3, 4]);
+    }
+
+    #[test]
+    fn test_strict_memory() {
+        let mut emu = Emulator::new(vec![]);
+        assert_eq!(emu.memory().len(), 0x6001);
+        assert!(emu.set_memory(0x6000, 1).is_ok());
+        assert!(emu.set_memory(0x6001, 1).is_err());
+
+        // @0x6001 is fine until M is read, by D=M here
+        emu.load_rom(vec![0x6001, 0xFC10]).unwrap();
+        emu.step().unwrap();
+        assert!(emu.step().is_err());
+
+        let mut emu =
+            Emulator::with_config(vec![0x6001, 0xFC10], EmulatorConfig::extended()).unwrap();
+        emu.step_n(2).unwrap();
+    }
+
+    #[test]
+    fn test_lenient_memory() {
+        let mut emu = Emulator::with_config(vec![], EmulatorConfig::extended()).unwrap();
+        assert_eq!(emu.memory().len(), 0x8000);
+        emu.set_memory(0x7fff, 5).unwrap();
+        assert_eq!(emu.memory()[0x7fff], 5);
+    }
+
+    #[test]
+    fn test_extended_instruction_set() {
+        // @5, D=A, D=D<<, A=D>>
+        let rom = vec![5, 0xEC10, 0xAC10, 0xA420];
+        // Without the extension, the prefix is ignored, so D=D<< is D=A
+        let mut emu = Emulator::new(rom.clone());
+        emu.step_n(3).unwrap();
+        assert_eq!(emu.cpu.d, 5);
+
+        let config = EmulatorConfig {
+            instruction_set: InstructionSet::Extended,
+            ..EmulatorConfig::default()
+        };
+        let mut emu = Emulator::with_config(rom, config).unwrap();
+        emu.step_n(4).unwrap();
+        assert_eq!((emu.cpu.d, emu.cpu.a), (10, 5));
+
+        emu.cpu.d = 0xfff0;
+        emu.cpu.pc = 3;
+        emu.step().unwrap();
+        assert_eq!(emu.cpu.a, 0xfff8);
+    }
+
+    #[test]
+    fn test_rom_size() {
+        let config = EmulatorConfig {
+            rom_size: 4,
+            ..EmulatorConfig::default()
+        };
+        assert!(Emulator::with_config(vec![0; 5], config.clone()).is_err());
+        let mut emu = Emulator::with_config(vec![], config).unwrap();
+        assert!(emu.load_rom(vec![0; 5]).is_err());
+
+        // Unused ROM words execute as @0, and running off the end of the ROM is an error
+        emu.load_rom(vec![0b0000_0000_0000_0111]).unwrap();
+        emu.step_n(4).unwrap();
+        assert_eq!(emu.cpu.a, 0);
+        assert!(emu.step().is_err());
+    }
+
+    #[test]
+    fn test_is_halted() {
+        // @2, D=A, @2, 0;JMP
+        let rom =
+            parse_rom("0000000000000010\n1110110000010000\n0000000000000010\n1110101010000111")
+                .unwrap();
+        let mut emu = Emulator::new(rom);
+        emu.step_n(2).unwrap();
+        assert!(emu.is_halted());
+        emu.step().unwrap();
+        assert!(emu.is_halted());
+        emu.step().unwrap();
+        assert!(emu.is_halted());
+
+        emu.load_rom(vec![0b0000_0000_0000_0000, 0b1110_1010_1000_0111])
+            .unwrap();
+        assert!(emu.is_halted());
+        emu.load_rom(vec![0b0000_0000_0000_0000, 0b1110_1010_1000_0010])
+            .unwrap();
+        assert!(!emu.is_halted());
     }
 }
@@ -0,0 +1,330 @@
+pub const SCREEN_WIDTH: usize = 512;
+pub const SCREEN_HEIGHT: usize = 256;
+pub const WORDS_PER_ROW: usize = SCREEN_WIDTH / 16;
+pub const SCREEN_WORDS: usize = WORDS_PER_ROW * SCREEN_HEIGHT;
+
+/// A rectangle on the screen, in pixels.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Tracks which parts of the screen have changed, so renderers only need to redraw those parts.
+#[derive(Debug, Clone)]
+pub struct ScreenChanges {
+    // For each row of pixels, the range of words (start inclusive, end exclusive) that changed
+    rows: Vec<Option<(usize, usize)>>,
+}
+
+impl ScreenChanges {
+    pub fn none() -> Self {
+        Self {
+            rows: vec![None; SCREEN_HEIGHT],
+        }
+    }
+
+    pub fn all() -> Self {
+        Self {
+            rows: vec![Some((0, WORDS_PER_ROW)); SCREEN_HEIGHT],
+        }
+    }
+
+    /// Marks the word at `offset` from the start of the screen's memory map as changed.
+    pub fn mark_word(&mut self, offset: usize) {
+        let (row, col) = (offset / WORDS_PER_ROW, offset % WORDS_PER_ROW);
+        if let Some(range) = self.rows.get_mut(row) {
+            *range = Some(match *range {
+                Some((start, end)) => (start.min(col), end.max(col + 1)),
+                None => (col, col + 1),
+            });
+        }
+    }
+
+    pub fn merge(&mut self, other: &ScreenChanges) {
+        for (range, other_range) in self.rows.iter_mut().zip(&other.rows) {
+            *range = match (*range, *other_range) {
+                (Some((start, end)), Some((other_start, other_end))) => {
+                    Some((start.min(other_start), end.max(other_end)))
+                }
+                (range, other_range) => range.or(other_range),
+            };
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.iter().all(Option::is_none)
+    }
+
+    /// Returns the rows of pixels that contain changes.
+    pub fn dirty_rows(&self) -> impl Iterator<Item = usize> + '_ {
+        self.rows
+            .iter()
+            .enumerate()
+            .filter(|(_, range)| range.is_some())
+            .map(|(row, _)| row)
+    }
+
+    /// Returns the smallest rectangle containing all changes.
+    pub fn bounding_rect(&self) -> Option<Rect> {
+        let rects = self.rects();
+        let first = rects.first()?;
+        let (x, y) = (first.x, first.y);
+        let (mut x_start, mut x_end, mut y_end) = (x, x + first.width, y + first.height);
+        for rect in &rects[1..] {
+            x_start = x_start.min(rect.x);
+            x_end = x_end.max(rect.x + rect.width);
+            y_end = rect.y + rect.height;
+        }
+        Some(Rect {
+            x: x_start,
+            y,
+            width: x_end - x_start,
+            height: y_end - y,
+        })
+    }
+
+    /// Returns one rectangle for each run of consecutive changed rows, spanning the changed
+    /// columns in those rows.
+    pub fn rects(&self) -> Vec<Rect> {
+        let mut rects: Vec<Rect> = vec![];
+        let mut prev_row = None;
+        for (row, range) in self.rows.iter().enumerate() {
+            let Some((start, end)) = *range else {
+                continue;
+            };
+            let (x, width) = (start * 16, (end - start) * 16);
+
+            match rects.last_mut() {
+                Some(rect) if prev_row == Some(row - 1) => {
+                    let x_end = (rect.x + rect.width).max(x + width);
+                    rect.x = rect.x.min(x);
+                    rect.width = x_end - rect.x;
+                    rect.height += 1;
+                }
+                _ => rects.push(Rect {
+                    x,
+                    y: row,
+                    width,
+                    height: 1,
+                }),
+            }
+            prev_row = Some(row);
+        }
+        rects
+    }
+}
+
+/// How `render_rgba` draws the screen.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct RenderOptions {
+    /// RGB colour of lit pixels (1 bits).
+    pub foreground: [u8; 3],
+    pub background: [u8; 3],
+    /// Each pixel is drawn as a `scale` x `scale` block.
+    pub scale: usize,
+    /// Dims every other row of the output, like the scanlines on a CRT.
+    pub scanlines: bool,
+    /// Swaps the foreground and background colours.
+    pub inverted: bool,
+}
+
+impl RenderOptions {
+    /// Black pixels on a white background, like the official CPU emulator.
+    pub fn black_on_white() -> Self {
+        Self {
+            foreground: [0, 0, 0],
+            background: [255, 255, 255],
+            scale: 1,
+            scanlines: false,
+            inverted: false,
+        }
+    }
+
+    pub fn green_on_black() -> Self {
+        Self {
+            foreground: [0, 255, 0],
+            background: [0, 0, 0],
+            ..Self::black_on_white()
+        }
+    }
+
+    /// The size of the rendered image, in pixels.
+    pub fn image_size(&self) -> (usize, usize) {
+        (SCREEN_WIDTH * self.scale, SCREEN_HEIGHT * self.scale)
+    }
+
+    /// The length of the RGBA buffer `render_rgba` needs.
+    pub fn buffer_len(&self) -> usize {
+        let (width, height) = self.image_size();
+        width * height * 4
+    }
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self::green_on_black()
+    }
+}
+
+/// Renders the given rows of pixels from the screen's memory map into an RGBA image buffer of
+/// `options.buffer_len()` bytes. Rows that aren't listed are left untouched, so callers can
+/// redraw only the rows in a `ScreenChanges`.
+pub fn render_rgba(
+    screen: &[u16],
+    options: &RenderOptions,
+    rows: impl IntoIterator<Item = usize>,
+    buf: &mut [u8],
+) -> Result<(), String> {
+    if options.scale == 0 {
+        return Err("The scale must be at least 1".to_owned());
+    }
+    if buf.len() != options.buffer_len() {
+        return Err(format!(
+            "The image buffer is {} bytes, but must be {} bytes",
+            buf.len(),
+            options.buffer_len()
+        ));
+    }
+
+    let (fg, bg) = if options.inverted {
+        (options.background, options.foreground)
+    } else {
+        (options.foreground, options.background)
+    };
+    let dim = |[r, g, b]: [u8; 3]| [r / 2, g / 2, b / 2];
+    let (image_width, _) = options.image_size();
+
+    for row in rows.into_iter().filter(|&row| row < SCREEN_HEIGHT) {
+        for sub_row in 0..options.scale {
+            let y = row * options.scale + sub_row;
+            let dimmed = options.scanlines && y % 2 == 1;
+            let line = &mut buf[y * image_width * 4..(y + 1) * image_width * 4];
+            for (x, pixel) in line.chunks_exact_mut(4).enumerate() {
+                let col = x / options.scale;
+                let word = screen.get(row * WORDS_PER_ROW + col / 16).unwrap_or(&0);
+                let colour = if (word >> (col % 16)) & 1 == 1 {
+                    fg
+                } else {
+                    bg
+                };
+                let colour = if dimmed { dim(colour) } else { colour };
+                pixel.copy_from_slice(&[colour[0], colour[1], colour[2], 255]);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_screen_changes() {
+        let mut changes = ScreenChanges::none();
+        assert!(changes.is_empty());
+        assert_eq!(changes.bounding_rect(), None);
+
+        // Row 1, words 2 and 4, then row 2 word 1
+        changes.mark_word(WORDS_PER_ROW + 2);
+        changes.mark_word(WORDS_PER_ROW + 4);
+        changes.mark_word(2 * WORDS_PER_ROW + 1);
+        // Row 10, last word
+        changes.mark_word(11 * WORDS_PER_ROW - 1);
+
+        assert!(!changes.is_empty());
+        assert_eq!(changes.dirty_rows().collect::<Vec<usize>>(), vec![1, 2, 10]);
+        assert_eq!(
+            changes.rects(),
+            vec![
+                Rect {
+                    x: 16,
+                    y: 1,
+                    width: 64,
+                    height: 2
+                },
+                Rect {
+                    x: 496,
+                    y: 10,
+                    width: 16,
+                    height: 1
+                }
+            ]
+        );
+        assert_eq!(
+            changes.bounding_rect(),
+            Some(Rect {
+                x: 16,
+                y: 1,
+                width: 496,
+                height: 10
+            })
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut a = ScreenChanges::none();
+        a.mark_word(3);
+        let mut b = ScreenChanges::none();
+        b.mark_word(1);
+        b.mark_word(WORDS_PER_ROW * 5);
+        a.merge(&b);
+
+        assert_eq!(a.dirty_rows().collect::<Vec<usize>>(), vec![0, 5]);
+        assert_eq!(
+            a.rects()[0],
+            Rect {
+                x: 16,
+                y: 0,
+                width: 48,
+                height: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_all() {
+        assert_eq!(
+            ScreenChanges::all().bounding_rect(),
+            Some(Rect {
+                x: 0,
+                y: 0,
+                width: SCREEN_WIDTH,
+                height: SCREEN_HEIGHT
+            })
+        );
+    }
+
+    #[test]
+    fn test_render_rgba() {
+        let mut screen = vec![0u16; SCREEN_HEIGHT * WORDS_PER_ROW];
+        // Light the first pixel of the first row
+        screen[0] = 1;
+
+        let mut options = RenderOptions::black_on_white();
+        let mut buf = vec![0u8; options.buffer_len()];
+        render_rgba(&screen, &options, 0..SCREEN_HEIGHT, &mut buf).unwrap();
+        assert_eq!(&buf[0..8], &[0, 0, 0, 255, 255, 255, 255, 255]);
+
+        options.scale = 2;
+        options.scanlines = true;
+        options.inverted = true;
+        assert!(render_rgba(&screen, &options, 0..1, &mut buf).is_err());
+        let mut buf = vec![0u8; options.buffer_len()];
+        render_rgba(&screen, &options, 0..1, &mut buf).unwrap();
+        let row_len = SCREEN_WIDTH * 2 * 4;
+        // The lit pixel covers two columns, drawn in the background colour as it's inverted
+        assert_eq!(
+            &buf[0..12],
+            &[255, 255, 255, 255, 255, 255, 255, 255, 0, 0, 0, 255]
+        );
+        // The second row is a dimmed scanline
+        assert_eq!(&buf[row_len..row_len + 4], &[127, 127, 127, 255]);
+        // Rows that weren't rendered are untouched
+        assert_eq!(&buf[2 * row_len..2 * row_len + 4], &[0, 0, 0, 0]);
+    }
+}
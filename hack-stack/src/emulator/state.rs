@@ -0,0 +1,177 @@
+//! Saving and restoring the complete state of an emulator, so a running program can be shared
+//! and resumed elsewhere, e.g. exported from the web emulator and loaded into `hack-emulate`.
+//!
+//! The format is little-endian throughout:
+//!
+//! ```text
+//! magic          b"HACKSTATE"
+//! version        u16
+//! ram_size       u32
+//! rom_size       u32
+//! screen_base    u16
+//! keyboard_addr  u16
+//! memory_mode    u8   (0 = strict, 1 = lenient)
+//! isa            u8   (0 = hack, 1 = extended; from version 2)
+//! d, a, m, pc    u16 each
+//! write_m        u8
+//! rom            u32 length, followed by that many u16 words
+//! memory         u32 length, followed by that many u16 words
+//! ```
+
+use super::{cpu::Cpu, Emulator, EmulatorConfig, MemoryMode, ScreenChanges};
+use crate::common::{InstructionSet, Reader};
+
+const MAGIC: &[u8] = b"HACKSTATE";
+const VERSION: u16 = 2;
+
+/// Returns true if `data` looks like a saved emulator state rather than a program.
+pub fn is_state(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+impl Emulator {
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(64 + 2 * (self.rom.len() + self.memory.len()));
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+
+        let config = &self.config;
+        buf.extend_from_slice(&(config.ram_size as u32).to_le_bytes());
+        buf.extend_from_slice(&(config.rom_size as u32).to_le_bytes());
+        buf.extend_from_slice(&config.screen_base.to_le_bytes());
+        buf.extend_from_slice(&config.keyboard_addr.to_le_bytes());
+        buf.push(match config.memory_mode {
+            MemoryMode::Strict => 0,
+            MemoryMode::Lenient => 1,
+        });
+        buf.push(match config.instruction_set {
+            InstructionSet::Hack => 0,
+            InstructionSet::Extended => 1,
+        });
+
+        let cpu = &self.cpu;
+        for reg in [cpu.d, cpu.a, cpu.m, cpu.pc].iter() {
+            buf.extend_from_slice(&reg.to_le_bytes());
+        }
+        buf.push(cpu.write_m as u8);
+
+        for words in [&self.rom, &self.memory].iter() {
+            buf.extend_from_slice(&(words.len() as u32).to_le_bytes());
+            for word in words.iter() {
+                buf.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+        buf
+    }
+
+    pub fn from_state(state: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader::new(state, "emulator state");
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err("Not an emulator state file".to_owned());
+        }
+        let version = reader.u16()?;
+        // Version 1 is the same, but without the instruction set
+        if version != 1 && version != VERSION {
+            return Err(format!("Unsupported emulator state version {}", version));
+        }
+
+        let config = EmulatorConfig {
+            ram_size: reader.u32()? as usize,
+            rom_size: reader.u32()? as usize,
+            screen_base: reader.u16()?,
+            keyboard_addr: reader.u16()?,
+            memory_mode: match reader.u8()? {
+                0 => MemoryMode::Strict,
+                1 => MemoryMode::Lenient,
+                mode => return Err(format!("Invalid memory mode {}", mode)),
+            },
+            instruction_set: match version {
+                1 => InstructionSet::Hack,
+                _ => match reader.u8()? {
+                    0 => InstructionSet::Hack,
+                    1 => InstructionSet::Extended,
+                    isa => return Err(format!("Invalid instruction set {}", isa)),
+                },
+            },
+        };
+        let cpu = Cpu {
+            d: reader.u16()?,
+            a: reader.u16()?,
+            m: reader.u16()?,
+            pc: reader.u16()?,
+            write_m: reader.u8()? != 0,
+            instruction_set: config.instruction_set,
+        };
+        let rom = reader.words()?;
+        let memory = reader.words()?;
+
+        if rom.len() > config.rom_size {
+            return Err("The ROM is larger than the configured ROM size".to_owned());
+        }
+        if memory.len() != config.ram_size {
+            return Err("The RAM doesn't match the configured RAM size".to_owned());
+        }
+        if !reader.is_at_end() {
+            return Err("Unexpected data after the end of the emulator state".to_owned());
+        }
+
+        Ok(Emulator {
+            cpu,
+            config,
+            rom,
+            memory,
+            screen_changes: ScreenChanges::all(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::parse_rom;
+
+    #[test]
+    fn test_round_trip() {
+        // @5, D=A, @100, M=D
+        let rom =
+            parse_rom("0000000000000101\n1110110000010000\n0000000001100100\n1110001100001000")
+                .unwrap();
+        let config = EmulatorConfig {
+            instruction_set: InstructionSet::Extended,
+            ..EmulatorConfig::extended()
+        };
+        let mut emu = Emulator::with_config(rom, config).unwrap();
+        emu.set_memory(0x7000, 9).unwrap();
+        emu.step_n(2).unwrap();
+
+        let state = emu.save_state();
+        let mut restored = Emulator::from_state(&state).unwrap();
+        assert_eq!(restored.config(), emu.config());
+        assert_eq!(restored.rom(), emu.rom());
+        assert_eq!(restored.memory(), emu.memory());
+        assert_eq!(restored.cpu.pc, 2);
+        assert_eq!(restored.cpu.d, 5);
+        assert_eq!(restored.cpu.instruction_set, InstructionSet::Extended);
+        assert_eq!(restored.take_screen_changes().dirty_rows().count(), 256);
+
+        restored.step_n(2).unwrap();
+        assert_eq!(restored.memory()[100], 5);
+    }
+
+    #[test]
+    fn test_invalid_state() {
+        let state = Emulator::new(vec![1, 2, 3]).save_state();
+        assert!(is_state(&state));
+        assert!(!is_state(b"0000000000000101\n"));
+        assert!(Emulator::from_state(b"nope").is_err());
+        assert!(Emulator::from_state(&state[..state.len() - 1]).is_err());
+
+        let mut extra = state.clone();
+        extra.push(0);
+        assert!(Emulator::from_state(&extra).is_err());
+
+        let mut bad_version = state;
+        bad_version[MAGIC.len()] = 99;
+        assert!(Emulator::from_state(&bad_version).is_err());
+    }
+}
@@ -2,6 +2,7 @@ use crate::common::SpanError;
 
 use super::{
     ast::*,
+    debug_info::{ClassDebugInfo, SubroutineDebugInfo, Variable},
     symbol_table::{SymbolKind, SymbolTable, SymbolTableEntry},
 };
 
@@ -51,6 +52,7 @@ pub struct Codegen<'a> {
     class_sym_tab: SymbolTable<'a>,
     func_sym_tab: SymbolTable<'a>,
     class: &'a Class<'a>,
+    debug_info: ClassDebugInfo,
 }
 
 impl<'a> Codegen<'a> {
@@ -62,9 +64,20 @@ impl<'a> Codegen<'a> {
             class_sym_tab: SymbolTable::new(),
             func_sym_tab: SymbolTable::new(),
             class,
+            debug_info: ClassDebugInfo {
+                name: class.name.item.to_owned(),
+                fields: vec![],
+                subroutines: vec![],
+            },
         }
     }
 
+    /// Returns the class's fields and each subroutine's variables, as allocated by the last call
+    /// to `generate`.
+    pub fn debug_info(&self) -> &ClassDebugInfo {
+        &self.debug_info
+    }
+
     pub fn generate(&mut self) -> Result<&str, &Vec<SpanError>> {
         for ClassVarDec { kind, var_dec } in &self.class.var_decs {
             let kind = match kind {
@@ -85,6 +98,11 @@ impl<'a> Codegen<'a> {
                 }
 
                 self.class_sym_tab.add(kind, var_dec.ty.item, name.item);
+                if kind == SymbolKind::Field {
+                    self.debug_info
+                        .fields
+                        .push(debug_variable(&self.class_sym_tab, name.item));
+                }
             }
         }
 
@@ -109,7 +127,7 @@ impl<'a> Codegen<'a> {
             })
             .sum::<usize>();
 
-        self.vm_writer.emit(&format!(
+        self.vm_writer.emit(format!(
             "function {}.{} {}",
             self.class.name.item, dec.name.item, locals,
         ));
@@ -151,10 +169,18 @@ impl<'a> Codegen<'a> {
         }
 
         // Add parameters to symbol table
+        let mut args = vec![];
         for param in &dec.params {
             self.func_sym_tab
                 .add(SymbolKind::Arg, param.ty.item, param.name.item);
+            args.push(debug_variable(&self.func_sym_tab, param.name.item));
         }
+        self.debug_info.subroutines.push(SubroutineDebugInfo {
+            name: format!("{}.{}", self.class.name.item, dec.name.item),
+            has_this: dec.kind.item != SubroutineKind::Function,
+            args,
+            locals: vec![],
+        });
 
         // Compile each of the statements in the function
         for stmt in &dec.statements {
@@ -189,6 +215,10 @@ impl<'a> Codegen<'a> {
 
             self.func_sym_tab
                 .add(SymbolKind::Var, var_dec.ty.item, name.item);
+            let local = debug_variable(&self.func_sym_tab, name.item);
+            if let Some(subroutine) = self.debug_info.subroutines.last_mut() {
+                subroutine.locals.push(local);
+            }
         }
     }
 
@@ -200,10 +230,10 @@ impl<'a> Codegen<'a> {
                 if let Some(entry) = self.var_lookup(name.item) {
                     self.vm_writer.pop(entry.kind.segment_name(), entry.index);
                 } else {
-                    self.errors.push(SpanError {
-                        msg: format!("variable {} not declared", name.item),
-                        span: name.span,
-                    })
+                    self.errors.push(SpanError::new(
+                        format!("variable {} not declared", name.item),
+                        name.span,
+                    ))
                 }
             }
             Assignee::Index(Index { array_name, index }) => {
@@ -214,10 +244,10 @@ impl<'a> Codegen<'a> {
                     self.vm_writer.pop("pointer", 1);
                     self.vm_writer.pop("that", 0);
                 } else {
-                    self.errors.push(SpanError {
-                        msg: format!("variable {} not declared", array_name.item),
-                        span: array_name.span,
-                    })
+                    self.errors.push(SpanError::new(
+                        format!("variable {} not declared", array_name.item),
+                        array_name.span,
+                    ))
                 }
             }
         }
@@ -308,10 +338,8 @@ impl<'a> Codegen<'a> {
                 self.vm_writer.emit("call String.new 1");
                 for c in lit.item.chars() {
                     if !c.is_ascii() {
-                        self.errors.push(SpanError {
-                            msg: "invalid string literal".into(),
-                            span: lit.span,
-                        });
+                        self.errors
+                            .push(SpanError::new("invalid string literal".into(), lit.span));
                         break;
                     }
 
@@ -354,10 +382,10 @@ impl<'a> Codegen<'a> {
                 if let Some(entry) = self.var_lookup(name.item) {
                     self.vm_writer.push(entry.kind.segment_name(), entry.index);
                 } else {
-                    self.errors.push(SpanError {
-                        msg: format!("variable {} not declared", name.item),
-                        span: name.span,
-                    })
+                    self.errors.push(SpanError::new(
+                        format!("variable {} not declared", name.item),
+                        name.span,
+                    ))
                 }
             }
             Expr::SubroutineCall(c) => self.compile_subroutine_call(c),
@@ -369,10 +397,10 @@ impl<'a> Codegen<'a> {
                     self.vm_writer.pop("pointer", 1);
                     self.vm_writer.push("that", 0);
                 } else {
-                    self.errors.push(SpanError {
-                        msg: format!("variable {} not declared", array_name.item),
-                        span: array_name.span,
-                    })
+                    self.errors.push(SpanError::new(
+                        format!("variable {} not declared", array_name.item),
+                        array_name.span,
+                    ))
                 }
             }
         }
@@ -393,6 +421,16 @@ impl<'a> Codegen<'a> {
     }
 }
 
+fn debug_variable(sym_tab: &SymbolTable, name: &str) -> Variable {
+    let entry = sym_tab.get(name).unwrap();
+    Variable {
+        name: name.to_owned(),
+        ty: entry.ty.to_owned(),
+        kind: entry.kind,
+        index: entry.index,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -786,6 +824,54 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_debug_info() {
+        let src = r#"
+        class Test {
+          static int count;
+          field int x, y;
+          method void move(int dx, Point p) {
+            var boolean done;
+          }
+          function void test() {}
+        }
+        "#;
+        let class_node = Parser::new(Tokenizer::new(src)).parse().unwrap();
+        let mut gen = Codegen::new(&class_node);
+        gen.generate().unwrap();
+        let info = gen.debug_info();
+
+        let var = |name: &str, ty: &str, kind, index| Variable {
+            name: name.to_owned(),
+            ty: ty.to_owned(),
+            kind,
+            index,
+        };
+        assert_eq!(info.name, "Test");
+        assert_eq!(
+            info.fields,
+            vec![
+                var("x", "int", SymbolKind::Field, 0),
+                var("y", "int", SymbolKind::Field, 1)
+            ]
+        );
+        let method = &info.subroutines[0];
+        assert_eq!(method.name, "Test.move");
+        assert!(method.has_this);
+        assert_eq!(
+            method.args,
+            vec![
+                var("dx", "int", SymbolKind::Arg, 1),
+                var("p", "Point", SymbolKind::Arg, 2)
+            ]
+        );
+        assert_eq!(
+            method.locals,
+            vec![var("done", "boolean", SymbolKind::Var, 0)]
+        );
+        assert!(!info.subroutines[1].has_this);
+    }
+
     fn compile(jack_src: &str) -> String {
         let class_node = Parser::new(Tokenizer::new(jack_src)).parse().unwrap();
         Codegen::new(&class_node).generate().unwrap().into()
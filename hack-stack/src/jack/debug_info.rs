@@ -0,0 +1,64 @@
+use super::symbol_table::SymbolKind;
+
+/// A variable, and where the compiler put it: index `index` of the VM segment named by
+/// `kind.segment_name()`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Variable {
+    pub name: String,
+    pub ty: String,
+    pub kind: SymbolKind,
+    pub index: u16,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SubroutineDebugInfo {
+    /// The VM function name, e.g. `Main.main`.
+    pub name: String,
+    /// Whether `this` points to an instance of the class.
+    pub has_this: bool,
+    pub args: Vec<Variable>,
+    pub locals: Vec<Variable>,
+}
+
+/// Describes the variables in a compiled class, so a debugger can show them by name.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ClassDebugInfo {
+    pub name: String,
+    pub fields: Vec<Variable>,
+    pub subroutines: Vec<SubroutineDebugInfo>,
+}
+
+/// Formats a value according to its Jack type: signed for `int`, `true`/`false` for `boolean`, a
+/// quoted character for `char`, and a hex address (or `null`) for objects.
+pub fn format_value(ty: &str, value: u16) -> String {
+    match ty {
+        "int" => (value as i16).to_string(),
+        "boolean" => match value {
+            0 => "false".to_owned(),
+            0xffff => "true".to_owned(),
+            _ => format!("{} (not a boolean)", value as i16),
+        },
+        "char" => match value {
+            0x20..=0x7e => format!("'{}'", value as u8 as char),
+            _ => value.to_string(),
+        },
+        _ if value == 0 => "null".to_owned(),
+        _ => format!("{:#06x}", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_value() {
+        assert_eq!(format_value("int", 0xfffe), "-2");
+        assert_eq!(format_value("boolean", 0xffff), "true");
+        assert_eq!(format_value("boolean", 0), "false");
+        assert_eq!(format_value("char", 65), "'A'");
+        assert_eq!(format_value("char", 128), "128");
+        assert_eq!(format_value("Ball", 0), "null");
+        assert_eq!(format_value("Array", 2048), "0x0800");
+    }
+}
@@ -1,10 +1,12 @@
 pub mod ast;
 pub mod codegen;
+pub mod debug_info;
 pub mod parser;
 pub mod symbol_table;
 pub mod tokenizer;
 pub mod tokens;
 
 pub use codegen::Codegen;
+pub use debug_info::ClassDebugInfo;
 pub use parser::Parser;
 pub use tokenizer::Tokenizer;
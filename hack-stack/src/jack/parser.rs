@@ -299,9 +299,8 @@ impl<'a> Parser<'a> {
                         item: val,
                         span: self.token.span,
                     })
-                    .map_err(|_| SpanError {
-                        msg: format!("invalid int literal {}", i),
-                        span: self.token.span,
+                    .map_err(|_| {
+                        SpanError::new(format!("invalid int literal {}", i), self.token.span)
                     })?,
             ),
             Kind::StrConst(_) => Expr::StrLit(self.token.to_spanned_str()),
@@ -409,7 +408,7 @@ impl<'a> Parser<'a> {
         Ok(*expr.item)
     }
 
-    fn advance(&mut self) -> Token {
+    fn advance(&mut self) -> Token<'a> {
         self.prev_token = self.token;
         match self.peeked_token {
             Some(token) => {
@@ -513,7 +512,7 @@ mod tests {
 
     use super::*;
 
-    fn parse(src: &str) -> Class {
+    fn parse(src: &str) -> Class<'_> {
         Parser::new(Tokenizer::new(src)).parse().unwrap()
     }
 
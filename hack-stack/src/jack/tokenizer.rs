@@ -196,7 +196,7 @@ mod tests {
     use super::*;
     use crate::common::Span;
 
-    fn tokenize(s: &str) -> Vec<Token> {
+    fn tokenize(s: &str) -> Vec<Token<'_>> {
         Tokenizer::new(s).collect()
     }
 
@@ -5,4 +5,5 @@ pub mod asm;
 pub mod common;
 pub mod emulator;
 pub mod jack;
+pub mod toolchain;
 pub mod vm;
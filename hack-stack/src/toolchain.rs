@@ -0,0 +1,237 @@
+//! Runs each stage of the toolchain on in-memory sources, reporting errors as diagnostics
+//! rather than printing them. This is what the web front-end uses to build programs.
+
+use crate::{
+    asm,
+    common::{Diagnostic, SourceFile, SpanError},
+    jack, vm,
+};
+
+/// An assembled program in the `.hack` format, the ROM address of each label in it, the RAM
+/// address of each variable, and the variables of any Jack classes it was compiled from.
+#[derive(Debug)]
+pub struct Program {
+    pub hack: String,
+    pub symbols: Vec<(String, u16)>,
+    pub variables: Vec<(String, u16)>,
+    pub debug_info: Vec<jack::ClassDebugInfo>,
+    /// Warnings about hand-written assembly that assembled, but probably has mistakes.
+    pub warnings: Vec<Diagnostic>,
+}
+
+/// Compiles a Jack class to VM code.
+pub fn compile_jack(source_file: &SourceFile) -> Result<String, Vec<Diagnostic>> {
+    compile_jack_with_debug_info(source_file).map(|(vm_code, _)| vm_code)
+}
+
+/// Compiles a Jack class to VM code, also returning where the compiler put its variables.
+pub fn compile_jack_with_debug_info(
+    source_file: &SourceFile,
+) -> Result<(String, jack::ClassDebugInfo), Vec<Diagnostic>> {
+    let to_diagnostic = |err: &SpanError| Diagnostic::from_span_error(source_file, err);
+
+    let mut parser = jack::Parser::new(jack::Tokenizer::new(&source_file.src));
+    let class = parser.parse().map_err(|err| vec![to_diagnostic(&err)])?;
+    let mut gen = jack::Codegen::new(&class);
+    let vm_code = gen
+        .generate()
+        .map_err(|errs| errs.iter().map(to_diagnostic).collect::<Vec<Diagnostic>>())?;
+    let vm_code = vm_code.to_owned();
+    Ok((vm_code, gen.debug_info().clone()))
+}
+
+/// Translates VM modules to assembly. Each file's name should be its module name (e.g. `Main`),
+/// as it's used to name static variables.
+pub fn translate_vm(
+    source_files: &[SourceFile],
+    bootstrap: bool,
+) -> Result<String, Vec<Diagnostic>> {
+    vm::translate(source_files, bootstrap, bootstrap).map_err(|(source_file, errs)| {
+        errs.iter()
+            .map(|err| Diagnostic::from_span_error(source_file, err))
+            .collect()
+    })
+}
+
+/// Assembles a program to the `.hack` format. Macros and constants are expanded, but there are no
+/// other files to `.include`. The program is checked with `asm::check`, and every error is returned
+/// along with any warnings; otherwise the warnings are returned with the program.
+pub fn assemble(source_file: &SourceFile) -> Result<Program, Vec<Diagnostic>> {
+    assemble_source(source_file, true)
+}
+
+fn assemble_source(source_file: &SourceFile, check: bool) -> Result<Program, Vec<Diagnostic>> {
+    let expanded = asm::preprocess(source_file, |_| {
+        Err("only a single file can be assembled here".to_owned())
+    })?;
+    let to_diagnostics = |errs: Vec<SpanError>| {
+        errs.iter()
+            .map(|err| expanded.diagnostic(err))
+            .collect::<Vec<Diagnostic>>()
+    };
+
+    let mut parser = asm::Parser::new(asm::Tokenizer::new(&expanded.src));
+    let (instructions, mut errors) = parser.parse_with_errors();
+    if check {
+        errors.extend(asm::check(&instructions));
+    }
+    let mut gen = asm::Codegen::new();
+    for var in &expanded.variables {
+        gen.declare_variable(&var.name, var.address);
+    }
+    let hack = gen.generate(&instructions).unwrap_or_else(|errs| {
+        errors.extend(errs);
+        String::new()
+    });
+    errors.sort_by_key(|err| err.span.start);
+    if errors.iter().any(SpanError::is_error) {
+        return Err(to_diagnostics(errors));
+    }
+
+    let symbols = gen
+        .labels()
+        .iter()
+        .map(|&(name, addr)| (name.to_owned(), addr))
+        .collect();
+    let variables = gen
+        .variables()
+        .iter()
+        .map(|&(name, addr)| (name.to_owned(), addr))
+        .collect();
+    Ok(Program {
+        hack,
+        symbols,
+        variables,
+        debug_info: vec![],
+        warnings: to_diagnostics(errors),
+    })
+}
+
+/// Builds a program from a set of `.jack` and `.vm` files, like running `jack-compile`,
+/// `hack-vm-translate` and `hack-assemble` on a directory. Diagnostics from every Jack file are
+/// reported, rather than stopping at the first file with errors.
+pub fn build(source_files: &[SourceFile]) -> Result<Program, Vec<Diagnostic>> {
+    let mut diagnostics = vec![];
+    let mut debug_info = vec![];
+    let mut vm_files = vec![];
+    for source_file in source_files {
+        let (module_name, ext) = source_file
+            .name
+            .rsplit_once('.')
+            .unwrap_or((&source_file.name, ""));
+        match ext {
+            // Code compiled from Jack is named after its class, as there's no `.vm` file
+            "jack" => match compile_jack_with_debug_info(source_file) {
+                Ok((vm_code, class_debug_info)) => {
+                    debug_info.push(class_debug_info);
+                    vm_files.push(SourceFile::new(vm_code, module_name.to_owned()));
+                }
+                Err(errs) => diagnostics.extend(errs),
+            },
+            "vm" => vm_files.push(SourceFile::new(
+                source_file.src.clone(),
+                source_file.name.clone(),
+            )),
+            _ => {}
+        }
+    }
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+
+    let asm_code = translate_vm(&vm_files, true)?;
+    // The generated code has plenty of unused labels, so there's no point checking it
+    let mut program = assemble_source(&SourceFile::new(asm_code, "program.asm".to_owned()), false)?;
+    program.debug_info = debug_info;
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::Severity,
+        emulator::{parse_rom, Emulator},
+    };
+
+    #[test]
+    fn test_build() {
+        let files = [
+            SourceFile::new(
+                "class Sys { function void init() { do Main.main(); return; } }".to_owned(),
+                "Sys.jack".to_owned(),
+            ),
+            SourceFile::new(
+                "function Main.main 0\npush constant 7\npop static 0\npush constant 0\nreturn\n"
+                    .to_owned(),
+                "Main.vm".to_owned(),
+            ),
+        ];
+        let program = build(&files).unwrap();
+        assert!(program.symbols.iter().any(|(name, _)| name == "Main.main"));
+        assert_eq!(program.variables, vec![("Main.0".to_owned(), 16)]);
+
+        let mut emu = Emulator::new(parse_rom(&program.hack).unwrap());
+        emu.step_n(1000).unwrap();
+        assert_eq!(emu.memory()[16], 7);
+    }
+
+    #[test]
+    fn test_build_diagnostics() {
+        let files = [
+            SourceFile::new(
+                "class Main {\n  function void main() {\n    let x = 1;\n    return;\n  }\n}\n"
+                    .to_owned(),
+                "Main.jack".to_owned(),
+            ),
+            SourceFile::new("class Sys {".to_owned(), "Sys.jack".to_owned()),
+        ];
+        let diagnostics = build(&files).unwrap_err();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].file, "Main.jack");
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (3, 9));
+        assert_eq!(diagnostics[1].file, "Sys.jack");
+
+        let vm_file = SourceFile::new(
+            "push constant 1\npop foo 0\n".to_owned(),
+            "Main.vm".to_owned(),
+        );
+        let diagnostics = build(&[vm_file]).unwrap_err();
+        assert_eq!(diagnostics[0].file, "Main.vm");
+        assert_eq!(diagnostics[0].line, 2);
+
+        let asm_file = SourceFile::new("@1\nD=X\n".to_owned(), "prog.asm".to_owned());
+        let diagnostics = assemble(&asm_file).unwrap_err();
+        assert_eq!(diagnostics[0].line, 2);
+
+        let asm_file = SourceFile::new("(END)\n(END)\n(X)\n".to_owned(), "prog.asm".to_owned());
+        let diagnostics = assemble(&asm_file).unwrap_err();
+        // The errors are returned with the warnings for the unused labels
+        assert_eq!(diagnostics.len(), 3);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[1].severity, Severity::Error);
+
+        // Every problem is reported, not just those found by the first pass to fail
+        let asm_file = SourceFile::new(
+            "(A)\n@A\nD=X\n(A)\n@A\nD=D+D\n".to_owned(),
+            "prog.asm".to_owned(),
+        );
+        let diagnostics = assemble(&asm_file).unwrap_err();
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|d| (d.line, d.msg.as_str()))
+                .collect::<Vec<(usize, &str)>>(),
+            vec![
+                (3, "invalid register X, expected D, A, or M"),
+                (4, "label `A' is already defined"),
+                (6, "the CPU can't compute `D+D'"),
+            ]
+        );
+        assert_eq!(diagnostics[1].notes[0].line, 1);
+
+        let asm_file = SourceFile::new("(X)\n".to_owned(), "prog.asm".to_owned());
+        let program = assemble(&asm_file).unwrap();
+        assert_eq!(program.warnings[0].msg, "label `X' is never used");
+    }
+}
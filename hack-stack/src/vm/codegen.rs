@@ -90,6 +90,15 @@ impl<'a> Codegen<'a> {
                         }
                         self.buf.push('\n');
                     }
+                    ir::ExtInst::ShiftLeft(bits) => {
+                        self.emit(&format!("// shift-left {}", bits));
+                        self.emit("@SP");
+                        self.emit("A=M-1");
+                        for _ in 0..*bits {
+                            self.emit("M=M<<");
+                        }
+                        self.buf.push('\n');
+                    }
                     ir::ExtInst::SubConst(constant) => {
                         self.emit(&format!("// sub-const {}", constant));
                         match constant {
@@ -1,8 +1,11 @@
 use std::collections::{HashMap, HashSet, VecDeque};
 
-use crate::common::SourceFile;
+use crate::common::{InstructionSet, SourceFile};
 
-use super::{ast, optimize::optimize_const_binary_ops};
+use super::{
+    ast,
+    optimize::{optimize_const_binary_ops, optimize_multiplications},
+};
 
 pub enum Instruction<'a> {
     Vm(ast::Instruction<'a>),
@@ -12,6 +15,8 @@ pub enum Instruction<'a> {
 pub enum ExtInst {
     AddConst(u16),
     SubConst(u16),
+    /// Shifts the top of the stack left by this many bits, using the extended instruction set.
+    ShiftLeft(u16),
 }
 
 pub struct Function<'a> {
@@ -85,9 +90,13 @@ impl<'a> Program<'a> {
         );
     }
 
-    pub fn optimize(&mut self) {
+    pub fn optimize(&mut self, instruction_set: InstructionSet) {
         for func in self.functions.values_mut() {
             func.instructions = optimize_const_binary_ops(std::mem::take(&mut func.instructions));
+            if instruction_set == InstructionSet::Extended {
+                func.instructions =
+                    optimize_multiplications(std::mem::take(&mut func.instructions));
+            }
         }
     }
 
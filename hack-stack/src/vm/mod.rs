@@ -10,12 +10,25 @@ pub use codegen::Codegen;
 pub use parser::Parser;
 pub use tokenizer::Tokenizer;
 
-use crate::common::{SourceFile, SpanError};
+use crate::common::{InstructionSet, SourceFile, SpanError};
 
+/// Translates VM code to assembly. `bootstrap` adds code that calls `Sys.init`, and `dce` removes
+/// the functions that can't be reached from it. Code that's linked with separately assembled
+/// objects, which may call any of its functions, should be translated without `dce`.
 pub fn translate(
     source_files: &[SourceFile],
     bootstrap: bool,
     dce: bool,
+) -> Result<String, (&SourceFile, Vec<SpanError>)> {
+    translate_for(source_files, bootstrap, dce, InstructionSet::Hack)
+}
+
+/// Like `translate`, but uses instructions from `instruction_set` where they're faster.
+pub fn translate_for(
+    source_files: &[SourceFile],
+    bootstrap: bool,
+    dce: bool,
+    instruction_set: InstructionSet,
 ) -> Result<String, (&SourceFile, Vec<SpanError>)> {
     let mut program = ir::Program::new();
     for source_file in source_files {
@@ -34,7 +47,7 @@ pub fn translate(
         program.mark_reachable_functions();
     }
 
-    program.optimize();
+    program.optimize(instruction_set);
 
     let mut gen = Codegen::new(bootstrap);
 
@@ -68,7 +81,7 @@ pub fn translate(
     Ok(gen.finalize().unwrap())
 }
 
-fn vm_code_to_ir(file: &SourceFile) -> Result<Vec<ir::Instruction>, Vec<SpanError>> {
+fn vm_code_to_ir(file: &SourceFile) -> Result<Vec<ir::Instruction<'_>>, Vec<SpanError>> {
     let tokenizer = Tokenizer::new(&file.src);
     let mut parser = Parser::new(tokenizer);
     parser.parse().map(|instructions| {
@@ -40,6 +40,57 @@ pub fn optimize_const_binary_ops(insts: Vec<Instruction>) -> Vec<Instruction> {
     new_insts
 }
 
+/// Replaces multiplications by a power of two with shifts, which are only in the extended
+/// instruction set. This assumes `Math.multiply` is the OS's, which wraps around on overflow just
+/// like a shift does.
+pub fn optimize_multiplications(insts: Vec<Instruction>) -> Vec<Instruction> {
+    use ast::Instruction::*;
+    use Instruction::{Ext, Vm};
+
+    let power_of_two = |inst: &Instruction| match inst {
+        Vm(Push(PushInstruction {
+            segment: ast::Segment::Constant,
+            offset,
+            ..
+        })) if *offset > 1 && offset.is_power_of_two() => Some(offset.trailing_zeros() as u16),
+        _ => None,
+    };
+
+    let mut new_insts: Vec<Instruction> = Vec::with_capacity(insts.len());
+    for inst in insts {
+        let is_multiply = matches!(
+            &inst,
+            Vm(Call(ast::CallInstruction {
+                function: "Math.multiply",
+                args: 2,
+                ..
+            }))
+        );
+        if !is_multiply {
+            new_insts.push(inst);
+            continue;
+        }
+
+        let len = new_insts.len();
+        let last = new_insts.last().and_then(power_of_two);
+        // `push constant 2^n; push x` can swap, as long as pushing x has no other effect
+        let second_last = match new_insts.last() {
+            Some(Vm(Push(_))) if len >= 2 => power_of_two(&new_insts[len - 2]),
+            _ => None,
+        };
+        if let Some(bits) = last {
+            new_insts.pop();
+            new_insts.push(Ext(ExtInst::ShiftLeft(bits)));
+        } else if let Some(bits) = second_last {
+            new_insts.remove(len - 2);
+            new_insts.push(Ext(ExtInst::ShiftLeft(bits)));
+        } else {
+            new_insts.push(inst);
+        }
+    }
+    new_insts
+}
+
 const MAX_U15: u16 = 0x7fff;
 
 fn optimize_const_binary_add<'a>(
@@ -289,7 +289,7 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn advance(&mut self) -> Token {
+    fn advance(&mut self) -> Token<'a> {
         self.prev_token = self.token;
         match self.peeked_token {
             Some(token) => {
@@ -267,6 +267,77 @@ fn test_strings() {
     assert_eq!(ram[256], 0xffff);
 }
 
+#[test]
+fn test_optimized() {
+    let prog_src = r#"
+    class Sys {
+      function void init() {
+        var Array xs;
+        var int i, sum;
+        let xs = Memory.alloc(10);
+        while (i < 10) {
+          let xs[i] = Sys.square(i);
+          let i = i + 1;
+        }
+        let i = 0;
+        while (i < 10) {
+          if ((xs[i] & 1) = 1) {
+            let sum = sum + xs[i];
+          } else {
+            let sum = sum - 1;
+          }
+          let i = i + 1;
+        }
+        return sum;
+      }
+
+      function int square(int x) {
+        var int i, result;
+        while (i < x) {
+          let result = result + x;
+          let i = i + 1;
+        }
+        return result;
+      }
+    }
+    "#;
+    let prog_vm_src = SourceFile::new(compile(prog_src), "Sys.jack".into());
+    let asm_src = vm::translate(&[prog_vm_src, malloc_vm_src()], true, true).unwrap();
+
+    // The optimized program must leave memory the same way, in no more steps. The stack above SP
+    // is left out, since it holds return addresses from finished calls, which the optimizer moves.
+    let run = |hack_src: &str| {
+        let mut emu = emulator::Emulator::new(parse_rom(hack_src));
+        let mut steps = 0;
+        while !emu.is_halted() {
+            assert!(steps < 100_000, "the program didn't halt");
+            emu.step().unwrap();
+            steps += 1;
+        }
+        (steps, emu.memory().to_owned())
+    };
+    let (steps, memory) = run(&assemble(&asm_src));
+    let instructions = asm::Parser::new(asm::Tokenizer::new(&asm_src))
+        .parse()
+        .unwrap();
+    let optimized = asm::Codegen::new()
+        .generate(&asm::optimize(instructions))
+        .unwrap();
+    let (optimized_steps, optimized_memory) = run(&optimized);
+
+    let sp = memory[0] as usize;
+    assert_eq!(sp, 257);
+    assert_eq!(memory[256], 165 - 5);
+    assert_eq!(memory[..sp], optimized_memory[..sp]);
+    assert_eq!(memory[2048..], optimized_memory[2048..]);
+    assert!(
+        optimized_steps <= steps,
+        "the optimized program took {} steps, rather than {}",
+        optimized_steps,
+        steps
+    );
+}
+
 // Very primitive bump allocator that never frees. Good enough to test object construction.
 fn malloc_vm_src() -> SourceFile {
     let mem_src = r#"
@@ -318,9 +389,8 @@ fn eval(vm_src_files: &[SourceFile], steps: usize) -> Vec<u16> {
 }
 
 fn parse_rom(hack_src: &str) -> Vec<u16> {
-    let mut rom = vec![0u16; 0x2000];
-    for (i, line) in hack_src.lines().enumerate() {
-        rom[i] = u16::from_str_radix(line.trim_end(), 2).unwrap();
-    }
-    rom
+    hack_src
+        .lines()
+        .map(|line| u16::from_str_radix(line.trim_end(), 2).unwrap())
+        .collect()
 }
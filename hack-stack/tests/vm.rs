@@ -1,4 +1,4 @@
-use hack_stack::{asm, emulator, vm};
+use hack_stack::{asm, common, emulator, vm};
 mod fixtures;
 
 #[test]
@@ -271,18 +271,103 @@ fn test_statics_test() {
     assert_eq!(ram[262], 8);
 }
 
+#[test]
+fn test_linked_statics_test() {
+    // The classes are translated like a program directory, and Sys like a separate OS
+    let classes = &[
+        load_fixture("StaticsTest/Class1.vm"),
+        load_fixture("StaticsTest/Class2.vm"),
+    ];
+    let sys = &[load_fixture("StaticsTest/Sys.vm")];
+    let objects = vec![
+        (
+            "Classes.hobj".to_owned(),
+            assemble_object(&vm::translate(classes, true, false).unwrap()),
+        ),
+        (
+            "Sys.hobj".to_owned(),
+            assemble_object(&vm::translate(sys, false, false).unwrap()),
+        ),
+    ];
+    let program = asm::link(&objects).unwrap();
+    let mut emu = emulator::Emulator::new(program.words);
+
+    emu.set_memory(0, 256).unwrap();
+
+    for _ in 0..2500 {
+        emu.step().unwrap();
+    }
+
+    let ram = emu.memory();
+    assert_eq!(ram[0], 263);
+    assert_eq!(ram[261], 0u16.wrapping_sub(2));
+    assert_eq!(ram[262], 8);
+}
+
+#[test]
+fn test_dce_without_sys_init() {
+    // Only Sys.init and what it calls are kept, so without it, every function is removed, unless
+    // removing them is turned off, as it must be for code the OS is linked with
+    let classes = &[
+        load_fixture("StaticsTest/Class1.vm"),
+        load_fixture("StaticsTest/Class2.vm"),
+    ];
+    let stripped = vm::translate(classes, true, true).unwrap();
+    assert!(!stripped.contains("(Class1.set)"));
+    let kept = vm::translate(classes, true, false).unwrap();
+    assert!(kept.contains("(Class1.set)") && kept.contains("(Class2.get)"));
+}
+
+#[test]
+fn test_extended_multiplication() {
+    let src = "function Main.main 0\npush constant 7\npush constant 8\ncall Math.multiply 2\npop static 0\n\
+               push constant 4\npush static 0\ncall Math.multiply 2\npop static 1\n\
+               push constant 3\nneg\npush constant 2\ncall Math.multiply 2\npop static 2\n\
+               label END\ngoto END\n";
+    let source_files = &[common::SourceFile::new(src.to_owned(), "Main".to_owned())];
+    let asm_src =
+        vm::translate_for(source_files, false, false, common::InstructionSet::Extended).unwrap();
+    assert!(!asm_src.contains("Math.multiply"));
+
+    let mut parser = asm::Parser::new(asm::Tokenizer::new(&asm_src));
+    let mut cg = asm::Codegen::new();
+    cg.set_instruction_set(common::InstructionSet::Extended);
+    let hack_src = cg.generate(&parser.parse().unwrap()).unwrap();
+    let mut config = emulator::EmulatorConfig::hack();
+    config.instruction_set = common::InstructionSet::Extended;
+    let mut emu = emulator::Emulator::with_config(parse_rom(&hack_src), config).unwrap();
+
+    emu.set_memory(0, 256).unwrap();
+
+    for _ in 0..200 {
+        emu.step().unwrap();
+    }
+
+    let ram = emu.memory();
+    assert_eq!(ram[0], 256);
+    assert_eq!(ram[16], 56);
+    assert_eq!(ram[17], 224);
+    assert_eq!(ram[18], 0u16.wrapping_sub(6));
+}
+
 fn assemble(asm_src: &str) -> String {
     let mut parser = asm::Parser::new(asm::Tokenizer::new(asm_src));
     let mut cg = asm::Codegen::new();
     cg.generate(&parser.parse().unwrap()).unwrap()
 }
 
+fn assemble_object(asm_src: &str) -> asm::object::Object {
+    let instructions = asm::Parser::new(asm::Tokenizer::new(asm_src))
+        .parse()
+        .unwrap();
+    asm::Codegen::new().generate_object(&instructions).unwrap()
+}
+
 fn parse_rom(hack_src: &str) -> Vec<u16> {
-    let mut rom = vec![0u16; 0x2000];
-    for (i, line) in hack_src.lines().enumerate() {
-        rom[i] = u16::from_str_radix(line.trim_end(), 2).unwrap();
-    }
-    rom
+    hack_src
+        .lines()
+        .map(|line| u16::from_str_radix(line.trim_end(), 2).unwrap())
+        .collect()
 }
 
 fn load_fixture(name: &str) -> hack_stack::common::SourceFile {
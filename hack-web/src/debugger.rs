@@ -0,0 +1,278 @@
+use hack_stack::{
+    asm,
+    emulator::{self, debugger::VariableValue, StopReason},
+    jack::debug_info::format_value,
+};
+use wasm_bindgen::prelude::*;
+
+use crate::{toolchain::BuildOutput, HackEmulator};
+
+/// Why a run stopped. `reason` is one of `breakpoint`, `watchpoint`, `halted`, `cycle_limit` or
+/// `error`.
+#[wasm_bindgen]
+pub struct StopInfo {
+    reason: String,
+    pub cycles: u32,
+    /// For watchpoints, the address that was written and its old and new values.
+    pub address: u16,
+    pub old_value: u16,
+    pub new_value: u16,
+    message: Option<String>,
+}
+
+#[wasm_bindgen]
+impl StopInfo {
+    #[wasm_bindgen(getter)]
+    pub fn reason(&self) -> String {
+        self.reason.clone()
+    }
+
+    /// The error message, if the program failed.
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> Option<String> {
+        self.message.clone()
+    }
+}
+
+impl StopInfo {
+    pub(crate) fn new(cycles: u64, reason: StopReason) -> Self {
+        let mut info = StopInfo {
+            reason: String::new(),
+            cycles: cycles as u32,
+            address: 0,
+            old_value: 0,
+            new_value: 0,
+            message: None,
+        };
+        info.reason = match reason {
+            StopReason::Breakpoint => "breakpoint",
+            StopReason::Watchpoint { addr, old, new } => {
+                info.address = addr;
+                info.old_value = old;
+                info.new_value = new;
+                "watchpoint"
+            }
+            StopReason::Halted => "halted",
+            StopReason::CycleLimit => "cycle_limit",
+            StopReason::Error(err) => {
+                info.message = Some(err);
+                "error"
+            }
+        }
+        .to_owned();
+        info
+    }
+}
+
+#[wasm_bindgen]
+pub struct StackFrame {
+    function: Option<String>,
+    pub address: u16,
+}
+
+#[wasm_bindgen]
+impl StackFrame {
+    #[wasm_bindgen(getter)]
+    pub fn function(&self) -> Option<String> {
+        self.function.clone()
+    }
+}
+
+/// A Jack variable in the current frame. `kind` is the VM segment it's stored in: `argument`,
+/// `local` or `this`.
+#[wasm_bindgen]
+pub struct JackVariable {
+    name: String,
+    ty: String,
+    kind: String,
+    pub value: u16,
+}
+
+#[wasm_bindgen]
+impl JackVariable {
+    #[wasm_bindgen(getter)]
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    #[wasm_bindgen(getter, js_name = "type")]
+    pub fn ty(&self) -> String {
+        self.ty.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn kind(&self) -> String {
+        self.kind.clone()
+    }
+
+    /// The value formatted for its type, e.g. `-1`, `true`, `'a'` or `null`.
+    #[wasm_bindgen(getter)]
+    pub fn display(&self) -> String {
+        format_value(&self.ty, self.value)
+    }
+}
+
+impl From<VariableValue> for JackVariable {
+    fn from(var: VariableValue) -> Self {
+        JackVariable {
+            name: var.name,
+            ty: var.ty,
+            kind: var.kind.segment_name().to_owned(),
+            value: var.value,
+        }
+    }
+}
+
+/// The variables of the Jack subroutine that's currently running.
+#[wasm_bindgen]
+pub struct JackFrame {
+    function: String,
+    args: Vec<VariableValue>,
+    locals: Vec<VariableValue>,
+    fields: Vec<VariableValue>,
+}
+
+#[wasm_bindgen]
+impl JackFrame {
+    #[wasm_bindgen(getter)]
+    pub fn function(&self) -> String {
+        self.function.clone()
+    }
+
+    /// An array of `JackVariable`s.
+    #[wasm_bindgen(getter)]
+    pub fn args(&self) -> js_sys::Array {
+        to_js_variables(&self.args)
+    }
+
+    /// An array of `JackVariable`s.
+    #[wasm_bindgen(getter)]
+    pub fn locals(&self) -> js_sys::Array {
+        to_js_variables(&self.locals)
+    }
+
+    /// An array of `JackVariable`s: the fields of `this`, for methods and constructors.
+    #[wasm_bindgen(getter)]
+    pub fn fields(&self) -> js_sys::Array {
+        to_js_variables(&self.fields)
+    }
+}
+
+fn to_js_variables(vars: &[VariableValue]) -> js_sys::Array {
+    vars.iter()
+        .cloned()
+        .map(|var| JsValue::from(JackVariable::from(var)))
+        .collect()
+}
+
+#[wasm_bindgen]
+impl HackEmulator {
+    /// Loads a program built by `Project.build`, along with its symbols and debug info so the
+    /// debugger can name functions and Jack variables.
+    #[wasm_bindgen]
+    pub fn load_program(&mut self, program: &BuildOutput) -> Result<(), JsValue> {
+        let hack = program
+            .output()
+            .ok_or_else(|| JsValue::from_str("the build failed, so there's no program to load"))?;
+        self.load_rom(&hack)?;
+        self.debugger.set_symbols(program.symbols.clone());
+        self.program = None;
+        self.debugger.set_debug_info(program.debug_info.clone());
+        Ok(())
+    }
+
+    /// Runs until a breakpoint or watchpoint is hit, the program halts or fails, or
+    /// `max_cycles` instructions have run.
+    #[wasm_bindgen]
+    pub fn run_until(&mut self, max_cycles: u32) -> StopInfo {
+        let (cycles, reason) = self.run_cycles(max_cycles as u64);
+        StopInfo::new(cycles, reason)
+    }
+
+    #[wasm_bindgen]
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.debugger.add_breakpoint(addr);
+    }
+
+    #[wasm_bindgen]
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.debugger.remove_breakpoint(addr);
+    }
+
+    #[wasm_bindgen]
+    pub fn breakpoints(&self) -> Vec<u16> {
+        self.debugger.breakpoints().collect()
+    }
+
+    #[wasm_bindgen]
+    pub fn add_watchpoint(&mut self, addr: u16) {
+        self.debugger.add_watchpoint(addr);
+    }
+
+    #[wasm_bindgen]
+    pub fn remove_watchpoint(&mut self, addr: u16) {
+        self.debugger.remove_watchpoint(addr);
+    }
+
+    #[wasm_bindgen]
+    pub fn watchpoints(&self) -> Vec<u16> {
+        self.debugger.watchpoints().collect()
+    }
+
+    /// Disassembles the ROM from `start` up to (but not including) `end`, returning one string
+    /// per instruction.
+    #[wasm_bindgen]
+    pub fn disassemble(&self, start: u16, end: u16) -> js_sys::Array {
+        let rom = self.emu.rom();
+        (start..end.min(rom.len() as u16))
+            .map(|addr| {
+                let inst = rom[addr as usize];
+                let text = asm::disassemble_for(inst, self.emu.cpu.instruction_set)
+                    .unwrap_or_else(|| format!("<invalid {:016b}>", inst));
+                JsValue::from_str(&text)
+            })
+            .collect()
+    }
+
+    /// Returns the name of the function containing a ROM address, if the program was loaded
+    /// with symbols.
+    #[wasm_bindgen]
+    pub fn function_at(&self, addr: u16) -> Option<String> {
+        self.debugger.function_at(addr).map(str::to_owned)
+    }
+
+    /// Returns the file and line, e.g. `Main.asm:12`, that the instruction at a ROM address was
+    /// assembled from, if the program was loaded from a `.hackx` file.
+    #[wasm_bindgen]
+    pub fn source_line(&self, addr: u16) -> Option<String> {
+        let (file, line) = self.program.as_ref()?.source_line(addr)?;
+        Some(format!("{}:{}", file, line))
+    }
+
+    /// Returns an array of `StackFrame`s, innermost first.
+    #[wasm_bindgen]
+    pub fn call_stack(&self) -> js_sys::Array {
+        self.debugger
+            .call_stack(&self.emu)
+            .into_iter()
+            .map(|frame: emulator::debugger::Frame| {
+                JsValue::from(StackFrame {
+                    function: frame.function,
+                    address: frame.addr,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the arguments, locals and fields of the Jack subroutine that's currently running,
+    /// or `undefined` if it wasn't compiled from Jack in a loaded `Project`.
+    #[wasm_bindgen]
+    pub fn jack_frame(&self) -> Option<JackFrame> {
+        self.debugger.jack_frame(&self.emu).map(|frame| JackFrame {
+            function: frame.function,
+            args: frame.args,
+            locals: frame.locals,
+            fields: frame.fields,
+        })
+    }
+}
@@ -1,14 +1,40 @@
 #![allow(clippy::new_without_default)]
 
+mod debugger;
 mod panic_handler;
+mod toolchain;
 
-use hack_stack::emulator;
+use hack_stack::asm::hackx::Hackx;
+use hack_stack::common::InstructionSet;
+use hack_stack::emulator::{
+    self, keyboard,
+    screen::{self, RenderOptions},
+    KeyQueue, ScreenChanges, StopReason,
+};
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
 pub struct HackEmulator {
     emu: emulator::Emulator,
+    debugger: emulator::Debugger,
+    // The `.hackx` program loaded, if any, for its line numbers
+    program: Option<Hackx>,
+    clock: emulator::Clock,
+    keys: KeyQueue,
+    render_options: RenderOptions,
     pixel_buffer: Vec<u8>,
+    // Screen changes are taken from the emulator by whichever method asks first, so each
+    // consumer keeps its own record of what has changed since it last looked.
+    pixel_buffer_changes: ScreenChanges,
+    dirty_rect_changes: ScreenChanges,
+}
+
+#[wasm_bindgen]
+pub struct DirtyRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
 }
 
 #[wasm_bindgen]
@@ -26,35 +52,134 @@ impl HackEmulator {
         let rom = vec![];
         Self {
             emu: emulator::Emulator::new(rom),
-            pixel_buffer: vec![0u8; 512 * 256 * 4],
+            debugger: emulator::Debugger::new(),
+            program: None,
+            clock: emulator::Clock::new(emulator::DEFAULT_FREQUENCY),
+            keys: KeyQueue::new(key_hold_cycles(emulator::DEFAULT_FREQUENCY)),
+            render_options: RenderOptions::default(),
+            pixel_buffer: vec![0u8; RenderOptions::default().buffer_len()],
+            pixel_buffer_changes: ScreenChanges::all(),
+            dirty_rect_changes: ScreenChanges::all(),
         }
     }
 
     #[wasm_bindgen]
     pub fn load_rom(&mut self, source: &str) -> Result<(), JsValue> {
-        let mut rom = Vec::<u16>::with_capacity(0x2000);
+        let mut rom = Vec::<u16>::new();
         for line in source.lines().filter(|l| !l.is_empty()) {
             rom.push(
                 u16::from_str_radix(line.trim_end(), 2)
                     .map_err(|_| format!("error parsing instruction {}", line))?,
             );
         }
-        self.emu.load_rom(rom);
+        self.emu.load_rom(rom)?;
+        // Plain `.hack` files don't say which instruction set they need, so they get the standard
+        // one, whatever the last program used
+        self.emu.set_instruction_set(InstructionSet::Hack);
+        self.debugger.clear_stop();
+        self.debugger.set_symbols(vec![]);
+        self.debugger.set_debug_info(vec![]);
+        self.program = None;
         Ok(())
     }
 
+    /// Loads a program in the `.hackx` format, using its labels to name functions and its
+    /// instruction set to run it.
     #[wasm_bindgen]
-    pub fn step(&mut self, n: usize) -> Result<(), JsValue> {
-        // self.emu.set_keyboard(1);
-        for _ in 0..n {
-            self.emu.step().map_err(|e| JsValue::from_str(&e))?;
-        }
+    pub fn load_hackx(&mut self, data: &[u8]) -> Result<(), JsValue> {
+        let program = Hackx::from_bytes(data)?;
+        self.emu.load_rom(program.rom.clone())?;
+        self.debugger.clear_stop();
+        self.emu.set_instruction_set(program.instruction_set);
+        self.debugger.set_symbols(program.labels.clone());
+        self.debugger.set_debug_info(vec![]);
+        self.program = Some(program);
+        Ok(())
+    }
+
+    /// Returns a snapshot of the emulator that can be restored with `load_state`, or loaded into
+    /// `hack-emulate`.
+    #[wasm_bindgen]
+    pub fn save_state(&self) -> Vec<u8> {
+        self.emu.save_state()
+    }
+
+    #[wasm_bindgen]
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), JsValue> {
+        self.emu = emulator::Emulator::from_state(state)?;
+        self.debugger.clear_stop();
+        // Saved states don't include symbols or debug info, which may belong to a different
+        // program. The instruction set is restored from the state.
+        self.debugger.set_symbols(vec![]);
+        self.debugger.set_debug_info(vec![]);
+        self.program = None;
+        self.keys.release_all();
+        self.clock.reset();
         Ok(())
     }
 
     #[wasm_bindgen]
-    pub fn set_keyboard(&mut self, keycode: u16) {
-        self.emu.set_keyboard(keycode);
+    pub fn step(&mut self, n: u32) -> Result<(), JsValue> {
+        self.debugger
+            .step(&mut self.emu, n as u64)
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Runs as many instructions as the clock says are due at `now_ms`, which should come from
+    /// `performance.now()`. The run stops early at breakpoints and watchpoints, or if the program
+    /// halts or fails.
+    #[wasm_bindgen]
+    pub fn run_frame(&mut self, now_ms: f64) -> debugger::StopInfo {
+        let steps = self.clock.tick(now_ms / 1000.0);
+        let (cycles, reason) = self.run_cycles(steps);
+        debugger::StopInfo::new(cycles, reason)
+    }
+
+    #[wasm_bindgen]
+    pub fn reset_clock(&mut self) {
+        self.clock.reset();
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn frequency(&self) -> u32 {
+        self.clock.frequency()
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_frequency(&mut self, frequency: u32) {
+        self.clock.set_frequency(frequency);
+        self.keys.set_min_hold_cycles(key_hold_cycles(frequency));
+    }
+
+    #[wasm_bindgen]
+    pub fn effective_frequency(&self) -> f64 {
+        self.clock.effective_frequency()
+    }
+
+    /// Queues a key press, given a DOM `KeyboardEvent.key` value. Returns false if the key has
+    /// no Hack key code, in which case the event should be left for the browser to handle.
+    #[wasm_bindgen]
+    pub fn key_down(&mut self, key: &str) -> bool {
+        match keyboard::key_code_for_dom_key(key) {
+            Some(code) => {
+                self.keys.press(code);
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn key_up(&mut self, key: &str) {
+        if let Some(code) = keyboard::key_code_for_dom_key(key) {
+            self.keys.release(code);
+        }
+    }
+
+    /// Releases all keys, e.g. when the page loses focus and won't receive key up events.
+    #[wasm_bindgen]
+    pub fn release_all_keys(&mut self) {
+        self.keys.release_all();
     }
 
     #[wasm_bindgen]
@@ -73,22 +198,124 @@ impl HackEmulator {
         unsafe { js_sys::Uint16Array::view(self.emu.memory()) }
     }
 
+    /// Returns the bounding rectangle of the screen changes since the last call, or `undefined`
+    /// if nothing changed.
     #[wasm_bindgen]
-    pub fn screen_image_data(&mut self) -> js_sys::Uint8ClampedArray {
-        for (i, word) in self.emu.memory()[0x4000..0x6000].iter().enumerate() {
-            for bit_index in 0..16 {
-                let pixel_index = (i * 16 + bit_index) * 4;
-                self.pixel_buffer[pixel_index] = 0;
-                self.pixel_buffer[pixel_index + 1] =
-                    if (word >> bit_index) & 1 == 0 { 0 } else { 255 };
-                self.pixel_buffer[pixel_index + 2] = 0;
-                self.pixel_buffer[pixel_index + 3] = 255;
-            }
+    pub fn take_screen_dirty_rect(&mut self) -> Option<DirtyRect> {
+        self.collect_screen_changes();
+        let changes = std::mem::replace(&mut self.dirty_rect_changes, ScreenChanges::none());
+        changes.bounding_rect().map(|rect| DirtyRect {
+            x: rect.x as u32,
+            y: rect.y as u32,
+            width: rect.width as u32,
+            height: rect.height as u32,
+        })
+    }
+
+    /// Sets how `screen_image_data` and `render_screen` draw the screen. Colours are given as
+    /// `0xRRGGBB`.
+    #[wasm_bindgen]
+    pub fn set_screen_style(
+        &mut self,
+        foreground: u32,
+        background: u32,
+        scale: u32,
+        scanlines: bool,
+        inverted: bool,
+    ) -> Result<(), JsValue> {
+        if scale == 0 {
+            return Err(JsValue::from_str("the scale must be at least 1"));
         }
+        self.render_options = RenderOptions {
+            foreground: rgb(foreground),
+            background: rgb(background),
+            scale: scale as usize,
+            scanlines,
+            inverted,
+        };
+        self.pixel_buffer = vec![0u8; self.render_options.buffer_len()];
+        self.pixel_buffer_changes = ScreenChanges::all();
+        Ok(())
+    }
+
+    /// The width of the image produced by `screen_image_data` and `render_screen`.
+    #[wasm_bindgen(getter)]
+    pub fn screen_image_width(&self) -> u32 {
+        self.render_options.image_size().0 as u32
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn screen_image_height(&self) -> u32 {
+        self.render_options.image_size().1 as u32
+    }
+
+    #[wasm_bindgen]
+    pub fn screen_image_data(&mut self) -> js_sys::Uint8ClampedArray {
+        self.collect_screen_changes();
+        let changes = std::mem::replace(&mut self.pixel_buffer_changes, ScreenChanges::none());
+
+        // Only re-render the rows that changed since the last call. The buffer always matches
+        // the render options, so this can't fail.
+        screen::render_rgba(
+            self.emu.screen(),
+            &self.render_options,
+            changes.dirty_rows(),
+            &mut self.pixel_buffer,
+        )
+        .unwrap();
 
         // Unsafe, but avoids copying the array, so it's faster than using js_sys::Uint8ClampedArray::from
         unsafe { js_sys::Uint8ClampedArray::view(self.pixel_buffer.as_slice()) }
     }
+
+    /// Renders the whole screen into an RGBA buffer of `screen_image_width *
+    /// screen_image_height * 4` bytes, e.g. for taking screenshots.
+    #[wasm_bindgen]
+    pub fn render_screen(&self, buf: &mut [u8]) -> Result<(), JsValue> {
+        screen::render_rgba(
+            self.emu.screen(),
+            &self.render_options,
+            0..screen::SCREEN_HEIGHT,
+            buf,
+        )?;
+        Ok(())
+    }
+}
+
+impl HackEmulator {
+    /// Runs up to `max_cycles` instructions with the debugger, feeding queued key events to the
+    /// keyboard register as they become due.
+    fn run_cycles(&mut self, max_cycles: u64) -> (u64, StopReason) {
+        let mut total = 0;
+        loop {
+            let until_next_key = self.keys.apply_due_events();
+            self.emu.set_keyboard(self.keys.key_code());
+
+            let remaining = max_cycles - total;
+            let chunk = until_next_key.map_or(remaining, |n| n.min(remaining));
+            let (cycles, reason) = self.debugger.run(&mut self.emu, chunk);
+            self.keys.advance(cycles);
+            total += cycles;
+            if reason != StopReason::CycleLimit || total == max_cycles {
+                return (total, reason);
+            }
+        }
+    }
+
+    fn collect_screen_changes(&mut self) {
+        let changes = self.emu.take_screen_changes();
+        self.pixel_buffer_changes.merge(&changes);
+        self.dirty_rect_changes.merge(&changes);
+    }
+}
+
+fn rgb(colour: u32) -> [u8; 3] {
+    [(colour >> 16) as u8, (colour >> 8) as u8, colour as u8]
+}
+
+// Hold each key change for a frame, so programs that poll the keyboard once per frame see it
+fn key_hold_cycles(frequency: u32) -> u64 {
+    (frequency / emulator::FRAME_RATE) as u64
 }
 
 #[wasm_bindgen(start)]
@@ -0,0 +1,184 @@
+use hack_stack::{
+    common::{Diagnostic, SourceFile},
+    jack::ClassDebugInfo,
+    toolchain::{self, Program},
+};
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct BuildDiagnostic {
+    file: String,
+    pub line: u32,
+    pub column: u32,
+    message: String,
+    severity: String,
+    notes: Vec<BuildDiagnostic>,
+}
+
+#[wasm_bindgen]
+impl BuildDiagnostic {
+    #[wasm_bindgen(getter)]
+    pub fn file(&self) -> String {
+        self.file.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+
+    /// Either `error`, `warning` or `note`.
+    #[wasm_bindgen(getter)]
+    pub fn severity(&self) -> String {
+        self.severity.clone()
+    }
+
+    /// An array of `BuildDiagnostic`s with the severity `note`, pointing at other places that
+    /// help explain this one, e.g. where a label was first defined.
+    #[wasm_bindgen(getter)]
+    pub fn notes(&self) -> js_sys::Array {
+        self.notes
+            .iter()
+            .map(|note| JsValue::from(note.clone()))
+            .collect()
+    }
+}
+
+/// The result of running part of the toolchain: either the output and any warnings, or the
+/// diagnostics explaining why there's no output.
+#[wasm_bindgen]
+pub struct BuildOutput {
+    output: Option<String>,
+    // Labels and their ROM addresses, for programs that were assembled
+    pub(crate) symbols: Vec<(String, u16)>,
+    // The variables of the Jack classes the program was compiled from
+    pub(crate) debug_info: Vec<ClassDebugInfo>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+#[wasm_bindgen]
+impl BuildOutput {
+    #[wasm_bindgen(getter)]
+    pub fn output(&self) -> Option<String> {
+        self.output.clone()
+    }
+
+    /// An array of `BuildDiagnostic`s.
+    #[wasm_bindgen(getter)]
+    pub fn diagnostics(&self) -> js_sys::Array {
+        self.diagnostics
+            .iter()
+            .map(|d| {
+                JsValue::from(BuildDiagnostic {
+                    file: d.file.clone(),
+                    line: d.line as u32,
+                    column: d.column as u32,
+                    message: d.msg.clone(),
+                    severity: d.severity.to_string(),
+                    notes: d
+                        .notes
+                        .iter()
+                        .map(|note| BuildDiagnostic {
+                            file: note.file.clone(),
+                            line: note.line as u32,
+                            column: note.column as u32,
+                            message: note.msg.clone(),
+                            severity: "note".to_owned(),
+                            notes: vec![],
+                        })
+                        .collect(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl From<Result<String, Vec<Diagnostic>>> for BuildOutput {
+    fn from(result: Result<String, Vec<Diagnostic>>) -> Self {
+        match result {
+            Ok(output) => BuildOutput {
+                output: Some(output),
+                symbols: vec![],
+                debug_info: vec![],
+                diagnostics: vec![],
+            },
+            Err(diagnostics) => BuildOutput {
+                output: None,
+                symbols: vec![],
+                debug_info: vec![],
+                diagnostics,
+            },
+        }
+    }
+}
+
+impl From<Result<Program, Vec<Diagnostic>>> for BuildOutput {
+    fn from(result: Result<Program, Vec<Diagnostic>>) -> Self {
+        match result {
+            Ok(program) => BuildOutput {
+                output: Some(program.hack),
+                symbols: program.symbols,
+                debug_info: program.debug_info,
+                diagnostics: program.warnings,
+            },
+            Err(diagnostics) => BuildOutput {
+                output: None,
+                symbols: vec![],
+                debug_info: vec![],
+                diagnostics,
+            },
+        }
+    }
+}
+
+/// A set of `.jack` and `.vm` files that are built into a single program, e.g. a game and the
+/// OS library.
+#[wasm_bindgen]
+pub struct Project {
+    files: Vec<SourceFile>,
+}
+
+#[wasm_bindgen]
+impl Project {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self { files: vec![] }
+    }
+
+    /// Adds a file, replacing any existing file with the same name.
+    #[wasm_bindgen]
+    pub fn set_file(&mut self, name: &str, source: &str) {
+        self.remove_file(name);
+        self.files
+            .push(SourceFile::new(source.to_owned(), name.to_owned()));
+    }
+
+    #[wasm_bindgen]
+    pub fn remove_file(&mut self, name: &str) {
+        self.files.retain(|f| f.name != name);
+    }
+
+    /// Builds the project into a `.hack` program that can be passed to
+    /// `HackEmulator.load_program`.
+    #[wasm_bindgen]
+    pub fn build(&self) -> BuildOutput {
+        toolchain::build(&self.files).into()
+    }
+}
+
+#[wasm_bindgen]
+pub fn compile_jack(name: &str, source: &str) -> BuildOutput {
+    toolchain::compile_jack(&SourceFile::new(source.to_owned(), name.to_owned())).into()
+}
+
+#[wasm_bindgen]
+pub fn translate_vm(name: &str, source: &str, bootstrap: bool) -> BuildOutput {
+    let source_file = SourceFile::new(source.to_owned(), name.to_owned());
+    toolchain::translate_vm(&[source_file], bootstrap).into()
+}
+
+#[wasm_bindgen]
+pub fn assemble(name: &str, source: &str) -> BuildOutput {
+    toolchain::assemble(&SourceFile::new(source.to_owned(), name.to_owned())).into()
+}
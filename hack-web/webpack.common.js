@@ -16,7 +16,7 @@ module.exports = {
         exclude: /node_modules/,
       },
       {
-        test: /\.hack$/,
+        test: /\.hackx?$/,
         type: 'asset/resource',
       }
     ]
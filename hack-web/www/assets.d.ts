@@ -1 +1,2 @@
-declare module "*.hack";
\ No newline at end of file
+declare module "*.hack";
+declare module "*.hackx";
\ No newline at end of file
@@ -70,6 +70,7 @@ export class GLScreenView {
   shaderProgram: WebGLProgram;
   texture: WebGLTexture;
   uScreenResolutionLoc: WebGLUniformLocation | null;
+  textureLoaded: boolean;
   emulator: HackEmulator;
 
   constructor(emulator: HackEmulator) {
@@ -95,6 +96,7 @@ export class GLScreenView {
     this.shaderProgram = this.initShaders();
     this.initVertexBuffers();
     this.texture = this.initTexture();
+    this.textureLoaded = false;
 
     // Set the screen resolution uniform, which is updated on resize.
     this.uScreenResolutionLoc = this.gl.getUniformLocation(this.shaderProgram, 'u_screen_resolution');
@@ -212,10 +214,26 @@ export class GLScreenView {
   }
 
   loadTexture() {
+    this.gl.bindTexture(this.gl.TEXTURE_2D, this.texture);
+
+    const dirtyRect = this.emulator.take_screen_dirty_rect();
+    if (this.textureLoaded) {
+      if (dirtyRect) {
+        // Only upload the rows that changed. Each texel holds a whole word, and a row is only
+        // 32 words, so it's simplest to upload full rows.
+        const start = 0x4000 + dirtyRect.y * 32;
+        const data = this.emulator.memory.slice(start, start + dirtyRect.height * 32);
+        this.gl.texSubImage2D(this.gl.TEXTURE_2D, 0, 0, dirtyRect.y, 32, dirtyRect.height, this.gl.RGBA, this.gl.UNSIGNED_SHORT_4_4_4_4, data);
+        dirtyRect.free();
+      }
+      return;
+    }
+    dirtyRect?.free();
+    this.textureLoaded = true;
+
     // Pass the screen memory (vram) straight to the GPU as a texture, then
     // unpack the 16-bit words into pixel values in the fragement shader.
     const data = this.emulator.memory.slice(0x4000, 0x6000);
-    this.gl.bindTexture(this.gl.TEXTURE_2D, this.texture);
 
     // WebGL 1 isn't very flexible about texture formats, so we use one of the
     // (few) 16-bit formats available. In the fragment shader, we'll be able to
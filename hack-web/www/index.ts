@@ -6,7 +6,6 @@ import { RomLoader } from './rom-loader';
 
 class App {
   emulator: HackEmulator;
-  keysPressed: number[];
   debugMode: boolean;
   cpuView: CpuView;
   screenView: GLScreenView;
@@ -15,11 +14,10 @@ class App {
   lastFrameTime: number;
   startBtn: HTMLButtonElement;
   stepBtn: HTMLButtonElement;
-  speedSlider: HTMLInputElement;
+  clockSlider: HTMLInputElement;
 
   constructor() {
     this.emulator = new HackEmulator();
-    this.keysPressed = [];
     this.debugMode = false;
     this.running = false;
     this.lastFrameTime = performance.now();
@@ -63,26 +61,52 @@ class App {
     this.stepBtn = document.createElement('button');
     this.stepBtn.innerText = 'Step';
     this.stepBtn.disabled = true;
-    this.stepBtn.addEventListener('click', () => this.update(1));
+    this.stepBtn.addEventListener('click', () => this.step());
     controlsEl.append(this.stepBtn);
 
-    const speedLabel = document.createElement('label');
-    speedLabel.innerText = 'Emulation speed:';
-    speedLabel.style.color = 'white';
-    speedLabel.style.marginLeft = '1.5rem';
-    controlsEl.append(speedLabel);
-
-    this.speedSlider = document.createElement('input');
-    this.speedSlider.type = 'range';
-    this.speedSlider.min = '1';
-    this.speedSlider.max = '500000';
-    this.speedSlider.value = '200000';
-    this.speedSlider.style.marginRight = '1.5rem';
-    controlsEl.append(this.speedSlider);
+    const clockLabel = document.createElement('label');
+    clockLabel.innerText = 'Clock speed:';
+    clockLabel.style.color = 'white';
+    clockLabel.style.marginLeft = '1.5rem';
+    controlsEl.append(clockLabel);
+
+    this.clockSlider = document.createElement('input');
+    this.clockSlider.type = 'range';
+    this.clockSlider.min = '100000';
+    this.clockSlider.max = '30000000';
+    this.clockSlider.step = '100000';
+    this.clockSlider.value = this.emulator.frequency.toString();
+    this.clockSlider.style.marginRight = '1.5rem';
+    this.clockSlider.addEventListener('input', () => {
+      this.emulator.frequency = parseInt(this.clockSlider.value, 10);
+    });
+    controlsEl.append(this.clockSlider);
 
     const romLoader = new RomLoader(this.loadRom.bind(this));
     controlsEl.append(romLoader.el);
 
+    const saveStateBtn = document.createElement('button');
+    saveStateBtn.innerText = 'Save state';
+    saveStateBtn.addEventListener('click', () => this.saveState());
+    controlsEl.append(saveStateBtn);
+
+    const stateInput = document.createElement('input');
+    stateInput.type = 'file';
+    stateInput.style.display = 'none';
+    stateInput.addEventListener('change', async () => {
+      const file = stateInput.files?.[0];
+      if (file) {
+        this.loadState(new Uint8Array(await file.arrayBuffer()));
+      }
+      stateInput.value = '';
+    });
+    controlsEl.append(stateInput);
+
+    const loadStateBtn = document.createElement('button');
+    loadStateBtn.innerText = 'Load state';
+    loadStateBtn.addEventListener('click', () => stateInput.click());
+    controlsEl.append(loadStateBtn);
+
     diagsEl.append(controlsEl);
 
     document.body.append(appEl);
@@ -99,7 +123,9 @@ class App {
     this.startBtn.innerText = 'Stop';
     document.addEventListener('keydown', this.handleKeydown);
     document.addEventListener('keyup', this.handleKeyup);
-    this.update(200000);
+    window.addEventListener('blur', this.handleBlur);
+    this.emulator.reset_clock();
+    this.update();
   }
 
   stop() {
@@ -107,23 +133,67 @@ class App {
     this.startBtn.innerText = 'Start';
     document.removeEventListener('keydown', this.handleKeydown);
     document.removeEventListener('keyup', this.handleKeyup);
+    window.removeEventListener('blur', this.handleBlur);
+    this.emulator.release_all_keys();
   }
 
-  loadRom(rom: string) {
-    this.emulator.load_rom(rom);
+  loadRom(rom: string | Uint8Array) {
+    if (typeof rom === 'string') {
+      this.emulator.load_rom(rom);
+    } else {
+      this.emulator.load_hackx(rom);
+    }
     this.cpuView.update();
     this.screenView.update();
     this.startBtn.disabled = false;
     this.stepBtn.disabled = false;
   }
 
-  update(steps: number) {
-    const t1 = performance.now();
+  saveState() {
+    // Copy into a fresh array, as newer TypeScript versions don't accept arrays that may be
+    // backed by shared memory as blob parts
+    const state = new Uint8Array(this.emulator.save_state());
+    const blob = new Blob([state], { type: 'application/octet-stream' });
+    const link = document.createElement('a');
+    link.href = URL.createObjectURL(blob);
+    link.download = 'hack-emulator.state';
+    link.click();
+    URL.revokeObjectURL(link.href);
+  }
+
+  loadState(state: Uint8Array) {
+    try {
+      this.emulator.load_state(state);
+    } catch (e) {
+      console.log(e);
+      return;
+    }
+    this.cpuView.update();
+    this.screenView.update();
+    this.startBtn.disabled = false;
+    this.stepBtn.disabled = false;
+  }
+
+  step() {
     try {
-      this.emulator.step(steps);
+      this.emulator.step(1);
     } catch (e) {
       console.log(e)
     }
+    this.cpuView.update();
+    this.screenView.update();
+  }
+
+  update() {
+    const t1 = performance.now();
+    const stopInfo = this.emulator.run_frame(t1);
+    if (stopInfo.reason !== 'cycle_limit') {
+      // Breakpoints, watchpoints, errors and the end of the program all pause the emulator
+      console.log(`stopped: ${stopInfo.reason}`, stopInfo.message ?? '');
+      this.stop();
+      this.cpuView.update();
+    }
+    stopInfo.free();
     const stepTime = performance.now() - t1;
 
     if (this.debugMode) {
@@ -135,11 +205,11 @@ class App {
     if (this.debugMode) {
       const frameTime = performance.now() - this.lastFrameTime;
       this.lastFrameTime = performance.now();
-      this.perfView.update(stepTime, frameTime);
+      this.perfView.update(stepTime, frameTime, this.emulator.effective_frequency());
     }
 
     if (this.running) {
-      requestAnimationFrame(this.update.bind(this, parseInt(this.speedSlider.value, 10)));
+      requestAnimationFrame(this.update.bind(this));
     }
   }
 
@@ -151,41 +221,22 @@ class App {
   }
 
   handleKeydown = (ev: KeyboardEvent) => {
-    const keyCode = KeyMap[ev.code];
-    if (!keyCode) {
+    if (ev.ctrlKey || ev.metaKey) {
       return;
     }
-
-    if (this.keysPressed.includes(keyCode)) {
-      return;
+    // Auto-repeat is left to the program, which sees the key as held down
+    if (ev.repeat || this.emulator.key_down(ev.key)) {
+      ev.preventDefault();
     }
-
-    this.keysPressed.push(keyCode);
-    this.emulator.set_keyboard(keyCode);
-
-    ev.preventDefault();
   };
 
   handleKeyup = (ev: KeyboardEvent) => {
-    const keyCode = KeyMap[ev.code];
-    this.keysPressed = this.keysPressed.filter(k => k !== keyCode);
-
-    const newKeyCode = this.keysPressed[this.keysPressed.length - 1] || 0;
-    this.emulator.set_keyboard(newKeyCode);
+    this.emulator.key_up(ev.key);
   };
-}
 
-const KeyMap: { [keyName: string]: number } = {
-  ArrowLeft: 130,
-  ArrowUp: 131,
-  ArrowRight: 132,
-  ArrowDown: 133,
-};
-for (let i = 'A'.charCodeAt(0); i <= 'Z'.charCodeAt(0); i++) {
-  KeyMap[`Key${String.fromCharCode(i)}`] = i;
-}
-for (let i = '0'.charCodeAt(0); i <= '9'.charCodeAt(0); i++) {
-  KeyMap[`Key${String.fromCharCode(i)}`] = i;
+  handleBlur = () => {
+    this.emulator.release_all_keys();
+  };
 }
 
 const app = new App();
\ No newline at end of file
@@ -10,10 +10,10 @@ export class PerfView {
     this.stepTimes = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
     this.frameTimes = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
 
-    this.update(0, 0);
+    this.update(0, 0, 0);
   }
 
-  update(newStepTime: number, newFrameTime: number) {
+  update(newStepTime: number, newFrameTime: number, frequency: number) {
     this.stepTimes.push(newStepTime);
     this.stepTimes.shift();
     this.frameTimes.push(newFrameTime);
@@ -25,6 +25,7 @@ export class PerfView {
       ` step time: ${stepTime} ms`,
       `frame time: ${frameTime} ms`,
       `       fps: ${Math.round(1000 / frameTime)}`,
+      `     clock: ${(frequency / 1e6).toFixed(2)} MHz`,
     ].join('\n');
   }
 }
\ No newline at end of file
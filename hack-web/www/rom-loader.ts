@@ -3,7 +3,7 @@ import { loadRom, romNames } from "./roms";
 export class RomLoader {
   el: HTMLElement;
 
-  constructor(onLoad: (rom: string) => void) {
+  constructor(onLoad: (rom: string | Uint8Array) => void) {
     this.el = document.createElement('div');
 
     const select = document.createElement('select');
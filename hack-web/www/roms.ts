@@ -1,11 +1,15 @@
 // Dynamically load all ROMs from the roms folder
 const roms: { [k: string]: string } = {};
-const requireContext = require.context('./roms', true, /\.hack$/);
-requireContext.keys().forEach((key) => (roms[key.replace("./", "").replace(/\.hack$/, "")] = requireContext(key)));
+const requireContext = require.context('./roms', true, /\.hackx?$/);
+requireContext.keys().forEach((key) => (roms[key.replace("./", "").replace(/\.hackx?$/, "")] = requireContext(key)));
 
 export const romNames = Object.keys(roms);
 
-export async function loadRom(name: string) {
+// ROMs are either `.hack` text, or `.hackx` files with symbols and line numbers
+export async function loadRom(name: string): Promise<string | Uint8Array> {
   const rsp = await fetch(roms[name]);
+  if (roms[name].endsWith('.hackx')) {
+    return new Uint8Array(await rsp.arrayBuffer());
+  }
   return await rsp.text();
 }
\ No newline at end of file
@@ -18,7 +18,13 @@ export class ScreenView {
   }
 
   update() {
+    const width = this.emulator.screen_image_width;
+    const height = this.emulator.screen_image_height;
+    if (this.el.width !== width || this.el.height !== height) {
+      this.el.width = width;
+      this.el.height = height;
+    }
     const screenData = this.emulator.screen_image_data();
-    this.ctx.putImageData(new ImageData(screenData, 512, 256), 0, 0);
+    this.ctx.putImageData(new ImageData(screenData, width, height), 0, 0);
   }
 }
\ No newline at end of file